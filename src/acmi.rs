@@ -0,0 +1,171 @@
+//! Export of a flight to a Tacview ACMI 2.2 file, for debriefing with Tacview's analysis tools.
+//!
+//! The aircraft is written as a single object whose transform (`T=`) holds its position and attitude, with airspeed
+//! and similar fields as ACMI properties. ACMI files may be written as text, or zipped as a `.zip.acmi` file.
+
+use crate::export::ExportBlock;
+use crate::fdr::FDRWriteError;
+use crate::track::{self, FEET_PER_METER};
+use chrono::SecondsFormat;
+use std::io::{Seek, Write};
+
+/// The id of the aircraft object
+const AIRCRAFT_ID: &str = "1";
+
+/// Meters per second in a knot
+const METERS_PER_SECOND_PER_KNOT: f64 = 0.514444;
+
+/// Data block columns written as ACMI properties, with the name and scale of the property
+pub const ACMI_PROPERTIES: [(&str, &str, f64); 3] = [
+    ("IAS", "IAS", METERS_PER_SECOND_PER_KNOT),
+    ("TAS", "TAS", METERS_PER_SECOND_PER_KNOT),
+    ("AOA", "AOA", 1.0),
+];
+
+/// Escape a property value, as commas separate properties in ACMI
+fn escape_value(value: &str) -> String {
+    value.replace('\\', "\\\\").replace(',', "\\,").replace('\n', "\\\n")
+}
+
+/// Writes a data block as an ACMI file
+#[derive(Default)]
+pub struct AcmiWriter {
+    title: Option<String>,
+    tail_number: Option<String>,
+    airframe: Option<String>,
+    recorder: Option<String>,
+}
+
+impl AcmiWriter {
+    /// The title of the recording
+    pub fn title(mut self, title: String) -> Self {
+        self.title = Some(title);
+        self
+    }
+
+    /// The tail number of the aircraft, written as its registration and call sign
+    pub fn tail_number(mut self, tail_number: String) -> Self {
+        self.tail_number = Some(tail_number);
+        self
+    }
+
+    /// The airframe of the aircraft, such as "Mooney M20J", written as its name
+    pub fn airframe(mut self, airframe: Option<String>) -> Self {
+        self.airframe = airframe;
+        self
+    }
+
+    /// The device that recorded the flight, such as the avionics product
+    pub fn recorder(mut self, recorder: Option<String>) -> Self {
+        self.recorder = recorder;
+        self
+    }
+
+    pub fn write<W: Write>(&self, block: &ExportBlock, writer: &mut W) -> Result<(), FDRWriteError> {
+        let points = track::indexed_track_points(&block.block)?;
+        let properties: Vec<(&str, f64, Vec<Option<f64>>)> = ACMI_PROPERTIES
+            .iter()
+            .filter_map(|(column, property, scale)| {
+                block.column_values(column).map(|values| (*property, *scale, values))
+            })
+            .collect();
+
+        writeln!(writer, "FileType=text/acmi/tacview")?;
+        writeln!(writer, "FileVersion=2.2")?;
+        let Some((_, first)) = points.first() else {
+            return Ok(());
+        };
+        writeln!(
+            writer,
+            "0,ReferenceTime={}",
+            first.time.to_rfc3339_opts(SecondsFormat::AutoSi, true)
+        )?;
+        writeln!(writer, "0,DataSource=xfdr")?;
+        if let Some(recorder) = &self.recorder {
+            writeln!(writer, "0,DataRecorder={}", escape_value(recorder))?;
+        }
+        if let Some(title) = &self.title {
+            writeln!(writer, "0,Title={}", escape_value(title))?;
+        }
+
+        // the object's metadata is written with its first transform
+        let mut metadata = vec!["Type=Air+FixedWing".to_string()];
+        if let Some(airframe) = &self.airframe {
+            metadata.push(format!("Name={}", escape_value(airframe)));
+        }
+        if let Some(tail_number) = &self.tail_number {
+            metadata.push(format!("Registration={}", escape_value(tail_number)));
+            metadata.push(format!("CallSign={}", escape_value(tail_number)));
+        }
+
+        for (n, (i, p)) in points.iter().enumerate() {
+            writeln!(writer, "#{}", p.seconds_since(first))?;
+            write!(
+                writer,
+                "{},T={}|{}|{:.1}|{}|{}|{}",
+                AIRCRAFT_ID,
+                p.longitude,
+                p.latitude,
+                p.altitude / FEET_PER_METER,
+                p.roll,
+                p.pitch,
+                p.heading
+            )?;
+            if n == 0 {
+                write!(writer, ",{}", metadata.join(","))?;
+            }
+            for (property, scale, values) in &properties {
+                if let Some(value) = values[*i] {
+                    write!(writer, ",{}={:.2}", property, value * scale)?;
+                }
+            }
+            writeln!(writer)?;
+        }
+        Ok(())
+    }
+
+    /// Write a zipped ACMI file, as used by Tacview for `.zip.acmi` files
+    pub fn write_zip<W: Write + Seek>(&self, block: &ExportBlock, writer: W) -> Result<(), FDRWriteError> {
+        let mut zip = zip::ZipWriter::new(writer);
+        let options = zip::write::SimpleFileOptions::default().compression_method(zip::CompressionMethod::Deflated);
+        zip.start_file("flight.txt.acmi", options)
+            .map_err(|e| FDRWriteError::IO(e.into()))?;
+        self.write(block, &mut zip)?;
+        zip.finish().map_err(|e| FDRWriteError::IO(e.into()))?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{detection::read_avionics_log, export, fdr::FDRConfigurationBuilder, AviationLogSourceOption};
+    use std::io::Read;
+
+    #[test]
+    fn test_acmi_writer_zip() -> Result<(), Box<dyn std::error::Error>> {
+        let path = crate::resource_path("log_231104_084813_KPOU.csv");
+        let source = read_avionics_log(&AviationLogSourceOption::Garmin, &path)?;
+        let columns: Vec<String> = ACMI_PROPERTIES.iter().map(|p| p.0.to_string()).collect();
+        let block = export::export_block(source.as_ref(), &FDRConfigurationBuilder::default().build(), &columns)?;
+
+        let writer = AcmiWriter::default()
+            .tail_number("N12345".to_string())
+            .airframe(Some("Mooney M20J".to_string()));
+        let mut buffer = std::io::Cursor::new(Vec::new());
+        writer.write_zip(&block, &mut buffer)?;
+
+        let mut archive = zip::ZipArchive::new(buffer)?;
+        let mut acmi = String::new();
+        archive.by_index(0)?.read_to_string(&mut acmi)?;
+
+        let mut lines = acmi.lines();
+        assert_eq!(lines.next(), Some("FileType=text/acmi/tacview"));
+        assert_eq!(lines.next(), Some("FileVersion=2.2"));
+        assert!(acmi.contains("0,ReferenceTime=2023-11-04T12:50:01Z"));
+        assert!(acmi.contains(",Type=Air+FixedWing,Name=Mooney M20J,Registration=N12345,CallSign=N12345"));
+        assert!(acmi.contains(",IAS="));
+        assert_eq!(acmi.matches("\n1,T=").count(), 3676 - 149);
+        Ok(())
+    }
+}
@@ -0,0 +1,419 @@
+//! Airports and runways from a local database in the OurAirports CSV format, used to identify where a flight
+//! departed and arrived, and where on the runway it touched down.
+//!
+//! The database is the `airports.csv` and `runways.csv` files published by OurAirports. Runways are only matched
+//! when their ends have coordinates.
+
+use crate::filter::angle_difference;
+use crate::track;
+use polars::prelude::*;
+use serde::Serialize;
+use std::{
+    collections::HashMap,
+    error::Error,
+    fmt::Display,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+/// Aircraft further than this many nautical miles from every airport are not at an airport
+const MAX_AIRPORT_DISTANCE_NM: f64 = 3.0;
+/// Aircraft on a runway are tracking within this many degrees of its heading
+const MAX_RUNWAY_HEADING_OFFSET_DEG: f64 = 30.0;
+/// Aircraft on a runway are within this many feet of its centerline
+const MAX_CENTERLINE_OFFSET_FT: f64 = 300.0;
+/// Feet in a nautical mile
+const FEET_PER_NM: f64 = 6076.12;
+
+/// One end of a runway, from which it is landed on and taken off from toward the other end
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RunwayEnd {
+    pub ident: String,
+    pub latitude: f64,
+    pub longitude: f64,
+    pub elevation_ft: Option<f64>,
+    /// True heading along the runway from this end, in degrees
+    pub heading: f64,
+    /// How far past the end of the pavement the landing threshold is, in feet
+    pub displaced_threshold_ft: f64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Runway {
+    pub length_ft: Option<f64>,
+    pub width_ft: Option<f64>,
+    pub surface: Option<String>,
+    /// The ends of the runway that have coordinates
+    pub ends: Vec<RunwayEnd>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Airport {
+    pub ident: String,
+    /// The OurAirports type, such as `small_airport` or `heliport`
+    pub kind: String,
+    pub name: String,
+    pub latitude: f64,
+    pub longitude: f64,
+    pub elevation_ft: Option<f64>,
+    pub runways: Vec<Runway>,
+}
+
+/// Where an aircraft is relative to a runway
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RunwayPosition {
+    /// The ident of the runway end the aircraft is moving away from, such as `24`
+    pub runway: String,
+    /// Feet past the landing threshold along the runway, negative before it
+    pub distance_from_threshold_ft: f64,
+    /// Feet right of the centerline, facing along the runway
+    pub centerline_offset_ft: f64,
+    /// Feet of runway left ahead of the aircraft, if the length of the runway is known
+    pub remaining_ft: Option<f64>,
+    /// Degrees the aircraft tracks right of the runway heading
+    pub heading_offset_deg: f64,
+}
+
+impl Display for RunwayPosition {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "runway {}, {:.0} ft past the threshold, {:.0} ft {} of centerline",
+            self.runway,
+            self.distance_from_threshold_ft,
+            self.centerline_offset_ft.abs(),
+            if self.centerline_offset_ft < 0.0 {
+                "left"
+            } else {
+                "right"
+            }
+        )?;
+        if let Some(remaining) = self.remaining_ft {
+            write!(f, ", {:.0} ft remaining", remaining)?;
+        }
+        Ok(())
+    }
+}
+
+/// The true bearing from one position to another, in degrees
+pub fn bearing(lat1: f64, lon1: f64, lat2: f64, lon2: f64) -> f64 {
+    let (lat1, lat2) = (lat1.to_radians(), lat2.to_radians());
+    let d_lon = (lon2 - lon1).to_radians();
+    let y = d_lon.sin() * lat2.cos();
+    let x = lat1.cos() * lat2.sin() - lat1.sin() * lat2.cos() * d_lon.cos();
+    y.atan2(x).to_degrees().rem_euclid(360.0)
+}
+
+impl Runway {
+    /// Where an aircraft at a position, tracking a true course, is on the runway, if it is on or over it
+    pub fn position(&self, latitude: f64, longitude: f64, track: f64) -> Option<RunwayPosition> {
+        self.ends
+            .iter()
+            .filter(|end| angle_difference(end.heading, track, true).abs() <= MAX_RUNWAY_HEADING_OFFSET_DEG)
+            .map(|end| {
+                // close to a runway the earth is flat, so project the position onto the runway's axes
+                let north = (latitude - end.latitude) * 60.0 * FEET_PER_NM;
+                let east = (longitude - end.longitude) * 60.0 * FEET_PER_NM * end.latitude.to_radians().cos();
+                let heading = end.heading.to_radians();
+                let along = north * heading.cos() + east * heading.sin();
+                let right = east * heading.cos() - north * heading.sin();
+                RunwayPosition {
+                    runway: end.ident.clone(),
+                    distance_from_threshold_ft: along - end.displaced_threshold_ft,
+                    centerline_offset_ft: right,
+                    remaining_ft: self.length_ft.map(|l| l - along),
+                    heading_offset_deg: angle_difference(end.heading, track, true),
+                }
+            })
+            .filter(|p| p.centerline_offset_ft.abs() <= MAX_CENTERLINE_OFFSET_FT)
+            .filter(|p| p.distance_from_threshold_ft > -MAX_CENTERLINE_OFFSET_FT)
+            .find(|p| p.remaining_ft.is_none_or(|r| r > -MAX_CENTERLINE_OFFSET_FT))
+    }
+}
+
+impl Airport {
+    /// Where an aircraft at a position, tracking a true course, is on the runways of the airport, if it is on one
+    pub fn runway_position(&self, latitude: f64, longitude: f64, track: f64) -> Option<RunwayPosition> {
+        self.runways
+            .iter()
+            .filter_map(|r| r.position(latitude, longitude, track))
+            .min_by(|a, b| a.centerline_offset_ft.abs().total_cmp(&b.centerline_offset_ft.abs()))
+    }
+
+    /// Distance to a position, in nautical miles
+    pub fn distance_nm(&self, latitude: f64, longitude: f64) -> f64 {
+        track::distance_nm(self.latitude, self.longitude, latitude, longitude)
+    }
+}
+
+#[derive(Debug)]
+pub enum AirportError {
+    IO(std::io::Error),
+    Polars(PolarsError),
+    /// The CSV file does not have a column of the OurAirports format
+    MissingColumn(PathBuf, String),
+}
+
+impl Error for AirportError {}
+
+impl Display for AirportError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AirportError::IO(e) => write!(f, "IO error: {}", e),
+            AirportError::Polars(e) => write!(f, "Invalid airport database: {}", e),
+            AirportError::MissingColumn(path, column) => {
+                write!(f, "{} has no \"{}\" column", path.display(), column)
+            }
+        }
+    }
+}
+
+impl From<std::io::Error> for AirportError {
+    fn from(e: std::io::Error) -> Self {
+        AirportError::IO(e)
+    }
+}
+
+impl From<PolarsError> for AirportError {
+    fn from(e: PolarsError) -> Self {
+        AirportError::Polars(e)
+    }
+}
+
+/// A table read from an OurAirports CSV file, with every value as text
+struct Table {
+    path: PathBuf,
+    data: DataFrame,
+}
+
+impl Table {
+    fn read(path: &Path) -> Result<Self, AirportError> {
+        if !path.is_file() {
+            return Err(
+                std::io::Error::new(std::io::ErrorKind::NotFound, format!("{} not found", path.display())).into(),
+            );
+        }
+        let data = CsvReadOptions::default()
+            .with_has_header(true)
+            .with_infer_schema_length(Some(0))
+            .try_into_reader_with_file_path(Some(path.to_path_buf()))?
+            .finish()?;
+        Ok(Self {
+            path: path.to_path_buf(),
+            data,
+        })
+    }
+
+    fn text(&self, column: &str) -> Result<Vec<Option<String>>, AirportError> {
+        let values = self
+            .data
+            .column(column)
+            .map_err(|_| AirportError::MissingColumn(self.path.clone(), column.to_string()))?;
+        Ok(values
+            .str()?
+            .into_iter()
+            .map(|v| v.filter(|v| !v.is_empty()).map(str::to_string))
+            .collect())
+    }
+
+    fn number(&self, column: &str) -> Result<Vec<Option<f64>>, AirportError> {
+        Ok(self
+            .text(column)?
+            .into_iter()
+            .map(|v| v.and_then(|v| v.trim().parse().ok()))
+            .collect())
+    }
+}
+
+/// The columns of one end of the runways in a runways file, those prefixed `le` or `he`
+struct EndColumns {
+    ident: Vec<Option<String>>,
+    latitude: Vec<Option<f64>>,
+    longitude: Vec<Option<f64>>,
+    elevation: Vec<Option<f64>>,
+    heading: Vec<Option<f64>>,
+    displaced_threshold: Vec<Option<f64>>,
+}
+
+impl EndColumns {
+    fn read(table: &Table, prefix: &str) -> Result<Self, AirportError> {
+        let column = |name: &str| format!("{}_{}", prefix, name);
+        Ok(Self {
+            ident: table.text(&column("ident"))?,
+            latitude: table.number(&column("latitude_deg"))?,
+            longitude: table.number(&column("longitude_deg"))?,
+            elevation: table.number(&column("elevation_ft"))?,
+            heading: table.number(&column("heading_degT"))?,
+            displaced_threshold: table.number(&column("displaced_threshold_ft"))?,
+        })
+    }
+
+    fn position(&self, row: usize) -> Option<(f64, f64)> {
+        self.latitude[row].zip(self.longitude[row])
+    }
+
+    /// The end of the runway on a row, if it has coordinates
+    fn end(&self, row: usize, other: &EndColumns) -> Option<RunwayEnd> {
+        let (latitude, longitude) = self.position(row)?;
+        // the heading is often missing, but it runs toward the other end
+        let heading = self.heading[row].or_else(|| {
+            other
+                .position(row)
+                .map(|(lat, lon)| bearing(latitude, longitude, lat, lon))
+        })?;
+        Some(RunwayEnd {
+            ident: self.ident[row].clone().unwrap_or_default(),
+            latitude,
+            longitude,
+            elevation_ft: self.elevation[row],
+            heading,
+            displaced_threshold_ft: self.displaced_threshold[row].unwrap_or(0.0),
+        })
+    }
+}
+
+/// The airports and runways of an OurAirports database
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct AirportDatabase {
+    pub airports: Vec<Airport>,
+}
+
+impl AirportDatabase {
+    /// Load a database from a directory with `airports.csv` and `runways.csv`, or from an airports file, with the
+    /// runways file beside it if there is one
+    pub fn load(path: &Path) -> Result<Self, AirportError> {
+        let (airports, runways) = if path.is_dir() {
+            (path.join("airports.csv"), Some(path.join("runways.csv")))
+        } else {
+            let runways = path.with_file_name("runways.csv");
+            (path.to_path_buf(), runways.is_file().then_some(runways))
+        };
+
+        let table = Table::read(&airports)?;
+        let (idents, kinds, names) = (table.text("ident")?, table.text("type")?, table.text("name")?);
+        let (latitudes, longitudes) = (table.number("latitude_deg")?, table.number("longitude_deg")?);
+        let elevations = table.number("elevation_ft")?;
+        let mut database = AirportDatabase {
+            airports: (0..table.data.height())
+                .filter_map(|i| {
+                    Some(Airport {
+                        ident: idents[i].clone()?,
+                        kind: kinds[i].clone().unwrap_or_default(),
+                        name: names[i].clone().unwrap_or_default(),
+                        latitude: latitudes[i]?,
+                        longitude: longitudes[i]?,
+                        elevation_ft: elevations[i],
+                        runways: Vec::new(),
+                    })
+                })
+                .collect(),
+        };
+        if let Some(runways) = runways {
+            database.load_runways(&runways)?;
+        }
+        Ok(database)
+    }
+
+    /// Add the runways of a runways file to their airports
+    fn load_runways(&mut self, path: &Path) -> Result<(), AirportError> {
+        let table = Table::read(path)?;
+        let airports = table.text("airport_ident")?;
+        let closed = table.text("closed")?;
+        let (lengths, widths, surfaces) = (
+            table.number("length_ft")?,
+            table.number("width_ft")?,
+            table.text("surface")?,
+        );
+        let (low, high) = (EndColumns::read(&table, "le")?, EndColumns::read(&table, "he")?);
+
+        let index: HashMap<String, usize> = self
+            .airports
+            .iter()
+            .enumerate()
+            .map(|(i, a)| (a.ident.clone(), i))
+            .collect();
+        for i in 0..table.data.height() {
+            let Some(&airport) = airports[i].as_ref().and_then(|a| index.get(a)) else {
+                continue;
+            };
+            if closed[i].as_deref() == Some("1") {
+                continue;
+            }
+            let ends = [low.end(i, &high), high.end(i, &low)];
+            self.airports[airport].runways.push(Runway {
+                length_ft: lengths[i],
+                width_ft: widths[i],
+                surface: surfaces[i].clone(),
+                ends: ends.into_iter().flatten().collect(),
+            });
+        }
+        Ok(())
+    }
+
+    /// The nearest open airport for airplanes to a position, if one is close
+    pub fn nearest(&self, latitude: f64, longitude: f64) -> Option<&Airport> {
+        self.airports
+            .iter()
+            .filter(|a| !matches!(a.kind.as_str(), "closed" | "heliport" | "balloonport"))
+            .map(|a| (a, a.distance_nm(latitude, longitude)))
+            .filter(|(_, d)| *d <= MAX_AIRPORT_DISTANCE_NM)
+            .min_by(|a, b| a.1.total_cmp(&b.1))
+            .map(|(a, _)| a)
+    }
+
+    /// The airport an aircraft at a position, tracking a true course, is at, and where it is on its runways
+    ///
+    /// An airport with the aircraft on one of its runways is preferred to one nearer without.
+    pub fn identify(&self, latitude: f64, longitude: f64, track: f64) -> Option<(&Airport, Option<RunwayPosition>)> {
+        let on_runway = self
+            .airports
+            .iter()
+            .filter(|a| !a.runways.is_empty() && a.distance_nm(latitude, longitude) <= MAX_AIRPORT_DISTANCE_NM)
+            .filter_map(|a| a.runway_position(latitude, longitude, track).map(|p| (a, Some(p))))
+            .min_by(|a, b| {
+                a.0.distance_nm(latitude, longitude)
+                    .total_cmp(&b.0.distance_nm(latitude, longitude))
+            });
+        on_runway.or_else(|| self.nearest(latitude, longitude).map(|a| (a, None)))
+    }
+}
+
+/// Load an airport database named on the command line
+pub fn parse_airports_path(path: &str) -> Result<Arc<AirportDatabase>, String> {
+    AirportDatabase::load(Path::new(path))
+        .map(Arc::new)
+        .map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_identify_airports_and_runways() -> Result<(), Box<dyn std::error::Error>> {
+        let database = AirportDatabase::load(&crate::resource_path("ourairports"))?;
+        assert_eq!(database.airports.len(), 5);
+
+        // the heliport beside the runway and the closed field are not airports for airplanes
+        let (airport, position) = database.identify(41.629791, -73.8791603, 230.6).unwrap();
+        assert_eq!(airport.ident, "KPOU");
+        assert_eq!(position.unwrap().runway, "24");
+        assert_eq!(
+            database.nearest(40.5650, -74.9810).map(|a| a.ident.as_str()),
+            Some("N40")
+        );
+
+        // a touchdown on runway 25, past its displaced threshold
+        let (airport, position) = database.identify(40.5647265, -74.9814369, 234.0).unwrap();
+        let position = position.unwrap();
+        assert_eq!((airport.ident.as_str(), position.runway.as_str()), ("N40", "25"));
+        assert!(position.distance_from_threshold_ft > 0.0 && position.remaining_ft.unwrap() > 0.0);
+        assert!(position.centerline_offset_ft.abs() < 100.0);
+
+        // runways without coordinates aren't matched, but their airport is found
+        let (airport, position) = database.identify(41.5041, -74.1048, 270.0).unwrap();
+        assert_eq!((airport.ident.as_str(), position), ("KSWF", None));
+        assert!(database.identify(42.0, -75.0, 0.0).is_none());
+        Ok(())
+    }
+}
@@ -0,0 +1,387 @@
+//! Batch conversion of many avionics logs, such as a directory copied from an aircraft SD card, into FDR files.
+
+use crate::detection::{detect_source, open_avionics_log};
+use crate::fdr::{FDRConfiguration, FDRWriter, FlightDataSource};
+use crate::garmin::LogCheckMode;
+use crate::summary::FlightSummary;
+use crate::AviationLogSourceOption;
+use rayon::prelude::*;
+use std::{
+    collections::HashSet,
+    error::Error,
+    fmt::Display,
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+/// The default template used to name converted files
+pub const DEFAULT_NAME_TEMPLATE: &str = "{date}_{time}_{tail}_{airport}.fdr";
+
+/// Check whether an input refers to many logs (a directory or a glob pattern) rather than a single file
+///
+/// A file whose name holds glob characters, such as `[`, is a single file.
+pub fn is_batch_input(input: &Path) -> bool {
+    input.is_dir() || (!input.exists() && input.to_str().is_some_and(|s| s.contains(['*', '?', '['])))
+}
+
+/// Find the log files referred to by a directory, searched recursively, or a glob pattern
+pub fn collect_inputs(input: &Path) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+    let mut files = Vec::new();
+    if input.is_dir() {
+        let mut dirs = vec![input.to_path_buf()];
+        while let Some(dir) = dirs.pop() {
+            for entry in std::fs::read_dir(dir)? {
+                let path = entry?.path();
+                if path.is_dir() {
+                    dirs.push(path);
+                } else if path.extension().is_some_and(|e| e.eq_ignore_ascii_case("csv")) {
+                    files.push(path);
+                }
+            }
+        }
+    } else {
+        let pattern = input.to_str().ok_or("Input pattern is not valid unicode")?;
+        for path in glob::glob(pattern)? {
+            let path = path?;
+            if path.is_file() {
+                files.push(path);
+            }
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Make a value, such as a tail number read from a log, safe to use in a file name
+///
+/// Path separators and control characters are replaced with `_`, and so is `..`, so the value can't name a file
+/// outside of the directory it is written to.
+pub fn sanitize_file_name(value: &str) -> String {
+    let name: String = value
+        .chars()
+        .map(|c| {
+            if matches!(c, '/' | '\\') || c.is_control() {
+                '_'
+            } else {
+                c
+            }
+        })
+        .collect();
+    name.replace("..", "_")
+}
+
+/// Fill in the placeholders of an output file name template from the metadata of a source
+///
+/// Supported placeholders are `{tail}`, `{date}` (YYYY-MM-DD), `{time}` (HHMMSS), `{airport}` (departure airport the
+/// source records), `{departure}` and `{arrival}` (airports identified with the airport database of the
+/// configuration) and `{stem}` (the input file name without its extension). Unknown values are replaced with
+/// "unknown", and values are passed through [sanitize_file_name].
+pub fn output_file_name(
+    template: &str,
+    source: &dyn FlightDataSource,
+    config: &FDRConfiguration,
+    input: &Path,
+) -> String {
+    const UNKNOWN: &str = "unknown";
+    let timestamp = source.timestamp();
+    let stem = input.file_stem().and_then(|s| s.to_str()).unwrap_or(UNKNOWN);
+    // identifying the airports needs the whole flight, so only summarize it when they are named
+    let summary = names_airports(template)
+        .then(|| FlightSummary::from_source(source, config).ok())
+        .flatten();
+    let airport = |airport: Option<String>| sanitize_file_name(&airport.unwrap_or(UNKNOWN.to_string()));
+
+    template
+        .replace("{tail}", &sanitize_file_name(&config.tail_number(source)))
+        .replace(
+            "{date}",
+            &timestamp.map_or(UNKNOWN.to_string(), |t| t.format("%Y-%m-%d").to_string()),
+        )
+        .replace(
+            "{time}",
+            &timestamp.map_or(UNKNOWN.to_string(), |t| t.format("%H%M%S").to_string()),
+        )
+        .replace("{airport}", &airport(source.departure_airport()))
+        .replace(
+            "{departure}",
+            &airport(summary.as_ref().and_then(|s| s.departure.clone())),
+        )
+        .replace("{arrival}", &airport(summary.and_then(|s| s.arrival)))
+        .replace("{stem}", &sanitize_file_name(stem))
+}
+
+/// Number the outputs that share a path, in the order of their inputs, as `name-2.fdr`, `name-3.fdr` and so on, so
+/// that logs converted in parallel don't overwrite each other
+fn unique_outputs<T>(outputs: &mut [Result<(T, PathBuf), String>]) {
+    let mut used = HashSet::new();
+    for (_, output) in outputs.iter_mut().flatten() {
+        let stem = output
+            .file_stem()
+            .map_or_else(String::new, |s| s.to_string_lossy().to_string());
+        let extension = output.extension().map(|e| e.to_string_lossy().to_string());
+        let mut number = 1;
+        while used.contains(output) {
+            number += 1;
+            let name = match &extension {
+                Some(extension) => format!("{}-{}.{}", stem, number, extension),
+                None => format!("{}-{}", stem, number),
+            };
+            output.set_file_name(name);
+        }
+        used.insert(output.clone());
+    }
+}
+
+/// Whether an output file name template names the airports identified from the whole flight
+fn names_airports(template: &str) -> bool {
+    template.contains("{departure}") || template.contains("{arrival}")
+}
+
+/// The result of converting one file in a batch
+#[derive(Debug)]
+pub enum BatchOutcome {
+    /// The file was converted to the contained output path
+    Converted(PathBuf),
+    /// The output already existed, so the file was not converted again
+    Skipped(PathBuf),
+    /// The file could not be converted
+    Failed(String),
+}
+
+/// Outcomes of a batch conversion, in the order of the input files
+#[derive(Debug, Default)]
+pub struct BatchSummary {
+    pub results: Vec<(PathBuf, BatchOutcome)>,
+}
+
+impl BatchSummary {
+    pub fn converted(&self) -> usize {
+        self.count(|o| matches!(o, BatchOutcome::Converted(_)))
+    }
+
+    pub fn skipped(&self) -> usize {
+        self.count(|o| matches!(o, BatchOutcome::Skipped(_)))
+    }
+
+    pub fn failed(&self) -> usize {
+        self.count(|o| matches!(o, BatchOutcome::Failed(_)))
+    }
+
+    fn count(&self, predicate: impl Fn(&BatchOutcome) -> bool) -> usize {
+        self.results.iter().filter(|(_, o)| predicate(o)).count()
+    }
+}
+
+impl Display for BatchSummary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (input, outcome) in &self.results {
+            match outcome {
+                BatchOutcome::Converted(output) => {
+                    writeln!(f, "converted {} -> {}", input.display(), output.display())?
+                }
+                BatchOutcome::Skipped(output) => {
+                    writeln!(f, "skipped   {} ({} exists)", input.display(), output.display())?
+                }
+                BatchOutcome::Failed(err) => writeln!(f, "failed    {}: {}", input.display(), err)?,
+            }
+        }
+        write!(
+            f,
+            "{} converted, {} skipped, {} failed",
+            self.converted(),
+            self.skipped(),
+            self.failed()
+        )
+    }
+}
+
+/// Converts many avionics logs into FDR files in an output directory, in parallel
+pub struct BatchConverter {
+    config: FDRConfiguration,
+    output_dir: PathBuf,
+    source: Option<AviationLogSourceOption>,
+    name_template: String,
+    jobs: usize,
+    overwrite: bool,
+    batch_size: Option<usize>,
+    logcheck: LogCheckMode,
+}
+
+impl BatchConverter {
+    pub fn new(config: FDRConfiguration, output_dir: PathBuf) -> Self {
+        Self {
+            config,
+            output_dir,
+            source: None,
+            name_template: DEFAULT_NAME_TEMPLATE.to_string(),
+            jobs: 0,
+            overwrite: false,
+            batch_size: None,
+            logcheck: LogCheckMode::default(),
+        }
+    }
+
+    /// The source of the logs, otherwise the source of each log is detected
+    pub fn source(mut self, source: Option<AviationLogSourceOption>) -> Self {
+        self.source = source;
+        self
+    }
+
+    /// The template used to name output files, see [output_file_name]
+    pub fn name_template(mut self, template: String) -> Self {
+        self.name_template = template;
+        self
+    }
+
+    /// The number of files to convert at once, or 0 to use one per CPU
+    pub fn jobs(mut self, jobs: usize) -> Self {
+        self.jobs = jobs;
+        self
+    }
+
+    /// If set, convert files even when their output already exists
+    pub fn overwrite(mut self, overwrite: bool) -> Self {
+        self.overwrite = overwrite;
+        self
+    }
+
+    /// If set, read each log in batches of this many records
+    pub fn batch_size(mut self, batch_size: Option<usize>) -> Self {
+        self.batch_size = batch_size;
+        self
+    }
+
+    /// What to do with records that fail their checksum
+    pub fn logcheck(mut self, logcheck: LogCheckMode) -> Self {
+        self.logcheck = logcheck;
+        self
+    }
+
+    /// Convert every input file, returning the outcome for each
+    pub fn convert(&self, inputs: &[PathBuf]) -> Result<BatchSummary, Box<dyn Error>> {
+        std::fs::create_dir_all(&self.output_dir)?;
+        let pool = rayon::ThreadPoolBuilder::new().num_threads(self.jobs).build()?;
+        let results = pool.install(|| {
+            // name every output before converting any, so that logs named alike can be told apart
+            let mut outputs: Vec<_> = inputs
+                .par_iter()
+                .map(|input| self.output_path(input).map_err(|e| e.to_string()))
+                .collect();
+            unique_outputs(&mut outputs);
+            inputs
+                .par_iter()
+                .zip(outputs)
+                .map(|(input, output)| {
+                    let outcome = match output {
+                        Ok((source, output)) => self.convert_to(input, &source, output),
+                        Err(e) => BatchOutcome::Failed(e),
+                    };
+                    (input.clone(), outcome)
+                })
+                .collect()
+        });
+        Ok(BatchSummary { results })
+    }
+
+    /// Convert a single input file into the output directory
+    pub fn convert_file(&self, input: &Path) -> BatchOutcome {
+        match self.output_path(input) {
+            Ok((source, output)) => self.convert_to(input, &source, output),
+            Err(e) => BatchOutcome::Failed(e.to_string()),
+        }
+    }
+
+    /// Convert a single input file into the output directory, replacing its output if it exists, such as when the log
+    /// has changed since it was converted
+    pub fn reconvert_file(&self, input: &Path) -> BatchOutcome {
+        match self.output_path(input) {
+            Ok((source, output)) => self
+                .convert_one(input, &source, output, true)
+                .unwrap_or_else(|e| BatchOutcome::Failed(e.to_string())),
+            Err(e) => BatchOutcome::Failed(e.to_string()),
+        }
+    }
+
+    /// The source of a log, and the path it is converted to
+    fn output_path(&self, input: &Path) -> Result<(AviationLogSourceOption, PathBuf), Box<dyn Error>> {
+        let source = self.source.map_or_else(|| detect_source(input), Ok)?;
+
+        // the name usually only needs the header and first record, so open the log as a stream to find it
+        let header = if names_airports(&self.name_template) {
+            open_avionics_log(&source, input, None, self.logcheck)?
+        } else {
+            open_avionics_log(&source, input, Some(1), self.logcheck)?
+        };
+        let name = output_file_name(&self.name_template, header.as_ref(), &self.config, input);
+        Ok((source, self.output_dir.join(name)))
+    }
+
+    /// Convert a log to an output path
+    fn convert_to(&self, input: &Path, source: &AviationLogSourceOption, output: PathBuf) -> BatchOutcome {
+        self.convert_one(input, source, output, self.overwrite)
+            .unwrap_or_else(|e| BatchOutcome::Failed(e.to_string()))
+    }
+
+    fn convert_one(
+        &self,
+        input: &Path,
+        source: &AviationLogSourceOption,
+        output: PathBuf,
+        overwrite: bool,
+    ) -> Result<BatchOutcome, Box<dyn Error>> {
+        if output.exists() && !overwrite {
+            return Ok(BatchOutcome::Skipped(output));
+        }
+
+        let data = open_avionics_log(source, input, self.batch_size, self.logcheck)?;
+
+        // write to a temporary file first, so an interrupted conversion is not mistaken for a finished one
+        let mut file = tempfile::NamedTempFile::new_in(&self.output_dir)?;
+        FDRWriter::new(self.config.clone()).write(data, &mut file)?;
+        file.flush()?;
+        file.persist(&output)?;
+        Ok(BatchOutcome::Converted(output))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fdr::FDRConfigurationBuilder;
+
+    #[test]
+    fn test_batch_convert_skips_existing() -> Result<(), Box<dyn Error>> {
+        let input_dir = tempfile::tempdir()?;
+        let output_dir = tempfile::tempdir()?;
+        let log = input_dir.path().join("data_log").join("log_231104_084813_KPOU.csv");
+        std::fs::create_dir_all(log.parent().unwrap())?;
+        std::fs::copy(crate::resource_path("log_231104_084813_KPOU.csv"), &log)?;
+        std::fs::write(input_dir.path().join("notes.csv"), "not,a,log\n")?;
+
+        let inputs = collect_inputs(input_dir.path())?;
+        assert_eq!(inputs.len(), 2);
+
+        let config = FDRConfigurationBuilder::default().build();
+        let converter = BatchConverter::new(config, output_dir.path().to_path_buf()).jobs(2);
+
+        let summary = converter.convert(&inputs)?;
+        assert_eq!((summary.converted(), summary.skipped(), summary.failed()), (1, 0, 1));
+        assert!(output_dir.path().join("2023-11-04_124813_N12345_KPOU.fdr").exists());
+
+        let summary = converter.convert(&inputs)?;
+        assert_eq!((summary.converted(), summary.skipped(), summary.failed()), (0, 1, 1));
+
+        // logs named alike are numbered rather than overwriting each other
+        let copy = input_dir.path().join("copy [1]").join("log_231104_084813_KPOU.csv");
+        std::fs::create_dir_all(copy.parent().unwrap())?;
+        std::fs::copy(&log, &copy)?;
+        assert!(!is_batch_input(&copy));
+        let summary = converter.overwrite(true).convert(&collect_inputs(input_dir.path())?)?;
+        assert_eq!((summary.converted(), summary.skipped(), summary.failed()), (2, 0, 1));
+        assert!(output_dir.path().join("2023-11-04_124813_N12345_KPOU-2.fdr").exists());
+
+        // values from the log can't name a file outside of the output directory
+        assert_eq!(sanitize_file_name("../N1/2"), "__N1_2");
+        Ok(())
+    }
+}
@@ -0,0 +1,297 @@
+//! Derived parameters: values X-Plane can replay that are not logged directly, computed from the columns that are.
+//!
+//! An aircraft profile names the derivations to run. Each adds its columns to the data before fields are mapped to
+//! DREFs, and is skipped when the log lacks a column it needs.
+
+use crate::fdr::DataRef;
+use crate::garmin;
+use crate::profile::{AircraftProfile, EngineType};
+use polars::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// Feet per minute in a knot
+const FPM_PER_KNOT: f64 = 6076.12 / 60.0;
+
+/// A named computation of columns from other columns
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Derivation {
+    /// `AoA`, the angle of attack estimated as the pitch less the flight path angle, from `Pitch`, `VSpd` and `TAS`
+    AngleOfAttack,
+    /// `DensAlt`, the density altitude from the pressure altitude `AltPress` and outside air temperature `OAT`
+    DensityAltitude,
+    /// `HdWnd` and `XWnd`, the headwind and crosswind from the right, from the air vector of `TAS` and `HDG` and the
+    /// ground vector of `GndSpd` and `TRK`
+    WindComponents,
+    /// `FUsed`, the fuel burned by every engine since the start of the log, integrating the `E{n} FFlow` columns
+    FuelBurned,
+}
+
+impl Derivation {
+    /// The names and units of the columns the derivation adds. Fuel is in `fuel_unit`, the unit of fuel of the fuel
+    /// flow, see [fuel_unit].
+    pub fn columns(&self, fuel_unit: &'static str) -> Vec<(&'static str, &'static str)> {
+        match self {
+            Derivation::AngleOfAttack => vec![("AoA", "deg")],
+            Derivation::DensityAltitude => vec![("DensAlt", "ft")],
+            Derivation::WindComponents => vec![("HdWnd", "kt"), ("XWnd", "kt")],
+            Derivation::FuelBurned => vec![("FUsed", fuel_unit)],
+        }
+    }
+
+    /// The names of the columns the derivation adds
+    pub fn names(&self) -> Vec<&'static str> {
+        self.columns("").into_iter().map(|(name, _)| name).collect()
+    }
+
+    /// The values of the columns of the derivation, in the order of [Derivation::columns], or None if a column it
+    /// needs is missing. Derivations that accumulate continue from `state`, and leave it for the next batch.
+    fn derive(&self, data: &DataFrame, state: &mut DerivationState) -> PolarsResult<Option<Vec<Vec<Option<f64>>>>> {
+        let values = |name: &str| -> PolarsResult<Option<Vec<Option<f64>>>> {
+            match data.column(name) {
+                Ok(c) => Ok(Some(c.cast(&DataType::Float64)?.f64()?.into_iter().collect())),
+                Err(_) => Ok(None),
+            }
+        };
+        macro_rules! inputs {
+            ($($name:expr),+) => {
+                ($(match values($name)? {
+                    Some(v) => v,
+                    None => return Ok(None),
+                }),+)
+            };
+        }
+
+        let derived = match self {
+            Derivation::AngleOfAttack => {
+                let (pitch, vspd, tas) = inputs!("Pitch", "VSpd", "TAS");
+                let aoa = (0..data.height())
+                    .map(|i| {
+                        // the flight path angle is meaningless without airspeed
+                        let tas = tas[i].filter(|t| *t > 1.0)?;
+                        Some(pitch[i]? - vspd[i]?.atan2(tas * FPM_PER_KNOT).to_degrees())
+                    })
+                    .collect();
+                vec![aoa]
+            }
+            Derivation::DensityAltitude => {
+                let (pressure_altitude, oat) = inputs!("AltPress", "OAT");
+                let density_altitude = pressure_altitude
+                    .iter()
+                    .zip(&oat)
+                    .map(|(pa, oat)| {
+                        let isa = 15.0 - 1.98 * pa.as_ref()? / 1000.0;
+                        Some(pa.as_ref()? + 118.8 * (oat.as_ref()? - isa))
+                    })
+                    .collect();
+                vec![density_altitude]
+            }
+            Derivation::WindComponents => {
+                let (tas, heading, ground_speed, track) = inputs!("TAS", "HDG", "GndSpd", "TRK");
+                let (headwind, crosswind) = (0..data.height())
+                    .map(|i| {
+                        let (tas, heading) = (tas[i]?, heading[i]?.to_radians());
+                        let (gs, track) = (ground_speed[i]?, track[i]?.to_radians());
+                        // the wind is the ground vector less the air vector, toward north and east
+                        let north = gs * track.cos() - tas * heading.cos();
+                        let east = gs * track.sin() - tas * heading.sin();
+                        let along = north * heading.cos() + east * heading.sin();
+                        let right = east * heading.cos() - north * heading.sin();
+                        Some((-along, -right))
+                    })
+                    .map(|c| (c.map(|c| c.0), c.map(|c| c.1)))
+                    .unzip();
+                vec![headwind, crosswind]
+            }
+            Derivation::FuelBurned => {
+                let flows: Vec<Vec<Option<f64>>> = data
+                    .get_column_names()
+                    .iter()
+                    .filter(|name| is_engine_field(name, "FFlow"))
+                    .map(|name| values(name))
+                    .collect::<PolarsResult<Option<_>>>()?
+                    .unwrap_or_default();
+                if flows.is_empty() {
+                    return Ok(None);
+                }
+                let times: Vec<Option<f64>> = data
+                    .column("timestamp")?
+                    .datetime()?
+                    .cast_time_unit(TimeUnit::Microseconds)
+                    .into_iter()
+                    .map(|t| t.map(|t| t as f64 / 1e6))
+                    .collect();
+                let flow = |i: usize| -> Option<f64> { flows.iter().map(|f| f[i]).sum() };
+
+                // integrate the total flow per hour over time, with the trapezoid rule
+                let burned = (0..data.height())
+                    .map(|i| {
+                        let (time, flow) = (times[i]?, flow(i)?);
+                        if let Some((last_time, last_flow)) = state.last_flow.filter(|(t, _)| time > *t) {
+                            state.fuel_used += (flow + last_flow) / 2.0 * (time - last_time) / 3600.0;
+                        }
+                        state.last_flow = Some((time, flow));
+                        Some(state.fuel_used)
+                    })
+                    .collect();
+                vec![burned]
+            }
+        };
+        Ok(Some(derived))
+    }
+}
+
+/// Whether a column is a field of any engine, such as `E2 FFlow` for the field `FFlow`
+pub fn is_engine_field(name: &str, field: &str) -> bool {
+    name.strip_prefix('E')
+        .and_then(|rest| rest.strip_suffix(field))
+        .and_then(|rest| rest.strip_suffix(' '))
+        .is_some_and(|n| !n.is_empty() && n.chars().all(|c| c.is_ascii_digit()))
+}
+
+/// What derivations that accumulate over a log, such as the fuel burned, have reached at the end of a batch of its
+/// records, to continue from in the next
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DerivationState {
+    fuel_used: f64,
+    /// The time, in seconds, and total fuel flow of the last record with both
+    last_flow: Option<(f64, f64)>,
+}
+
+/// Add the columns of derivations to a dataframe, replacing any it already has, and skipping derivations that need
+/// a column it lacks
+pub fn derive_columns(data: DataFrame, derivations: &[Derivation]) -> PolarsResult<DataFrame> {
+    derive_batch_columns(data, derivations, &mut DerivationState::default())
+}
+
+/// Add the columns of derivations to a batch of the records of a log, as [derive_columns], continuing from the state
+/// the batch before it left
+pub fn derive_batch_columns(
+    mut data: DataFrame,
+    derivations: &[Derivation],
+    state: &mut DerivationState,
+) -> PolarsResult<DataFrame> {
+    for derivation in derivations {
+        let Some(derived) = derivation.derive(&data, state)? else {
+            continue;
+        };
+        for (name, values) in derivation.names().into_iter().zip(derived) {
+            data.with_column(Series::new(name.into(), values))?;
+        }
+    }
+    Ok(data)
+}
+
+/// The unit of the fuel burned by the engines of a log: the unit of fuel its fuel flow is logged in, such as `gals`
+/// for `gph`, otherwise the usual unit of the engine type of the profile, or of the engines logged, gallons for pistons
+/// and pounds for turbines
+pub fn fuel_unit(units: &[(String, String)], profile: &AircraftProfile) -> &'static str {
+    let logged = units
+        .iter()
+        .find(|(name, _)| is_engine_field(name, "FFlow"))
+        .and_then(|(_, unit)| match unit.trim().to_ascii_lowercase().as_str() {
+            "gph" => Some("gals"),
+            "pph" | "lbs/hr" => Some("lbs"),
+            _ => None,
+        });
+    logged.unwrap_or_else(|| {
+        let columns: Vec<&str> = units.iter().map(|(name, _)| name.as_str()).collect();
+        match profile
+            .engine_type
+            .unwrap_or_else(|| garmin::logged_engine_type(&columns))
+        {
+            EngineType::Piston => "gals",
+            EngineType::Turbine => "lbs",
+        }
+    })
+}
+
+/// The DREFs derived columns are written to, for those X-Plane has one for. `fuel_scale` is the kilograms in the unit
+/// of the fuel burned, without which it is not written.
+pub fn derived_drefs(fuel_scale: Option<f64>) -> Vec<(&'static str, DataRef)> {
+    let mut drefs = vec![(
+        "AoA",
+        DataRef::new("sim/flightmodel2/misc/AoA_angle_degrees".to_string()),
+    )];
+    if let Some(scale) = fuel_scale {
+        drefs.push((
+            "FUsed",
+            DataRef::new("sim/cockpit2/fuel/fuel_totalizer_sum_kg".to_string()).with_scale(scale),
+        ));
+    }
+    drefs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fdr::{FDRConfigurationBuilder, FlightDataError};
+    use crate::{detection::read_avionics_log, AviationLogSourceOption};
+
+    #[test]
+    fn test_derived_columns() -> Result<(), Box<dyn std::error::Error>> {
+        let path = crate::resource_path("log_231104_084813_KPOU.csv");
+        let data = read_avionics_log(&AviationLogSourceOption::Garmin, &path)?.data()?;
+        let derivations = [
+            Derivation::AngleOfAttack,
+            Derivation::DensityAltitude,
+            Derivation::WindComponents,
+            Derivation::FuelBurned,
+        ];
+        let derived = derive_columns(data.clone(), &derivations)?;
+        assert_eq!(derived.width(), data.width() + 5);
+
+        // a level cruise is flown at a small angle of attack
+        let aoa = derived.column("AoA")?.f64()?;
+        let cruise = aoa.get(2500).unwrap();
+        assert!(cruise.abs() < 6.0, "{}", cruise);
+
+        // the winds agree with the speed the avionics logged
+        let (head, cross) = (derived.column("HdWnd")?.f64()?, derived.column("XWnd")?.f64()?);
+        let logged = data.column("WndSpd")?.cast(&DataType::Float64)?;
+        let logged = logged.f64()?.get(2500).unwrap();
+        let derived_speed = head.get(2500).unwrap().hypot(cross.get(2500).unwrap());
+        assert!((derived_speed - logged).abs() < 10.0, "{} {}", derived_speed, logged);
+
+        // fuel burned only grows, to about the fuel flow over the length of the log
+        let used: Vec<f64> = derived.column("FUsed")?.f64()?.into_iter().flatten().collect();
+        assert!(used.windows(2).all(|w| w[1] >= w[0]));
+        let total = *used.last().unwrap();
+        assert!(total > 1.0 && total < 20.0, "{}", total);
+
+        // fuel burned continues from one batch of the log to the next
+        let mut state = DerivationState::default();
+        let first = derive_batch_columns(data.slice(0, 1000), &derivations, &mut state)?;
+        let rest = derive_batch_columns(data.slice(1000, data.height()), &derivations, &mut state)?;
+        let batched = first.vstack(&rest)?;
+        assert!(batched.column("FUsed")?.equals_missing(derived.column("FUsed")?));
+
+        // derivations whose inputs are missing are skipped
+        let without = derive_columns(data.drop("AltPress")?, &[Derivation::DensityAltitude])?;
+        assert!(without.column("DensAlt").is_err());
+
+        // derived columns without a dataref are left out of the block, rather than failing a strict conversion
+        let source = read_avionics_log(&AviationLogSourceOption::Garmin, &path)?;
+        let profile = AircraftProfile {
+            derive: derivations.to_vec(),
+            ..Default::default()
+        };
+        let config = FDRConfigurationBuilder::default()
+            .auto_drefs(true)
+            .strict(true)
+            .profile(profile)
+            .build();
+        match source.data_block(&config) {
+            Err(FlightDataError::MissingDrefs(columns)) => {
+                assert!(!columns
+                    .iter()
+                    .any(|c| ["DensAlt", "HdWnd", "XWnd"].contains(&c.as_str())))
+            }
+            other => panic!(
+                "expected the logged columns without a dataref, not {:?}",
+                other.map(|_| ())
+            ),
+        }
+        Ok(())
+    }
+}
@@ -1,8 +1,15 @@
-use crate::{fdr::FlightDataSource, garmin, AviationLogSourceOption};
+use crate::{
+    fdr::{DataRef, FlightDataSource},
+    garmin::{self, LogCheckMode},
+    profile::AircraftProfile,
+    AviationLogSourceOption,
+};
 use std::{
+    collections::HashMap,
     error::Error,
     fmt::{Display, Formatter},
     path::Path,
+    time::Duration,
 };
 
 /// Error type for source detection
@@ -35,7 +42,69 @@ pub fn read_avionics_log(
     source: &AviationLogSourceOption,
     path: &Path,
 ) -> Result<Box<dyn FlightDataSource>, Box<dyn Error>> {
+    open_avionics_log(source, path, None, LogCheckMode::default())
+}
+
+/// Open an avionics log file as a source that is read in batches of `batch_size` records
+///
+/// Memory use is bounded by the batch size rather than the length of the log, which suits very long or high-rate logs.
+pub fn stream_avionics_log(
+    source: &AviationLogSourceOption,
+    path: &Path,
+    batch_size: usize,
+) -> Result<Box<dyn FlightDataSource>, Box<dyn Error>> {
+    open_avionics_log(source, path, Some(batch_size), LogCheckMode::default())
+}
+
+/// Open an avionics log file, read whole or in batches, handling records that fail their checksum as `logcheck` says
+pub fn open_avionics_log(
+    source: &AviationLogSourceOption,
+    path: &Path,
+    batch_size: Option<usize>,
+    logcheck: LogCheckMode,
+) -> Result<Box<dyn FlightDataSource>, Box<dyn Error>> {
+    match (source, batch_size) {
+        (AviationLogSourceOption::Garmin, Some(batch_size)) => {
+            Ok(Box::new(garmin::GarminLogStream::new(path, batch_size, logcheck)?))
+        }
+        (AviationLogSourceOption::Garmin, None) => Ok(Box::new(garmin::GarminLogFile::new(path, logcheck)?)),
+    }
+}
+
+/// Open an avionics log file that is still being written, as a source whose data blocks are the records appended to
+/// it, checking for more every `poll_interval` until none have been appended for `idle_timeout`
+pub fn follow_avionics_log(
+    source: &AviationLogSourceOption,
+    path: &Path,
+    poll_interval: Duration,
+    idle_timeout: Option<Duration>,
+    logcheck: LogCheckMode,
+) -> Result<Box<dyn FlightDataSource>, Box<dyn Error>> {
+    match source {
+        AviationLogSourceOption::Garmin => Ok(Box::new(garmin::GarminLogFollow::new(
+            path,
+            poll_interval,
+            idle_timeout,
+            logcheck,
+        )?)),
+    }
+}
+
+/// The mapping from the field names of a source to X-Plane DREFs, used when automatically mapping fields
+pub fn dref_map(source: &AviationLogSourceOption, profile: &AircraftProfile) -> Vec<(String, DataRef)> {
+    match source {
+        AviationLogSourceOption::Garmin => garmin::dref_map(profile),
+    }
+}
+
+/// The DREFs the fields of a log are written to, for the fields that have one, given the units they are logged in
+pub fn column_drefs(
+    source: &AviationLogSourceOption,
+    columns: &[&str],
+    units: &[(String, String)],
+    profile: &AircraftProfile,
+) -> HashMap<String, DataRef> {
     match source {
-        AviationLogSourceOption::Garmin => Ok(Box::new(garmin::GarminLogFile::new(path)?)),
+        AviationLogSourceOption::Garmin => garmin::column_drefs(columns, units, profile),
     }
 }
@@ -0,0 +1,528 @@
+//! Cylinder temperature analysis of each engine of a flight: leaning, GAMI spread, CHT and EGT spread, and shock
+//! cooling.
+//!
+//! A leaning event is a fall in fuel flow during which the exhaust gas temperatures rise, which tells it apart from a
+//! power reduction. A cylinder peaks during the event when its EGT falls again before the fuel flow stops falling.
+//! When every cylinder peaks the engine ends the event lean of peak, and the GAMI spread is the difference between
+//! the fuel flows at which the first and last cylinders peaked. Cylinder spreads and shock cooling are measured in
+//! flight only.
+
+use crate::fdr::{FDRConfiguration, FlightDataError, FlightDataSource};
+use crate::garmin::match_engine_pattern;
+use crate::track;
+use chrono::{DateTime, NaiveDate, Utc};
+use polars::prelude::*;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt::Display;
+
+/// Fuel flow and EGTs are smoothed over this many records before leaning is detected
+const SMOOTHING_RECORDS: usize = 5;
+/// A leaning event lowers the fuel flow by at least this much, in the unit of fuel flow
+const MIN_LEAN_DROP: f64 = 1.0;
+/// A leaning event lasts at least this many seconds, longer than the fall in fuel flow as an engine starts
+const MIN_LEAN_SECS: f64 = 10.0;
+/// A leaning event lasts at most this many seconds, which bounds the records scanned from each start
+const MAX_LEAN_SECS: f64 = 300.0;
+/// Fuel flow rising more than this above its lowest value ends a leaning event
+const LEAN_RISE_TOLERANCE: f64 = 0.3;
+/// During a leaning event the mean EGT rises by at least this many deg F
+const LEAN_EGT_RISE_F: f64 = 25.0;
+/// A cylinder has peaked once its EGT falls this many deg F below its highest
+const PEAK_DROP_F: f64 = 10.0;
+/// Cylinders cooling faster than this many deg F per minute are shock cooled
+const SHOCK_COOLING_F_PER_MIN: f64 = 50.0;
+/// Without a logged cooling rate, it is measured from the CHTs over this many seconds
+const COOLING_WINDOW_SECS: f64 = 60.0;
+/// The widest range of RPM during a leaning event, so that a change of power is not taken for leaning
+const LEAN_RPM_BAND: f64 = 100.0;
+/// The widest range of manifold pressure during a leaning event, in inches of mercury
+const LEAN_MAP_BAND_INHG: f64 = 1.0;
+/// A gap in recording longer than this many seconds ends a leaning event
+const MAX_GAP_SECS: f64 = 5.0;
+
+/// The values of each cylinder of an engine, with its cylinder number
+type Cylinders = Vec<(usize, Vec<Option<f64>>)>;
+
+/// Where a cylinder's EGT peaked during a leaning event
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct CylinderPeak {
+    pub cylinder: usize,
+    pub time: DateTime<Utc>,
+    /// The peak EGT in deg F
+    pub egt: f64,
+    /// The fuel flow at the peak
+    pub fuel_flow: f64,
+}
+
+/// A fall in fuel flow with rising EGTs
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct LeaningEvent {
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+    pub start_fuel_flow: f64,
+    pub end_fuel_flow: f64,
+    /// The cylinders that peaked, in the order they peaked
+    pub peaks: Vec<CylinderPeak>,
+    /// Whether every cylinder peaked, leaving the engine lean of peak
+    pub lean_of_peak: bool,
+    /// The difference between the fuel flows at which the first and last cylinders peaked, if every cylinder did
+    pub gami_spread: Option<f64>,
+}
+
+/// The largest difference between the temperatures of the cylinders of an engine
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Spread {
+    pub time: DateTime<Utc>,
+    /// The difference in deg F
+    pub spread: f64,
+    pub hottest: usize,
+    pub coolest: usize,
+}
+
+/// The fastest cooling of the cylinder heads of an engine
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ShockCooling {
+    pub time: DateTime<Utc>,
+    /// The fastest cooling rate in deg F per minute
+    pub max_rate: f64,
+    /// Seconds spent cooling faster than 50 deg F per minute
+    pub seconds_over_limit: f64,
+}
+
+/// The cylinder temperature analysis of an engine
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct EngineReport {
+    pub engine: usize,
+    pub cylinders: usize,
+    pub leaning: Vec<LeaningEvent>,
+    pub cht_spread: Option<Spread>,
+    pub egt_spread: Option<Spread>,
+    pub shock_cooling: Option<ShockCooling>,
+}
+
+/// The engine analysis of a flight
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct EngineAnalysis {
+    pub tail_number: String,
+    /// The UTC date of the first record
+    pub date: Option<NaiveDate>,
+    pub engines: Vec<EngineReport>,
+}
+
+impl EngineAnalysis {
+    /// Analyze the engines of the flight logged by a source
+    pub fn from_source(source: &dyn FlightDataSource, config: &FDRConfiguration) -> Result<Self, FlightDataError> {
+        let data = source.data()?;
+        let engines = analyze_engines(&data).map_err(|e| FlightDataError::ReadError(e.to_string()))?;
+        Ok(EngineAnalysis {
+            tail_number: config.tail_number(source),
+            date: source.timestamp().map(|t| t.date_naive()),
+            engines,
+        })
+    }
+}
+
+/// The mean of the values of a centered window around each record, or None where the window has no values
+fn smooth(values: &[Option<f64>]) -> Vec<Option<f64>> {
+    let half = SMOOTHING_RECORDS / 2;
+    (0..values.len())
+        .map(|i| {
+            let window: Vec<f64> = values[i.saturating_sub(half)..(i + half + 1).min(values.len())]
+                .iter()
+                .flatten()
+                .copied()
+                .collect();
+            (!window.is_empty()).then(|| window.iter().sum::<f64>() / window.len() as f64)
+        })
+        .collect()
+}
+
+/// The cylinder columns of a family such as `EGT` for each engine, in order of cylinder
+fn cylinder_columns(df: &DataFrame, family: &str) -> PolarsResult<BTreeMap<usize, Cylinders>> {
+    let pattern = format!("E{{n}} {}{{m}}", family);
+    let mut engines: BTreeMap<usize, Cylinders> = BTreeMap::new();
+    for name in df.get_column_names() {
+        if let Some((engine, Some(cylinder))) = match_engine_pattern(&pattern, name) {
+            let values = track::float_column(df, name)?.unwrap_or_default();
+            engines.entry(engine).or_default().push((cylinder, values));
+        }
+    }
+    engines
+        .values_mut()
+        .for_each(|c| c.sort_by_key(|(cylinder, _)| *cylinder));
+    Ok(engines)
+}
+
+/// The record with the largest difference between the hottest and coolest cylinder, of those with every cylinder
+fn max_spread(cylinders: &[(usize, Vec<Option<f64>>)], times: &[Option<DateTime<Utc>>]) -> Option<Spread> {
+    let mut max: Option<Spread> = None;
+    for (i, time) in times.iter().enumerate() {
+        let Some(time) = time else {
+            continue;
+        };
+        let values: Option<Vec<(usize, f64)>> = cylinders.iter().map(|(c, v)| v[i].map(|v| (*c, v))).collect();
+        let Some(values) = values.filter(|v| v.len() > 1) else {
+            continue;
+        };
+        let hottest = values.iter().max_by(|a, b| a.1.total_cmp(&b.1))?;
+        let coolest = values.iter().min_by(|a, b| a.1.total_cmp(&b.1))?;
+        let spread = hottest.1 - coolest.1;
+        if max.as_ref().is_none_or(|m| spread > m.spread) {
+            max = Some(Spread {
+                time: *time,
+                spread,
+                hottest: hottest.0,
+                coolest: coolest.0,
+            });
+        }
+    }
+    max
+}
+
+/// The cooling rate of each record in deg F per minute: the logged `CHT CLD`, which is negative while cooling, or
+/// else the fastest fall of any CHT over the last minute
+fn cooling_rates(logged: Option<Vec<Option<f64>>>, chts: &Cylinders, seconds: &[Option<f64>]) -> Vec<Option<f64>> {
+    if let Some(logged) = logged {
+        return logged.iter().map(|v| v.map(|v| (-v).max(0.0))).collect();
+    }
+    let mut earlier = 0;
+    (0..seconds.len())
+        .map(|i| {
+            let now = seconds[i]?;
+            while seconds[earlier].is_none_or(|t| now - t > COOLING_WINDOW_SECS) && earlier < i {
+                earlier += 1;
+            }
+            let minutes = (now - seconds[earlier]?) / 60.0;
+            if minutes <= 0.0 {
+                return None;
+            }
+            chts.iter()
+                .filter_map(|(_, v)| Some((v[earlier]? - v[i]?) / minutes))
+                .reduce(f64::max)
+                .map(|rate| rate.max(0.0))
+        })
+        .collect()
+}
+
+/// The leaning events of an engine, from its smoothed fuel flow and EGTs, while the power fields stay within their
+/// bands
+fn leaning_events(
+    fuel_flow: &[Option<f64>],
+    egts: &[(usize, Vec<Option<f64>>)],
+    power: &[(Vec<Option<f64>>, f64)],
+    times: &[Option<DateTime<Utc>>],
+    seconds: &[Option<f64>],
+) -> Vec<LeaningEvent> {
+    let mean_egt: Vec<Option<f64>> = (0..fuel_flow.len())
+        .map(|i| {
+            let values: Vec<f64> = egts.iter().filter_map(|(_, v)| v[i]).collect();
+            (!values.is_empty()).then(|| values.iter().sum::<f64>() / values.len() as f64)
+        })
+        .collect();
+
+    let mut events = Vec::new();
+    let mut start = 0;
+    while start < fuel_flow.len() {
+        let (Some(start_flow), Some(_)) = (fuel_flow[start], times[start]) else {
+            start += 1;
+            continue;
+        };
+        // follow the fuel flow down until it rises again
+        let mut lowest = start;
+        let mut end = start + 1;
+        let mut ranges: Vec<(f64, f64)> = power
+            .iter()
+            .map(|(values, _)| values[start].map_or((f64::INFINITY, f64::NEG_INFINITY), |v| (v, v)))
+            .collect();
+        while end < fuel_flow.len() {
+            let (Some(flow), Some(time), Some(last)) = (fuel_flow[end], seconds[end], seconds[end - 1]) else {
+                break;
+            };
+            if time - last > MAX_GAP_SECS || flow > fuel_flow[lowest].unwrap_or(start_flow) + LEAN_RISE_TOLERANCE {
+                break;
+            }
+            if seconds[start].is_some_and(|first| time - first > MAX_LEAN_SECS) {
+                break;
+            }
+            for ((values, _), range) in power.iter().zip(&mut ranges) {
+                if let Some(value) = values[end] {
+                    *range = (range.0.min(value), range.1.max(value));
+                }
+            }
+            if power
+                .iter()
+                .zip(&ranges)
+                .any(|((_, band), (low, high))| high - low > *band)
+            {
+                break;
+            }
+            if flow < fuel_flow[lowest].unwrap_or(start_flow) {
+                lowest = end;
+            }
+            end += 1;
+        }
+        let end_flow = fuel_flow[lowest].unwrap_or(start_flow);
+        let egt_rise = mean_egt[start..=lowest]
+            .iter()
+            .flatten()
+            .copied()
+            .reduce(f64::max)
+            .zip(mean_egt[start])
+            .map(|(max, first)| max - first);
+        let length = seconds[lowest]
+            .zip(seconds[start])
+            .map_or(0.0, |(last, first)| last - first);
+        if start_flow - end_flow < MIN_LEAN_DROP
+            || length < MIN_LEAN_SECS
+            || egt_rise.is_none_or(|rise| rise < LEAN_EGT_RISE_F)
+        {
+            start += 1;
+            continue;
+        }
+
+        let mut peaks: Vec<CylinderPeak> = egts
+            .iter()
+            .filter_map(|(cylinder, egt)| {
+                let (peak, egt_peak) = (start..=lowest)
+                    .filter_map(|i| egt[i].map(|e| (i, e)))
+                    .max_by(|a, b| a.1.total_cmp(&b.1))?;
+                let fell = (peak..=lowest)
+                    .filter_map(|i| egt[i])
+                    .any(|e| egt_peak - e >= PEAK_DROP_F);
+                fell.then_some(CylinderPeak {
+                    cylinder: *cylinder,
+                    time: times[peak]?,
+                    egt: egt_peak,
+                    fuel_flow: fuel_flow[peak]?,
+                })
+            })
+            .collect();
+        peaks.sort_by_key(|p| p.time);
+        let lean_of_peak = !egts.is_empty() && peaks.len() == egts.len();
+        let gami_spread = lean_of_peak.then(|| {
+            let flows = peaks.iter().map(|p| p.fuel_flow);
+            flows.clone().fold(f64::MIN, f64::max) - flows.fold(f64::MAX, f64::min)
+        });
+        if let (Some(first), Some(last)) = (times[start], times[lowest]) {
+            events.push(LeaningEvent {
+                start: first,
+                end: last,
+                start_fuel_flow: start_flow,
+                end_fuel_flow: end_flow,
+                peaks,
+                lean_of_peak,
+                gami_spread,
+            });
+        }
+        start = lowest + 1;
+    }
+    events
+}
+
+/// Analyze the cylinder temperatures of each engine that logs them in a dataframe
+pub fn analyze_engines(df: &DataFrame) -> PolarsResult<Vec<EngineReport>> {
+    let times: Vec<Option<DateTime<Utc>>> = df
+        .column("timestamp")?
+        .datetime()?
+        .cast_time_unit(TimeUnit::Microseconds)
+        .into_iter()
+        .map(|t| t.and_then(DateTime::<Utc>::from_timestamp_micros))
+        .collect();
+    let seconds: Vec<Option<f64>> = times
+        .iter()
+        .map(|t| t.map(|t| t.timestamp_micros() as f64 / 1e6))
+        .collect();
+    // spreads and cooling are measured in flight, as the cylinders settle on the ground after the engine starts
+    let airborne = track::airborne(df, &times)?;
+    let flying: Vec<Option<DateTime<Utc>>> = times.iter().zip(&airborne).map(|(t, a)| t.filter(|_| *a)).collect();
+    let (mut chts, mut egts) = (cylinder_columns(df, "CHT")?, cylinder_columns(df, "EGT")?);
+    let mut numbers: Vec<usize> = chts.keys().chain(egts.keys()).copied().collect();
+    numbers.sort();
+    numbers.dedup();
+
+    let mut reports = Vec::new();
+    for engine in numbers {
+        let (cht, egt) = (
+            chts.remove(&engine).unwrap_or_default(),
+            egts.remove(&engine).unwrap_or_default(),
+        );
+        let fuel_flow = track::float_column(df, &format!("E{} FFlow", engine))?;
+        let mut power = Vec::new();
+        for (field, band) in [("RPM", LEAN_RPM_BAND), ("MAP", LEAN_MAP_BAND_INHG)] {
+            if let Some(values) = track::float_column(df, &format!("E{} {}", engine, field))? {
+                power.push((values, band));
+            }
+        }
+        let leaning = match &fuel_flow {
+            Some(fuel_flow) => {
+                let smoothed: Cylinders = egt.iter().map(|(c, v)| (*c, smooth(v))).collect();
+                leaning_events(&smooth(fuel_flow), &smoothed, &power, &times, &seconds)
+            }
+            None => Vec::new(),
+        };
+
+        let logged_cooling = track::float_column(df, &format!("E{} CHT CLD", engine))?;
+        let rates = cooling_rates(logged_cooling, &cht, &seconds);
+        let shock_cooling = (0..rates.len())
+            .filter_map(|i| Some((flying[i]?, rates[i]?)))
+            .max_by(|a, b| a.1.total_cmp(&b.1))
+            .map(|(time, max_rate)| ShockCooling {
+                time,
+                max_rate,
+                seconds_over_limit: (1..rates.len())
+                    .filter(|i| airborne[*i] && rates[*i].is_some_and(|r| r > SHOCK_COOLING_F_PER_MIN))
+                    .filter_map(|i| Some(seconds[i]? - seconds[i - 1]?))
+                    .filter(|dt| *dt <= MAX_GAP_SECS)
+                    .sum(),
+            });
+
+        reports.push(EngineReport {
+            engine,
+            cylinders: cht.len().max(egt.len()),
+            leaning,
+            cht_spread: max_spread(&cht, &flying),
+            egt_spread: max_spread(&egt, &flying),
+            shock_cooling,
+        });
+    }
+    Ok(reports)
+}
+
+impl Display for EngineAnalysis {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let time = |t: &DateTime<Utc>| t.format("%H:%M:%SZ").to_string();
+        write!(
+            f,
+            "{} {}",
+            self.date.map_or("-".to_string(), |d| d.to_string()),
+            self.tail_number
+        )?;
+        if self.engines.is_empty() {
+            write!(f, "\n  No cylinder temperatures logged")?;
+        }
+        for report in &self.engines {
+            write!(f, "\n  Engine {}, {} cylinders", report.engine, report.cylinders)?;
+            for (family, spread) in [("CHT", &report.cht_spread), ("EGT", &report.egt_spread)] {
+                if let Some(spread) = spread {
+                    write!(
+                        f,
+                        "\n    {} spread: {:.0} F max at {}, {}{} hottest, {}{} coolest",
+                        family,
+                        spread.spread,
+                        time(&spread.time),
+                        family,
+                        spread.hottest,
+                        family,
+                        spread.coolest
+                    )?;
+                }
+            }
+            if let Some(cooling) = &report.shock_cooling {
+                write!(
+                    f,
+                    "\n    Shock cooling: {:.0} F/min max at {}, {:.0} s faster than {:.0} F/min",
+                    cooling.max_rate,
+                    time(&cooling.time),
+                    cooling.seconds_over_limit,
+                    SHOCK_COOLING_F_PER_MIN
+                )?;
+            }
+            for event in &report.leaning {
+                write!(
+                    f,
+                    "\n    Leaned {} to {}: fuel flow {:.1} to {:.1}, ",
+                    time(&event.start),
+                    time(&event.end),
+                    event.start_fuel_flow,
+                    event.end_fuel_flow
+                )?;
+                let peaks: Vec<String> = event
+                    .peaks
+                    .iter()
+                    .map(|p| format!("EGT{} at {:.1}", p.cylinder, p.fuel_flow))
+                    .collect();
+                match event.gami_spread {
+                    Some(spread) => write!(f, "lean of peak, GAMI spread {:.1} ({})", spread, peaks.join(", "))?,
+                    None if peaks.is_empty() => write!(f, "rich of peak")?,
+                    None => write!(f, "peaked {}", peaks.join(", "))?,
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{detection::read_avionics_log, AviationLogSourceOption};
+
+    #[test]
+    fn test_engine_analysis() -> Result<(), Box<dyn std::error::Error>> {
+        let path = crate::resource_path("log_231104_084813_KPOU.csv");
+        let data = read_avionics_log(&AviationLogSourceOption::Garmin, &path)?.data()?;
+        let reports = analyze_engines(&data)?;
+        assert_eq!(reports.len(), 1);
+        let report = &reports[0];
+        assert_eq!((report.engine, report.cylinders), (1, 4));
+
+        // the mixture is leaned for cruise, stopping rich of peak
+        assert_eq!(report.leaning.len(), 1, "{:?}", report.leaning);
+        let leaning = &report.leaning[0];
+        assert!(
+            leaning.start_fuel_flow > 13.0 && leaning.end_fuel_flow < 10.0,
+            "{:?}",
+            leaning
+        );
+        assert!(!leaning.lean_of_peak && leaning.peaks.is_empty());
+
+        let cht = report.cht_spread.as_ref().unwrap();
+        assert!(cht.spread > 20.0 && cht.spread < 60.0, "{:?}", cht);
+        // the logged cooling rate is used, and the cylinders are shock cooled as the power comes off on final
+        let cooling = report.shock_cooling.as_ref().unwrap();
+        assert_eq!(cooling.max_rate, 57.0);
+        assert!(cooling.seconds_over_limit > 0.0);
+        assert_eq!(cooling.time.format("%H:%M").to_string(), "13:44");
+
+        // an engine leaned through peak at steady power, with one cylinder cooling quickly and no logged cooling rate
+        let records = 200;
+        let flow: Vec<f64> = (0..records).map(|i| 14.0 - 6.0 * i as f64 / records as f64).collect();
+        let peaks = [10.0, 10.5, 9.8, 10.2];
+        let mut columns = vec![
+            Column::new(
+                "timestamp".into(),
+                (0..records as i64).map(|i| i * 1_000_000).collect::<Vec<_>>(),
+            )
+            .cast(&DataType::Datetime(TimeUnit::Microseconds, None))?,
+            Column::new("E1 FFlow".into(), flow.clone()),
+            Column::new("E1 RPM".into(), vec![2400.0; records]),
+            Column::new("E1 MAP".into(), vec![23.0; records]),
+        ];
+        for (cylinder, peak) in peaks.iter().enumerate() {
+            let egt: Vec<f64> = flow.iter().map(|f| 1450.0 - 40.0 * (f - peak).powi(2)).collect();
+            columns.push(Column::new(format!("E1 EGT{}", cylinder + 1).into(), egt));
+        }
+        let cht1: Vec<f64> = (0..records).map(|i| 400.0 - i as f64).collect();
+        columns.push(Column::new("E1 CHT1".into(), cht1));
+        for (cylinder, cht) in [(2, 380.0), (3, 390.0), (4, 385.0)] {
+            columns.push(Column::new(format!("E1 CHT{}", cylinder).into(), vec![cht; records]));
+        }
+        let reports = analyze_engines(&DataFrame::new(columns)?)?;
+        let report = &reports[0];
+        assert_eq!(report.leaning.len(), 1);
+        let leaning = &report.leaning[0];
+        assert!(leaning.lean_of_peak);
+        let order: Vec<usize> = leaning.peaks.iter().map(|p| p.cylinder).collect();
+        assert_eq!(order, vec![2, 4, 1, 3]);
+        let spread = leaning.gami_spread.unwrap();
+        assert!((spread - 0.7).abs() < 0.1, "{}", spread);
+
+        let cht = report.cht_spread.as_ref().unwrap();
+        assert_eq!((cht.hottest, cht.coolest), (3, 1));
+        let cooling = report.shock_cooling.as_ref().unwrap();
+        assert!((cooling.max_rate - 60.0).abs() < 1e-6, "{:?}", cooling);
+        assert!(cooling.seconds_over_limit > 150.0, "{:?}", cooling);
+        Ok(())
+    }
+}
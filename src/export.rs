@@ -0,0 +1,265 @@
+//! Export of the cleaned flight data of a log, for analysis in other tools.
+
+use crate::derive;
+use crate::fdr::{FDRConfiguration, FDRWriteError, FlightDataBlock, FlightDataError, FlightDataSource};
+use crate::profile::AircraftProfile;
+use crate::track;
+use polars::prelude::*;
+use std::io::Write;
+
+/// Formats of tables of the cleaned data of a log
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum TableFormat {
+    /// csv with a header row, with each column name followed by its unit in brackets
+    Csv,
+    /// Parquet, with each column name followed by its unit in brackets
+    Parquet,
+    /// A JSON object with the units of each column and an array of records
+    Json,
+}
+
+/// Write every cleaned field of a source as csv with a header row
+pub fn write_csv<W: Write>(source: &dyn FlightDataSource, writer: &mut W) -> Result<(), FDRWriteError> {
+    write_table(source, TableFormat::Csv, false, &AircraftProfile::default(), writer)
+}
+
+/// Write every cleaned field of a source as a table, with the parameters derived by the profile, and optionally with
+/// the columns of [derived_columns] appended
+pub fn write_table<W: Write>(
+    source: &dyn FlightDataSource,
+    format: TableFormat,
+    derived: bool,
+    profile: &AircraftProfile,
+    writer: &mut W,
+) -> Result<(), FDRWriteError> {
+    let mut df = derive::derive_columns(source.data()?, &profile.derive)?;
+    let mut units = converted_columns(source, profile);
+    if derived {
+        let extra = derived_columns(&df)?;
+        units.extend(DERIVED_UNITS.iter().map(|(n, u)| (n.to_string(), u.to_string())));
+        df = df.hstack(extra.get_columns())?;
+    }
+    let unit_of = |name: &str| {
+        if name == "timestamp" {
+            Some("UTC".to_string())
+        } else {
+            units.iter().find(|(n, _)| n == name).map(|(_, u)| u.clone())
+        }
+    };
+
+    match format {
+        TableFormat::Csv | TableFormat::Parquet => {
+            let names: Vec<String> = df
+                .get_column_names()
+                .iter()
+                .map(|name| match unit_of(name.as_str()) {
+                    Some(unit) if !unit.is_empty() => format!("{} [{}]", name, unit),
+                    _ => name.to_string(),
+                })
+                .collect();
+            df.set_column_names(names)?;
+            if format == TableFormat::Csv {
+                CsvWriter::new(writer).include_header(true).finish(&mut df)?;
+            } else {
+                ParquetWriter::new(writer).finish(&mut df)?;
+            }
+        }
+        TableFormat::Json => {
+            let units: serde_json::Map<String, serde_json::Value> = df
+                .get_column_names()
+                .iter()
+                .filter_map(|name| unit_of(name.as_str()).map(|u| (name.to_string(), u.into())))
+                .collect();
+
+            // write timestamps as text, rather than as numbers
+            if let Ok(ts) = df.column("timestamp")?.datetime()?.strftime("%Y-%m-%dT%H:%M:%S%.fZ") {
+                df.with_column(ts)?;
+            }
+            let mut records = Vec::new();
+            JsonWriter::new(&mut records)
+                .with_json_format(JsonFormat::Json)
+                .finish(&mut df)?;
+            let records: serde_json::Value = serde_json::from_slice(&records).map_err(std::io::Error::from)?;
+
+            let document = serde_json::json!({ "units": units, "records": records });
+            serde_json::to_writer(&mut *writer, &document).map_err(std::io::Error::from)?;
+            writeln!(writer)?;
+        }
+    }
+    Ok(())
+}
+
+/// The name and unit of every field logged by a source, followed by the parameters derived by the profile
+pub fn converted_columns(source: &dyn FlightDataSource, profile: &AircraftProfile) -> Vec<(String, String)> {
+    let mut units = source.columns();
+    let fuel_unit = derive::fuel_unit(&units, profile);
+    units.extend(
+        profile
+            .derive
+            .iter()
+            .flat_map(|d| d.columns(fuel_unit))
+            .map(|(n, u)| (n.to_string(), u.to_string())),
+    );
+    units
+}
+
+/// The names and units of the columns added by [derived_columns]
+pub const DERIVED_UNITS: [(&str, &str); 5] = [
+    ("elapsed", "s"),
+    ("distance", "nm"),
+    ("ground_speed", "kt"),
+    ("vertical_speed", "fpm"),
+    ("phase", ""),
+];
+
+/// Columns derived from the track of a dataframe whose first 7 columns are the required fields of a data block:
+/// seconds since the first record, cumulative distance flown, ground and vertical speed, and the phase of flight.
+/// Records without every required field are null.
+pub fn derived_columns(df: &DataFrame) -> Result<DataFrame, FlightDataError> {
+    let height = df.height();
+    let points = track::indexed_frame_points(df)?;
+    let track_points: Vec<track::TrackPoint> = points.iter().map(|(_, p)| *p).collect();
+    let speeds = track::speeds(&track_points);
+    let phases = track::phases(&track_points);
+
+    let mut elapsed = vec![None; height];
+    let mut distance = vec![None; height];
+    let mut ground_speed = vec![None; height];
+    let mut vertical_speed = vec![None; height];
+    let mut phase = vec![None; height];
+    let mut total = 0.0;
+    for (n, (i, p)) in points.iter().enumerate() {
+        if n > 0 {
+            total += track_points[n - 1].distance_to(p);
+        }
+        elapsed[*i] = Some(p.seconds_since(&track_points[0]));
+        distance[*i] = Some(total);
+        ground_speed[*i] = Some(speeds[n].0);
+        vertical_speed[*i] = Some(speeds[n].1);
+        phase[*i] = Some(phases[n].name());
+    }
+
+    DataFrame::new(vec![
+        Column::new(DERIVED_UNITS[0].0.into(), elapsed),
+        Column::new(DERIVED_UNITS[1].0.into(), distance),
+        Column::new(DERIVED_UNITS[2].0.into(), ground_speed),
+        Column::new(DERIVED_UNITS[3].0.into(), vertical_speed),
+        Column::new(DERIVED_UNITS[4].0.into(), phase),
+    ])
+    .map_err(|e| FlightDataError::ReadError(e.to_string()))
+}
+
+/// The auxiliary columns included in track exports when no others are chosen
+pub const DEFAULT_AUX_COLUMNS: [&str; 3] = ["IAS", "VSpd", "E1 RPM"];
+
+/// A data block of a source with extra columns of its cleaned data, for exporters that include fields without a DREF.
+/// The extra columns are kept apart from the block, whose every field other than the required ones has a DREF.
+pub struct ExportBlock {
+    pub block: FlightDataBlock,
+    /// Columns the block does not have, with a row for each of its records
+    pub extra: DataFrame,
+}
+
+impl ExportBlock {
+    /// The names of the columns of the block and its extra columns
+    pub fn column_names(&self) -> Vec<String> {
+        self.block
+            .data
+            .get_column_names()
+            .into_iter()
+            .chain(self.extra.get_column_names())
+            .map(|name| name.to_string())
+            .collect()
+    }
+
+    /// The values of a named column of the block or its extra columns as floats, if either has the column
+    pub fn column_values(&self, name: &str) -> Option<Vec<Option<f64>>> {
+        track::column_values(&self.block, name).or_else(|| {
+            let idx = self.extra.get_column_index(name)?;
+            track::float_values(&self.extra, idx).ok()
+        })
+    }
+}
+
+/// A data block of a source with the extra columns of its converted data it does not have, taken from the same
+/// records as the block. Columns that the source did not log or derive are skipped.
+pub fn export_block(
+    source: &dyn FlightDataSource,
+    config: &FDRConfiguration,
+    columns: &[String],
+) -> Result<ExportBlock, FlightDataError> {
+    let block = source.data_block(config)?;
+    let missing: Vec<&String> = columns.iter().filter(|name| block.data.column(name).is_err()).collect();
+    if missing.is_empty() {
+        return Ok(ExportBlock {
+            block,
+            extra: DataFrame::empty(),
+        });
+    }
+
+    let data = source.converted_data(config)?;
+    if data.height() != block.data.height() {
+        return Err(FlightDataError::ReadError(format!(
+            "the data block has {} records, but the source converted {}",
+            block.data.height(),
+            data.height()
+        )));
+    }
+    let extra: Vec<Column> = missing
+        .into_iter()
+        .filter_map(|name| data.column(name).ok().cloned())
+        .collect();
+    let extra = DataFrame::new(extra).map_err(|e| FlightDataError::ReadError(e.to_string()))?;
+    Ok(ExportBlock { block, extra })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{derive::Derivation, detection::read_avionics_log, AviationLogSourceOption};
+
+    #[test]
+    fn test_write_table_formats() -> Result<(), Box<dyn std::error::Error>> {
+        let path = crate::resource_path("log_231104_084813_KPOU.csv");
+        let source = read_avionics_log(&AviationLogSourceOption::Garmin, &path)?;
+
+        let mut buffer = Vec::new();
+        write_table(
+            source.as_ref(),
+            TableFormat::Csv,
+            true,
+            &AircraftProfile::default(),
+            &mut buffer,
+        )?;
+        let csv = String::from_utf8(buffer)?;
+        let header = csv.lines().next().unwrap();
+        assert!(header.starts_with("timestamp [UTC],Longitude [degrees],Latitude [degrees]"));
+        assert!(header.contains(",IAS [kt],"));
+        assert!(header.ends_with(",ground_speed [kt],vertical_speed [fpm],phase"));
+
+        let mut buffer = Vec::new();
+        write_table(
+            source.as_ref(),
+            TableFormat::Parquet,
+            false,
+            &AircraftProfile::default(),
+            &mut buffer,
+        )?;
+        let df = ParquetReader::new(std::io::Cursor::new(buffer)).finish()?;
+        assert_eq!(df.height(), 3676);
+        assert!(df.column("IAS [kt]").is_ok());
+
+        let mut buffer = Vec::new();
+        let profile = AircraftProfile {
+            derive: vec![Derivation::DensityAltitude],
+            ..Default::default()
+        };
+        write_table(source.as_ref(), TableFormat::Json, false, &profile, &mut buffer)?;
+        let json: serde_json::Value = serde_json::from_slice(&buffer)?;
+        assert_eq!(json["units"]["IAS"], "kt");
+        assert_eq!(json["units"]["DensAlt"], "ft");
+        assert_eq!(json["records"][0]["timestamp"], "2023-11-04T12:48:13Z");
+        assert_eq!(json["records"].as_array().unwrap().len(), 3676);
+        Ok(())
+    }
+}
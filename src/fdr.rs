@@ -1,6 +1,10 @@
+use crate::airports::AirportDatabase;
+use crate::filter::GlitchFilter;
+use crate::profile::{AircraftProfile, UnknownEnumValues};
 use chrono::Utc;
 use core::fmt;
 use polars::prelude::*;
+use std::collections::BTreeMap;
 use std::error::Error;
 use std::io::Write;
 
@@ -41,6 +45,7 @@ pub enum FlightDataError {
     MissingDrefs(Vec<String>),
     UnknownColumn(String),
     InsufficientData,
+    ReadError(String),
 }
 
 impl Error for FlightDataError {}
@@ -57,6 +62,9 @@ impl std::fmt::Display for FlightDataError {
             FlightDataError::InsufficientData => {
                 write!(f, "Insufficient data")
             }
+            FlightDataError::ReadError(err) => {
+                write!(f, "Read error: {}", err)
+            }
         }
     }
 }
@@ -97,6 +105,11 @@ pub trait FlightDataSource {
     /// The timestamp of the flight data, used for the TIME and DATE fields in the FDR file
     fn timestamp(&self) -> Option<chrono::DateTime<Utc>>;
 
+    /// The identifier of the airport the flight departed from, if the source records it
+    fn departure_airport(&self) -> Option<String> {
+        None
+    }
+
     /// The data, and their DREF entries, to be written to the FDR file
     fn data_block(&self, _config: &FDRConfiguration) -> Result<FlightDataBlock, FlightDataError> {
         // default implementation returns an empty data block with minimum required data
@@ -107,6 +120,94 @@ pub trait FlightDataSource {
             data: DataFrame::empty_with_schema(&schema),
         })
     }
+
+    /// Metadata recorded by the source, such as the airframe name or software versions, as key and value pairs
+    fn metadata(&self) -> BTreeMap<String, String> {
+        BTreeMap::new()
+    }
+
+    /// The name and unit of every field logged by the source, in the order they were logged
+    fn columns(&self) -> Vec<(String, String)> {
+        Vec::new()
+    }
+
+    /// All of the cleaned data logged by the source, including fields that do not map to a DREF
+    ///
+    /// The first 7 columns are the required fields of the FDR file, as in a data block. Sources that log fields
+    /// without a DREF must override this: the default implementation can only return the fields of a data block that
+    /// automatically maps every field it can to a DREF, as written to the FDR file.
+    fn data(&self) -> Result<DataFrame, FlightDataError> {
+        let config = FDRConfigurationBuilder::default().auto_drefs(true).build();
+        Ok(self.data_block(&config)?.data)
+    }
+
+    /// All of the data of the source as converted for a data block, including fields that do not map to a DREF
+    ///
+    /// These are the records of [FlightDataSource::data] with the parameters derived by the profile of the
+    /// configuration, and its true heading, glitch filter and enum translation applied, so they line up with the
+    /// records of the data block. The default implementation returns the fields of a data block that automatically
+    /// maps every field it can to a DREF.
+    fn converted_data(&self, config: &FDRConfiguration) -> Result<DataFrame, FlightDataError> {
+        let mut config = config.clone();
+        config.auto_drefs = true;
+        config.strict = false;
+        Ok(self.data_block(&config)?.data)
+    }
+
+    /// The data to be written to the FDR file as a sequence of blocks, which all share the same DREFs
+    ///
+    /// Sources that can read their data incrementally should override this so that writers can process a log without
+    /// holding all of it in memory. The default implementation yields the single block returned by `data_block`.
+    fn data_blocks<'a>(
+        &'a self,
+        config: &'a FDRConfiguration,
+    ) -> Box<dyn Iterator<Item = Result<FlightDataBlock, FlightDataError>> + 'a> {
+        Box::new(std::iter::once(self.data_block(config)))
+    }
+
+    /// The result of checking each record against the checksum logged with it, if the source logs and checks one
+    fn checksum_report(&self) -> Option<ChecksumReport> {
+        None
+    }
+
+    /// The names logged in enum columns that have no number to be written as, if the source logs enums
+    fn unknown_enum_values(&self, _config: &FDRConfiguration) -> Option<UnknownEnumValues> {
+        None
+    }
+}
+
+/// The number of records checked against their checksum, and the lines of the log holding those that did not match
+#[derive(Debug, Clone, Default, PartialEq, serde::Serialize)]
+pub struct ChecksumReport {
+    pub rows: usize,
+    pub invalid_lines: Vec<usize>,
+}
+
+impl ChecksumReport {
+    /// The number of records that did not match their checksum
+    pub fn invalid(&self) -> usize {
+        self.invalid_lines.len()
+    }
+}
+
+impl fmt::Display for ChecksumReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.invalid_lines.is_empty() {
+            return write!(f, "all {} records valid", self.rows);
+        }
+        // a badly corrupt log can fail thousands of records, so only list the first few
+        const LISTED: usize = 10;
+        let lines: Vec<String> = self.invalid_lines.iter().take(LISTED).map(|l| l.to_string()).collect();
+        write!(
+            f,
+            "{} of {} records invalid (line{} {}{})",
+            self.invalid(),
+            self.rows,
+            if self.invalid() == 1 { "" } else { "s" },
+            lines.join(", "),
+            if self.invalid() > LISTED { ", ..." } else { "" }
+        )
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -117,10 +218,14 @@ pub struct FDRConfiguration {
     pub strict: bool,
     pub auto_drefs: bool,
     pub allow_nulls: bool,
+    pub true_heading: bool,
+    pub glitch_filter: GlitchFilter,
+    pub profile: AircraftProfile,
+    pub airports: Option<Arc<AirportDatabase>>,
 }
 
 impl FDRConfiguration {
-    pub fn tail_number(&self, source: &Box<dyn FlightDataSource>) -> String {
+    pub fn tail_number(&self, source: &dyn FlightDataSource) -> String {
         match &self.tail_number_override {
             Some(tail_number) => tail_number.to_string(),
             None => source.tail_number().unwrap_or_else(|| self.defaut_tail_number.clone()),
@@ -145,6 +250,10 @@ pub struct FDRConfigurationBuilder {
     strict: bool,
     auto_drefs: bool,
     allow_nulls: bool,
+    true_heading: bool,
+    glitch_filter: GlitchFilter,
+    profile: AircraftProfile,
+    airports: Option<Arc<AirportDatabase>>,
 }
 
 impl Default for FDRConfigurationBuilder {
@@ -156,6 +265,10 @@ impl Default for FDRConfigurationBuilder {
             strict: false,
             auto_drefs: false,
             allow_nulls: false,
+            true_heading: true,
+            glitch_filter: GlitchFilter::default(),
+            profile: AircraftProfile::default(),
+            airports: None,
         }
     }
 }
@@ -197,6 +310,30 @@ impl FDRConfigurationBuilder {
         self
     }
 
+    /// If set, convert the magnetic heading logged by the source to a true heading, as expected by X-Plane
+    pub fn true_heading(mut self, true_heading: bool) -> Self {
+        self.true_heading = true_heading;
+        self
+    }
+
+    /// The filter that repairs glitches in the position and attitude of the source, off by default
+    pub fn glitch_filter(mut self, glitch_filter: GlitchFilter) -> Self {
+        self.glitch_filter = glitch_filter;
+        self
+    }
+
+    /// The profile of the aircraft that logged the data
+    pub fn profile(mut self, profile: AircraftProfile) -> Self {
+        self.profile = profile;
+        self
+    }
+
+    /// The airport database used to identify where flights departed and arrived
+    pub fn airports(mut self, airports: Option<Arc<AirportDatabase>>) -> Self {
+        self.airports = airports;
+        self
+    }
+
     /// Consume the builder and return a new FDRConfiguration
     pub fn build(self) -> FDRConfiguration {
         FDRConfiguration {
@@ -206,6 +343,10 @@ impl FDRConfigurationBuilder {
             strict: self.strict,
             auto_drefs: self.auto_drefs,
             allow_nulls: self.allow_nulls,
+            true_heading: self.true_heading,
+            glitch_filter: self.glitch_filter,
+            profile: self.profile,
+            airports: self.airports,
         }
     }
 }
@@ -245,6 +386,8 @@ impl From<polars::error::PolarsError> for FDRWriteError {
     }
 }
 
+impl Error for FDRWriteError {}
+
 impl fmt::Display for FDRWriteError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
@@ -263,23 +406,41 @@ impl From<FlightDataError> for FDRWriteError {
 
 impl FDRWriter {
     pub fn write<W: Write>(&self, source: Box<dyn FlightDataSource>, writer: &mut W) -> Result<(), FDRWriteError> {
-        //let mut writer = BufWriter::new(std::fs::File::create(path)?);
+        let tail_number = self.config.tail_number(source.as_ref());
+        self.write_blocks(&tail_number, source.data_blocks(&self.config), writer)
+    }
+
+    /// Write a FDR file from a sequence of data blocks, which must all share the same DREFs
+    pub fn write_blocks<W: Write>(
+        &self,
+        tail_number: &str,
+        mut blocks: impl Iterator<Item = Result<FlightDataBlock, FlightDataError>>,
+        writer: &mut W,
+    ) -> Result<(), FDRWriteError> {
         writeln!(writer, "A")?;
         writeln!(writer, "4")?;
 
         // write the fields
         writeln!(writer, "ACFT,{}", self.config.aircraft_model)?;
-        writeln!(writer, "TAIL,{}", self.config.tail_number(&source))?;
+        writeln!(writer, "TAIL,{}", tail_number)?;
 
-        // write the drefs
-        let data_block = source.data_block(&self.config)?;
+        // write the drefs, which are the same for every block of data
+        let first_block = blocks.next().unwrap_or(Err(FlightDataError::InsufficientData))?;
 
-        for dref in data_block.drefs.iter() {
+        for dref in first_block.drefs.iter() {
             writeln!(writer, "DREF,{},{}", dref.path, dref.scale)?;
         }
 
+        // write the data one block at a time
+        for data_block in std::iter::once(Ok(first_block)).chain(blocks) {
+            self.write_data(data_block?.data, writer)?;
+        }
+        Ok(())
+    }
+
+    /// Write the records of a data block as csv
+    fn write_data<W: Write>(&self, mut df: DataFrame, writer: &mut W) -> Result<(), FDRWriteError> {
         // prepare csv data for writing
-        let mut df = data_block.data;
         if let Ok(ts) = df.column("timestamp")?.datetime()?.strftime("%H:%M:%S") {
             df.with_column(ts)?;
         }
@@ -298,7 +459,10 @@ impl FDRWriter {
 mod tests {
     use std::{io::BufWriter, path::PathBuf};
 
-    use crate::{detection::read_avionics_log, AviationLogSourceOption};
+    use crate::{
+        detection::{read_avionics_log, stream_avionics_log},
+        AviationLogSourceOption,
+    };
 
     use super::*;
 
@@ -334,4 +498,43 @@ mod tests {
         assert_eq!(contents.is_empty(), false); // 22 is temporary, actual value will vary
         Ok(())
     }
+
+    #[test]
+    fn test_fdr_writer_streaming_matches_file() -> Result<(), Box<dyn std::error::Error>> {
+        use crate::derive::Derivation;
+
+        // derived parameters such as the fuel burned continue from one batch to the next
+        let profile = AircraftProfile {
+            derive: vec![
+                Derivation::AngleOfAttack,
+                Derivation::DensityAltitude,
+                Derivation::WindComponents,
+                Derivation::FuelBurned,
+            ],
+            ..Default::default()
+        };
+        let path = PathBuf::from(sample_csv());
+        for profile in [AircraftProfile::default(), profile] {
+            let cfg = FDRConfigurationBuilder::default()
+                .auto_drefs(true)
+                .profile(profile)
+                .build();
+            let writer = FDRWriter::new(cfg);
+
+            let mut expected = Vec::new();
+            writer.write(
+                read_avionics_log(&AviationLogSourceOption::Garmin, &path)?,
+                &mut expected,
+            )?;
+
+            let mut streamed = Vec::new();
+            writer.write(
+                stream_avionics_log(&AviationLogSourceOption::Garmin, &path, 100)?,
+                &mut streamed,
+            )?;
+
+            assert_eq!(String::from_utf8(expected)?, String::from_utf8(streamed)?);
+        }
+        Ok(())
+    }
 }
@@ -0,0 +1,478 @@
+//! Filtering of glitches in the position and attitude of a flight, such as GPS dropouts and AHRS resets, which make
+//! replays lurch.
+//!
+//! Detectors mark single-sample jumps as glitches: a fix that implies an implausible speed from the last good fix, or
+//! an attitude that changes faster than an aircraft can turn. Glitches are repaired by interpolating between the good
+//! samples around them, or removed. A median filter and a Kalman smoother may then smooth what remains.
+//!
+//! The filter runs over every record of a flight at once: a glitch is judged against the good samples before it, the
+//! median window looks ahead as well as back, and the smoother runs backwards from the last record. Filtering the
+//! batches of a log one at a time would restart each of these at every batch, so sources read in batches or followed
+//! as they are written refuse to filter, see [GlitchFilter::filter].
+
+use crate::track;
+use clap::ValueEnum;
+use polars::prelude::*;
+
+/// Consecutive glitches after which the last good sample is abandoned, as the aircraft really has moved there, such
+/// as when the GPS reacquires a fix after a long dropout
+const MAX_GLITCH_RUN: usize = 5;
+
+/// How glitches are handled once detected
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum GlitchRepair {
+    /// Replace a glitch with a value interpolated from the good samples around it
+    #[default]
+    Interpolate,
+    /// Remove the position and attitude of a record with a glitch, so it is not written
+    Remove,
+}
+
+/// Detects, repairs and smooths glitches in the position and attitude columns of a data block
+///
+/// Every stage is off by default, leaving the data unchanged.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct GlitchFilter {
+    max_speed: Option<f64>,
+    max_attitude_rate: Option<f64>,
+    median_window: Option<usize>,
+    kalman_smoothing: Option<f64>,
+    repair: GlitchRepair,
+}
+
+impl GlitchFilter {
+    /// Fixes that imply a ground speed above this many knots from the last good fix are glitches
+    pub fn max_speed(mut self, knots: Option<f64>) -> Self {
+        self.max_speed = knots;
+        self
+    }
+
+    /// Pitch, roll or heading that change faster than this many degrees per second are glitches
+    pub fn max_attitude_rate(mut self, degrees_per_second: Option<f64>) -> Self {
+        self.max_attitude_rate = degrees_per_second;
+        self
+    }
+
+    /// Replace each position and attitude value with the median of a window of this many samples around it
+    pub fn median_window(mut self, samples: Option<usize>) -> Self {
+        self.median_window = samples.filter(|s| *s > 1);
+        self
+    }
+
+    /// Smooth position and attitude with a constant velocity Kalman smoother, whose measurement noise is this many
+    /// times its process noise (in s³). Larger values smooth more.
+    pub fn kalman_smoothing(mut self, ratio: Option<f64>) -> Self {
+        self.kalman_smoothing = ratio.filter(|r| *r > 0.0);
+        self
+    }
+
+    /// How detected glitches are handled
+    pub fn repair(mut self, repair: GlitchRepair) -> Self {
+        self.repair = repair;
+        self
+    }
+
+    /// Whether the filter changes any data
+    pub fn is_enabled(&self) -> bool {
+        self.max_speed.is_some()
+            || self.max_attitude_rate.is_some()
+            || self.median_window.is_some()
+            || self.kalman_smoothing.is_some()
+    }
+
+    /// Filter the position and attitude columns of a dataframe, leaving other columns and the records unchanged.
+    /// The dataframe must hold every record of the flight, as glitches at its start can't be detected and its ends
+    /// are not smoothed with the records beyond them.
+    pub fn filter(&self, data: &DataFrame) -> PolarsResult<DataFrame> {
+        if !self.is_enabled() {
+            return Ok(data.clone());
+        }
+        let mut channels = Channels::from_frame(data)?;
+
+        let glitches = self.glitches(&channels);
+        if glitches.iter().flatten().any(|g| *g) {
+            match self.repair {
+                GlitchRepair::Interpolate => channels.interpolate(&glitches),
+                GlitchRepair::Remove => channels.remove(&glitches),
+            }
+        }
+        if let Some(window) = self.median_window {
+            channels.median(window);
+        }
+        if let Some(ratio) = self.kalman_smoothing {
+            channels.smooth(ratio);
+        }
+        channels.to_frame(data)
+    }
+
+    /// Which samples of each channel, in the order of [CHANNELS], are glitches
+    fn glitches(&self, channels: &Channels) -> Vec<Vec<bool>> {
+        let times = &channels.times;
+        let mut glitches = vec![vec![false; times.len()]; CHANNELS.len()];
+
+        if let Some(max_speed) = self.max_speed {
+            let (lon, lat) = (&channels.values[0], &channels.values[1]);
+            let fixes: Vec<Option<(f64, f64)>> = lat.iter().zip(lon).map(|(lat, lon)| lat.zip(*lon)).collect();
+            let position = detect(times, &fixes, |a, b, dt| {
+                track::distance_nm(a.0, a.1, b.0, b.1) / (dt / 3600.0) > max_speed
+            });
+            glitches[0] = position.clone();
+            glitches[1] = position;
+        }
+
+        if let Some(max_rate) = self.max_attitude_rate {
+            for (idx, channel) in CHANNELS.iter().enumerate().filter(|(_, c)| c.angular) {
+                let wraps = channel.wraps;
+                glitches[idx] = detect(times, &channels.values[idx], |a, b, dt| {
+                    angle_difference(*a, *b, wraps).abs() / dt > max_rate
+                });
+            }
+        }
+        glitches
+    }
+}
+
+/// A column filtered as one signal
+struct Channel {
+    name: &'static str,
+    /// Whether the column is an attitude angle, checked against the maximum attitude rate
+    angular: bool,
+    /// Whether the column is a heading, which wraps around at 360 degrees
+    wraps: bool,
+}
+
+/// The filtered columns: the required fields of a data block after the timestamp
+const CHANNELS: [Channel; 6] = [
+    Channel {
+        name: "Longitude",
+        angular: false,
+        wraps: false,
+    },
+    Channel {
+        name: "Latitude",
+        angular: false,
+        wraps: false,
+    },
+    Channel {
+        name: "AltB",
+        angular: false,
+        wraps: false,
+    },
+    Channel {
+        name: "HDG",
+        angular: true,
+        wraps: true,
+    },
+    Channel {
+        name: "Pitch",
+        angular: true,
+        wraps: false,
+    },
+    Channel {
+        name: "Roll",
+        angular: true,
+        wraps: false,
+    },
+];
+
+/// The difference from angle `a` to angle `b`, the shortest way around when they wrap
+pub(crate) fn angle_difference(a: f64, b: f64, wraps: bool) -> f64 {
+    if wraps {
+        (b - a + 180.0).rem_euclid(360.0) - 180.0
+    } else {
+        b - a
+    }
+}
+
+/// Mark the samples that `implausible` finds too far from the last good sample, given the seconds between them
+fn detect<T>(times: &[Option<f64>], values: &[Option<T>], implausible: impl Fn(&T, &T, f64) -> bool) -> Vec<bool> {
+    let mut glitches = vec![false; values.len()];
+    let mut last_good: Option<(f64, &T)> = None;
+    let mut run = 0;
+
+    for (idx, (time, value)) in times.iter().zip(values).enumerate() {
+        let (Some(time), Some(value)) = (time, value) else {
+            continue;
+        };
+        if let Some((good_time, good)) = last_good {
+            let dt = time - good_time;
+            if dt > 0.0 && implausible(good, value, dt) && run < MAX_GLITCH_RUN {
+                glitches[idx] = true;
+                run += 1;
+                continue;
+            }
+        }
+        last_good = Some((*time, value));
+        run = 0;
+    }
+    glitches
+}
+
+/// The timestamps, in seconds, and values of the filtered columns of a dataframe
+struct Channels {
+    times: Vec<Option<f64>>,
+    values: Vec<Vec<Option<f64>>>,
+}
+
+impl Channels {
+    fn from_frame(data: &DataFrame) -> PolarsResult<Self> {
+        let times = data
+            .column("timestamp")?
+            .datetime()?
+            .cast_time_unit(TimeUnit::Microseconds)
+            .into_iter()
+            .map(|t| t.map(|t| t as f64 / 1e6))
+            .collect();
+        let values = CHANNELS
+            .iter()
+            .map(|c| {
+                Ok(data
+                    .column(c.name)?
+                    .cast(&DataType::Float64)?
+                    .f64()?
+                    .into_iter()
+                    .collect())
+            })
+            .collect::<PolarsResult<_>>()?;
+        Ok(Self { times, values })
+    }
+
+    /// Replace the filtered columns of a dataframe, keeping their types
+    fn to_frame(&self, data: &DataFrame) -> PolarsResult<DataFrame> {
+        let mut data = data.clone();
+        for (channel, values) in CHANNELS.iter().zip(&self.values) {
+            let dtype = data.column(channel.name)?.dtype().clone();
+            let column = Series::new(channel.name.into(), values).cast(&dtype)?;
+            data.replace(channel.name, column)?;
+        }
+        Ok(data)
+    }
+
+    /// Replace glitches with values interpolated in time between the good samples around them
+    fn interpolate(&mut self, glitches: &[Vec<bool>]) {
+        for ((channel, values), glitches) in CHANNELS.iter().zip(self.values.iter_mut()).zip(glitches) {
+            let good = |i: &usize| !glitches[*i] && values[*i].is_some() && self.times[*i].is_some();
+            let repaired: Vec<Option<f64>> = (0..values.len())
+                .map(|i| {
+                    if !glitches[i] {
+                        return values[i];
+                    }
+                    let before = (0..i).rev().find(good);
+                    let after = (i + 1..values.len()).find(good);
+                    match (before, after, self.times[i]) {
+                        (Some(b), Some(a), Some(t)) => {
+                            let (tb, ta) = (self.times[b]?, self.times[a]?);
+                            let (vb, va) = (values[b]?, values[a]?);
+                            let fraction = if ta > tb { (t - tb) / (ta - tb) } else { 0.0 };
+                            let value = vb + angle_difference(vb, va, channel.wraps) * fraction;
+                            Some(if channel.wraps { value.rem_euclid(360.0) } else { value })
+                        }
+                        // hold the nearest good value at the ends of the flight
+                        (Some(b), None, _) => values[b],
+                        (None, Some(a), _) => values[a],
+                        _ => None,
+                    }
+                })
+                .collect();
+            *values = repaired;
+        }
+    }
+
+    /// Remove every filtered value of the records with a glitch in any channel
+    fn remove(&mut self, glitches: &[Vec<bool>]) {
+        for i in 0..self.times.len() {
+            if glitches.iter().any(|g| g[i]) {
+                for values in self.values.iter_mut() {
+                    values[i] = None;
+                }
+            }
+        }
+    }
+
+    /// Replace each value with the median of the values in a window around it
+    fn median(&mut self, window: usize) {
+        let half = window / 2;
+        for (channel, values) in CHANNELS.iter().zip(self.values.iter_mut()) {
+            let filtered: Vec<Option<f64>> = (0..values.len())
+                .map(|i| {
+                    let centre = values[i]?;
+                    // headings are compared by their difference from the centre, so 359 and 1 are neighbours
+                    let mut window: Vec<f64> = values[i.saturating_sub(half)..(i + half + 1).min(values.len())]
+                        .iter()
+                        .flatten()
+                        .map(|v| angle_difference(centre, *v, channel.wraps))
+                        .collect();
+                    window.sort_by(f64::total_cmp);
+                    let value = centre + window[window.len() / 2];
+                    Some(if channel.wraps { value.rem_euclid(360.0) } else { value })
+                })
+                .collect();
+            *values = filtered;
+        }
+    }
+
+    /// Smooth each channel with a constant velocity Rauch-Tung-Striebel smoother
+    fn smooth(&mut self, ratio: f64) {
+        for (channel, values) in CHANNELS.iter().zip(self.values.iter_mut()) {
+            // unwrap headings so that a turn through north is continuous
+            let mut unwrapped = values.clone();
+            if channel.wraps {
+                let mut last: Option<f64> = None;
+                for value in unwrapped.iter_mut().flatten() {
+                    if let Some(previous) = last {
+                        *value = previous + angle_difference(previous, *value, true);
+                    }
+                    last = Some(*value);
+                }
+            }
+            let smoothed = rts_smooth(&self.times, &unwrapped, ratio);
+            *values = smoothed
+                .into_iter()
+                .map(|v| v.map(|v| if channel.wraps { v.rem_euclid(360.0) } else { v }))
+                .collect();
+        }
+    }
+}
+
+/// A 2x2 matrix
+type Matrix = [[f64; 2]; 2];
+
+fn multiply(a: &Matrix, b: &Matrix) -> Matrix {
+    [
+        [
+            a[0][0] * b[0][0] + a[0][1] * b[1][0],
+            a[0][0] * b[0][1] + a[0][1] * b[1][1],
+        ],
+        [
+            a[1][0] * b[0][0] + a[1][1] * b[1][0],
+            a[1][0] * b[0][1] + a[1][1] * b[1][1],
+        ],
+    ]
+}
+
+fn transpose(a: &Matrix) -> Matrix {
+    [[a[0][0], a[1][0]], [a[0][1], a[1][1]]]
+}
+
+fn inverse(a: &Matrix) -> Matrix {
+    let det = a[0][0] * a[1][1] - a[0][1] * a[1][0];
+    [[a[1][1] / det, -a[0][1] / det], [-a[1][0] / det, a[0][0] / det]]
+}
+
+/// Smooth a signal with a constant velocity model, whose measurement noise is `ratio` times its process noise
+///
+/// Missing values are left missing, and samples without a time are skipped.
+fn rts_smooth(times: &[Option<f64>], values: &[Option<f64>], ratio: f64) -> Vec<Option<f64>> {
+    let samples: Vec<(usize, f64, f64)> = times
+        .iter()
+        .zip(values)
+        .enumerate()
+        .filter_map(|(i, (t, v))| Some((i, (*t)?, (*v)?)))
+        .collect();
+    let mut smoothed = values.to_vec();
+    let Some(&(_, _, first)) = samples.first() else {
+        return smoothed;
+    };
+
+    // the forward pass keeps the predicted and filtered state and covariance of every sample
+    let mut predicted: Vec<([f64; 2], Matrix)> = Vec::with_capacity(samples.len());
+    let mut filtered: Vec<([f64; 2], Matrix, Matrix)> = Vec::with_capacity(samples.len());
+    let mut x = [first, 0.0];
+    let mut p: Matrix = [[ratio, 0.0], [0.0, ratio]];
+    let mut last_time = samples[0].1;
+    for &(_, time, value) in &samples {
+        let dt = (time - last_time).max(0.0);
+        last_time = time;
+        let f: Matrix = [[1.0, dt], [0.0, 1.0]];
+        let q: Matrix = [[dt.powi(3) / 3.0, dt.powi(2) / 2.0], [dt.powi(2) / 2.0, dt]];
+        let xp = [x[0] + dt * x[1], x[1]];
+        let fp = multiply(&multiply(&f, &p), &transpose(&f));
+        let pp = [
+            [fp[0][0] + q[0][0], fp[0][1] + q[0][1]],
+            [fp[1][0] + q[1][0], fp[1][1] + q[1][1]],
+        ];
+
+        // update with the measurement of the position
+        let s = pp[0][0] + ratio;
+        let k = [pp[0][0] / s, pp[1][0] / s];
+        let innovation = value - xp[0];
+        x = [xp[0] + k[0] * innovation, xp[1] + k[1] * innovation];
+        p = [
+            [(1.0 - k[0]) * pp[0][0], (1.0 - k[0]) * pp[0][1]],
+            [pp[1][0] - k[1] * pp[0][0], pp[1][1] - k[1] * pp[0][1]],
+        ];
+        predicted.push((xp, pp));
+        filtered.push((x, p, f));
+    }
+
+    // the backward pass corrects each state with the smoothed state after it
+    let mut xs = x;
+    smoothed[samples[samples.len() - 1].0] = Some(xs[0]);
+    for n in (0..samples.len() - 1).rev() {
+        let (xf, pf, _) = filtered[n];
+        let (_, _, f_next) = filtered[n + 1];
+        let (xp, pp) = predicted[n + 1];
+        let c = multiply(&multiply(&pf, &transpose(&f_next)), &inverse(&pp));
+        let d = [xs[0] - xp[0], xs[1] - xp[1]];
+        xs = [
+            xf[0] + c[0][0] * d[0] + c[0][1] * d[1],
+            xf[1] + c[1][0] * d[0] + c[1][1] * d[1],
+        ];
+        smoothed[samples[n].0] = Some(xs[0]);
+    }
+    smoothed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{detection::read_avionics_log, fdr::FDRConfigurationBuilder, AviationLogSourceOption};
+
+    #[test]
+    fn test_glitch_filter_repairs_jumps() -> Result<(), Box<dyn std::error::Error>> {
+        let path = crate::resource_path("log_231104_084813_KPOU.csv");
+        let source = read_avionics_log(&AviationLogSourceOption::Garmin, &path)?;
+        let clean = source.data_block(&FDRConfigurationBuilder::default().build())?.data;
+
+        // a GPS teleport and an AHRS heading flip, part way through the flight
+        let mut latitude: Vec<Option<f64>> = clean.column("Latitude")?.f64()?.into_iter().collect();
+        let mut heading: Vec<Option<f64>> = clean.column("HDG")?.f64()?.into_iter().collect();
+        latitude[2000] = latitude[2000].map(|l| l + 1.0);
+        heading[2500] = heading[2500].map(|h| (h + 180.0) % 360.0);
+        let mut glitchy = clean.clone();
+        glitchy.replace("Latitude", Series::new("Latitude".into(), latitude))?;
+        glitchy.replace("HDG", Series::new("HDG".into(), heading))?;
+
+        let filter = GlitchFilter::default()
+            .max_speed(Some(400.0))
+            .max_attitude_rate(Some(60.0));
+        let glitches = filter.glitches(&Channels::from_frame(&glitchy)?);
+        assert!(glitches[1][2000] && glitches[3][2500]);
+        // the sample flight has no glitches of its own
+        assert_eq!(glitches.iter().flatten().filter(|g| **g).count(), 3);
+
+        let repaired = filter.filter(&glitchy)?;
+        let error = |name: &str, i: usize| -> PolarsResult<f64> {
+            Ok((repaired.column(name)?.f64()?.get(i).unwrap() - clean.column(name)?.f64()?.get(i).unwrap()).abs())
+        };
+        assert!(error("Latitude", 2000)? < 0.001);
+        assert!(error("HDG", 2500)? < 5.0);
+        assert!(repaired.column("Roll")?.equals_missing(clean.column("Roll")?));
+
+        let removed = filter.clone().repair(GlitchRepair::Remove).filter(&glitchy)?;
+        assert_eq!(
+            removed.column("Latitude")?.null_count(),
+            clean.column("Latitude")?.null_count() + 2
+        );
+        assert_eq!(removed.height(), clean.height());
+
+        // smoothing keeps the flight where it was
+        let smoothed = GlitchFilter::default()
+            .median_window(Some(5))
+            .kalman_smoothing(Some(1.0))
+            .filter(&clean)?;
+        let altitude_change = (smoothed.column("AltB")? - clean.column("AltB")?)?;
+        assert!(altitude_change.f64()?.into_iter().flatten().all(|d| d.abs() < 100.0));
+        Ok(())
+    }
+}
@@ -1,23 +1,31 @@
-use crate::fdr::{DataRef, FDRConfiguration, FlightDataBlock, FlightDataError, FlightDataSource};
+use crate::derive::{self, DerivationState};
+use crate::fdr::{ChecksumReport, DataRef, FDRConfiguration, FlightDataBlock, FlightDataError, FlightDataSource};
+use crate::profile::{self, AircraftProfile, EngineType, EnumTable, UnknownEnumValues};
+use crate::wmm;
 use chrono::Utc;
 use polars::prelude::*;
 use std::{
-    collections::HashMap,
+    collections::{BTreeMap, HashMap},
     error::Error,
     fmt::Display,
-    io::{BufRead, Read},
-    path::Path,
+    fs::File,
+    io::{BufRead, BufReader, Read},
+    path::{Path, PathBuf},
 };
 
 pub struct GarminLogFile {
     header: GarminEISLogHeader,
     data: DataFrame,
+    departure_airport: Option<String>,
+    logcheck: Option<ChecksumReport>,
 }
 
 #[derive(Debug)]
 pub enum GarminLogFileParseError {
     IO(std::io::Error),
     Polars(polars::error::PolarsError),
+    /// The row on this line of the log did not match its LogCheck
+    LogCheck(usize),
 }
 
 impl Error for GarminLogFileParseError {}
@@ -27,6 +35,7 @@ impl Display for GarminLogFileParseError {
         match self {
             GarminLogFileParseError::IO(e) => write!(f, "IO error: {}", e),
             GarminLogFileParseError::Polars(e) => write!(f, "Polars error: {}", e),
+            GarminLogFileParseError::LogCheck(line) => write!(f, "The row on line {} failed its LogCheck", line),
         }
     }
 }
@@ -44,11 +53,13 @@ impl From<polars::error::PolarsError> for GarminLogFileParseError {
 }
 
 impl GarminLogFile {
-    pub fn new(path: &Path) -> Result<Self, GarminLogFileParseError> {
-        let log = GarminEISLog::from_csv(&path)?;
+    pub fn new(path: &Path, logcheck: LogCheckMode) -> Result<Self, GarminLogFileParseError> {
+        let log = GarminEISLog::from_csv(&path, logcheck)?;
         Ok(Self {
             header: log.header,
             data: log.data,
+            departure_airport: airport_from_file_name(path),
+            logcheck: log.logcheck,
         })
     }
 }
@@ -62,74 +73,371 @@ impl FlightDataSource for GarminLogFile {
     }
 
     fn timestamp(&self) -> Option<chrono::DateTime<Utc>> {
-        match self.data.column("timestamp") {
-            Ok(Column::Series(s)) => Some(
-                s.datetime()
-                    .unwrap()
-                    .as_datetime_iter()
-                    .next()
-                    .unwrap()
-                    .unwrap()
-                    .and_utc(),
-            ),
-            _ => None,
-        }
+        first_timestamp(&self.data)
+    }
+
+    fn departure_airport(&self) -> Option<String> {
+        self.departure_airport.clone()
+    }
+
+    fn metadata(&self) -> BTreeMap<String, String> {
+        self.header.metadata()
+    }
+
+    fn columns(&self) -> Vec<(String, String)> {
+        self.header.column_units()
+    }
+
+    fn data(&self) -> Result<DataFrame, FlightDataError> {
+        Ok(self.data.clone())
     }
 
     fn data_block(&self, config: &FDRConfiguration) -> Result<FlightDataBlock, FlightDataError> {
-        const MANDATORY_COLS: usize = 7;
+        build_data_block(&self.data, &self.header.column_units(), config)
+    }
 
-        return if !config.auto_drefs {
-            // select the MANDATORY_COLUMNS
-            match self.data.select(
-                self.data
-                    .get_column_names()
-                    .iter()
-                    .take(MANDATORY_COLS)
-                    .map(|s| s.as_str())
-                    .collect::<Vec<&str>>(),
-            ) {
-                Ok(data) => Ok(FlightDataBlock::new(vec![], data)?),
-                Err(_) => Err(FlightDataError::InsufficientData),
-            }
-        } else {
-            let dref_map = build_dref_map();
-            // get the datarefs for the columns we care about, None for entries that dont map
-            let drefs: Vec<Option<DataRef>> = self
-                .data
-                .get_column_names()
-                .iter()
-                .skip(MANDATORY_COLS)
-                .map(|name| dref_map.get(name.as_str()).map_or(None, |dref| Some(dref.clone())))
-                .collect();
+    fn converted_data(&self, config: &FDRConfiguration) -> Result<DataFrame, FlightDataError> {
+        convert_batch(&self.data, config, &mut DerivationState::default())
+    }
 
-            // indices of missing drefs
-            let mising_idx: Vec<usize> = drefs
-                .iter()
-                .enumerate()
-                .filter(|(_, dref)| dref.is_none())
-                .map(|(idx, _)| idx + MANDATORY_COLS)
-                .collect();
-
-            if config.strict && !mising_idx.is_empty() {
-                return Err(FlightDataError::MissingDrefs(
-                    mising_idx
-                        .iter()
-                        .map(|i| self.data.get_column_names()[*i].to_string())
-                        .collect(),
-                ));
+    fn checksum_report(&self) -> Option<ChecksumReport> {
+        self.logcheck.clone()
+    }
+
+    fn unknown_enum_values(&self, config: &FDRConfiguration) -> Option<UnknownEnumValues> {
+        unknown_enum_values(&self.data, config)
+    }
+}
+
+/// A Garmin log file that is read in batches of rows, so that memory use does not depend on the length of the log
+pub struct GarminLogStream {
+    path: PathBuf,
+    header: GarminEISLogHeader,
+    timestamp: Option<chrono::DateTime<Utc>>,
+    batch_size: usize,
+    logcheck: LogCheckMode,
+}
+
+impl GarminLogStream {
+    pub fn new(path: &Path, batch_size: usize, logcheck: LogCheckMode) -> Result<Self, GarminLogFileParseError> {
+        // read the header and the time of the first row together, rather than opening the log again for the time
+        let mut lines = BufReader::new(File::open(path)?).split(b'\n');
+        let header = GarminEISLogHeader::read(&mut lines)?;
+        let timestamp = GarminEISLogBatches::new(lines, &header, 1, logcheck)
+            .filter_map(Result::ok)
+            .find(|df| df.height() > 0)
+            .and_then(|df| first_timestamp(&df));
+        Ok(Self {
+            path: path.to_path_buf(),
+            header,
+            timestamp,
+            batch_size: batch_size.max(1),
+            logcheck,
+        })
+    }
+
+    /// Iterate over the cleaned rows of the log, one batch at a time
+    pub fn batches(&self) -> Result<GarminEISLogBatches, GarminLogFileParseError> {
+        GarminEISLogBatches::open(&self.path, &self.header, self.batch_size, self.logcheck)
+    }
+}
+
+impl FlightDataSource for GarminLogStream {
+    fn tail_number(&self) -> Option<String> {
+        self.header.metadata.get("tail_number").cloned()
+    }
+
+    fn departure_airport(&self) -> Option<String> {
+        airport_from_file_name(&self.path)
+    }
+
+    fn timestamp(&self) -> Option<chrono::DateTime<Utc>> {
+        self.timestamp
+    }
+
+    fn metadata(&self) -> BTreeMap<String, String> {
+        self.header.metadata()
+    }
+
+    fn columns(&self) -> Vec<(String, String)> {
+        self.header.column_units()
+    }
+
+    fn data(&self) -> Result<DataFrame, FlightDataError> {
+        // gather every batch, for consumers that need the whole log at once
+        let mut data: Option<DataFrame> = None;
+        for batch in self.batches().map_err(|e| FlightDataError::ReadError(e.to_string()))? {
+            let batch = batch.map_err(|e| FlightDataError::ReadError(e.to_string()))?;
+            match data.as_mut() {
+                Some(df) => {
+                    df.vstack_mut(&batch)
+                        .map_err(|e| FlightDataError::ReadError(e.to_string()))?;
+                }
+                None => data = Some(batch),
             }
+        }
+        data.ok_or(FlightDataError::InsufficientData)
+    }
+
+    fn data_block(&self, config: &FDRConfiguration) -> Result<FlightDataBlock, FlightDataError> {
+        build_data_block(&self.data()?, &self.header.column_units(), config)
+    }
+
+    fn converted_data(&self, config: &FDRConfiguration) -> Result<DataFrame, FlightDataError> {
+        convert_batch(&self.data()?, config, &mut DerivationState::default())
+    }
+
+    fn data_blocks<'a>(
+        &'a self,
+        config: &'a FDRConfiguration,
+    ) -> Box<dyn Iterator<Item = Result<FlightDataBlock, FlightDataError>> + 'a> {
+        if config.glitch_filter.is_enabled() {
+            return Box::new(std::iter::once(Err(unfilterable())));
+        }
+        let units = self.header.column_units();
+        let mut state = DerivationState::default();
+        match self.batches() {
+            Ok(batches) => Box::new(batches.map(move |batch| match batch {
+                Ok(df) => build_batch_data_block(&df, &units, config, &mut state),
+                Err(e) => Err(FlightDataError::ReadError(e.to_string())),
+            })),
+            Err(e) => Box::new(std::iter::once(Err(FlightDataError::ReadError(e.to_string())))),
+        }
+    }
 
-            // remove missing drefs and missing columns
-            let missing_names: Vec<&str> = mising_idx
+    fn checksum_report(&self) -> Option<ChecksumReport> {
+        LogChecker::new(&self.header, self.logcheck).scan(&self.path).ok()?
+    }
+
+    /// The names without a number in every batch, so the log is not read into memory at once
+    fn unknown_enum_values(&self, config: &FDRConfiguration) -> Option<UnknownEnumValues> {
+        let mut unknown = UnknownEnumValues::default();
+        for batch in self.batches().ok()? {
+            unknown.extend(unknown_enum_values(&batch.ok()?, config)?);
+        }
+        Some(unknown)
+    }
+}
+
+/// A Garmin log file that is still being written, read incrementally as rows are appended
+///
+/// Rows are parsed with the header and schema read when the log was opened, and only complete lines are read, so a
+/// row that is half written is picked up once it is finished.
+pub struct GarminLogFollow {
+    path: PathBuf,
+    header: GarminEISLogHeader,
+    poll_interval: std::time::Duration,
+    idle_timeout: Option<std::time::Duration>,
+    logcheck: LogCheckMode,
+}
+
+impl GarminLogFollow {
+    /// Follow a log, checking for new rows every `poll_interval`, and stopping once no rows have been appended for
+    /// `idle_timeout`, if given
+    pub fn new(
+        path: &Path,
+        poll_interval: std::time::Duration,
+        idle_timeout: Option<std::time::Duration>,
+        logcheck: LogCheckMode,
+    ) -> Result<Self, GarminLogFileParseError> {
+        Ok(Self {
+            path: path.to_path_buf(),
+            header: GarminEISLogHeader::from_csv(path)?,
+            poll_interval,
+            idle_timeout,
+            logcheck,
+        })
+    }
+
+    /// Follow the cleaned rows of the log from its start, as they are appended
+    pub fn tail(&self) -> Result<GarminEISLogTail, GarminLogFileParseError> {
+        GarminEISLogTail::open(
+            &self.path,
+            &self.header,
+            self.poll_interval,
+            self.idle_timeout,
+            self.logcheck,
+        )
+    }
+}
+
+impl FlightDataSource for GarminLogFollow {
+    fn tail_number(&self) -> Option<String> {
+        self.header.metadata.get("tail_number").cloned()
+    }
+
+    fn departure_airport(&self) -> Option<String> {
+        airport_from_file_name(&self.path)
+    }
+
+    fn timestamp(&self) -> Option<chrono::DateTime<Utc>> {
+        GarminEISLogBatches::open(&self.path, &self.header, 1, self.logcheck)
+            .ok()?
+            .filter_map(Result::ok)
+            .find(|df| df.height() > 0)
+            .and_then(|df| first_timestamp(&df))
+    }
+
+    fn metadata(&self) -> BTreeMap<String, String> {
+        self.header.metadata()
+    }
+
+    fn columns(&self) -> Vec<(String, String)> {
+        self.header.column_units()
+    }
+
+    fn data(&self) -> Result<DataFrame, FlightDataError> {
+        // the rows written so far
+        self.tail()
+            .and_then(|mut tail| tail.poll())
+            .map_err(|e| FlightDataError::ReadError(e.to_string()))?
+            .ok_or(FlightDataError::InsufficientData)
+    }
+
+    fn data_block(&self, config: &FDRConfiguration) -> Result<FlightDataBlock, FlightDataError> {
+        build_data_block(&self.data()?, &self.header.column_units(), config)
+    }
+
+    fn converted_data(&self, config: &FDRConfiguration) -> Result<DataFrame, FlightDataError> {
+        convert_batch(&self.data()?, config, &mut DerivationState::default())
+    }
+
+    fn data_blocks<'a>(
+        &'a self,
+        config: &'a FDRConfiguration,
+    ) -> Box<dyn Iterator<Item = Result<FlightDataBlock, FlightDataError>> + 'a> {
+        if config.glitch_filter.is_enabled() {
+            return Box::new(std::iter::once(Err(unfilterable())));
+        }
+        let units = self.header.column_units();
+        let mut state = DerivationState::default();
+        match self.tail() {
+            Ok(tail) => Box::new(tail.map(move |rows| match rows {
+                Ok(df) => build_batch_data_block(&df, &units, config, &mut state),
+                Err(e) => Err(FlightDataError::ReadError(e.to_string())),
+            })),
+            Err(e) => Box::new(std::iter::once(Err(FlightDataError::ReadError(e.to_string())))),
+        }
+    }
+
+    /// The check of the rows written so far
+    fn checksum_report(&self) -> Option<ChecksumReport> {
+        LogChecker::new(&self.header, self.logcheck).scan(&self.path).ok()?
+    }
+}
+
+/// The error of a source read a batch at a time when the glitch filter is on, as it needs every record of the flight
+fn unfilterable() -> FlightDataError {
+    FlightDataError::ReadError("the glitch filter needs every record of the log, not one batch at a time".to_string())
+}
+
+/// The first timestamp in a cleaned dataframe, if any
+fn first_timestamp(data: &DataFrame) -> Option<chrono::DateTime<Utc>> {
+    data.column("timestamp")
+        .ok()?
+        .datetime()
+        .ok()?
+        .as_datetime_iter()
+        .next()
+        .flatten()
+        .map(|ts| ts.and_utc())
+}
+
+/// Build a data block, with the DREFs for its columns, from a cleaned Garmin dataframe
+fn build_data_block(
+    data: &DataFrame,
+    units: &[(String, String)],
+    config: &FDRConfiguration,
+) -> Result<FlightDataBlock, FlightDataError> {
+    build_batch_data_block(data, units, config, &mut DerivationState::default())
+}
+
+/// Convert a batch of the records of a cleaned Garmin dataframe as they are written to a data block, continuing the
+/// derivations of the batch before it: derive the parameters of the profile, correct the heading, filter glitches and
+/// translate enums
+fn convert_batch(
+    data: &DataFrame,
+    config: &FDRConfiguration,
+    state: &mut DerivationState,
+) -> Result<DataFrame, FlightDataError> {
+    let data = derive::derive_batch_columns(data.clone(), &config.profile.derive, state)
+        .map_err(|e| FlightDataError::ReadError(e.to_string()))?;
+    let data = if config.true_heading {
+        true_heading(&data).map_err(|e| FlightDataError::ReadError(e.to_string()))?
+    } else {
+        data
+    };
+    let data = config
+        .glitch_filter
+        .filter(&data)
+        .map_err(|e| FlightDataError::ReadError(e.to_string()))?;
+    // names without a number are written as the default of their table, and reported by unknown_enum_values
+    let (data, _) = translate_columns(data, &config.profile).map_err(|e| FlightDataError::ReadError(e.to_string()))?;
+    Ok(data)
+}
+
+/// Build the data block of a batch of the records of a log, continuing the derivations of the batch before it
+fn build_batch_data_block(
+    data: &DataFrame,
+    units: &[(String, String)],
+    config: &FDRConfiguration,
+    state: &mut DerivationState,
+) -> Result<FlightDataBlock, FlightDataError> {
+    const MANDATORY_COLS: usize = 7;
+
+    let data = convert_batch(data, config, state)?;
+
+    if !config.auto_drefs {
+        // select the MANDATORY_COLUMNS
+        match data.select(
+            data.get_column_names()
                 .iter()
-                .map(|i| self.data.get_column_names()[*i].as_str())
-                .collect();
+                .take(MANDATORY_COLS)
+                .map(|s| s.as_str())
+                .collect::<Vec<&str>>(),
+        ) {
+            Ok(data) => Ok(FlightDataBlock::new(vec![], data)?),
+            Err(_) => Err(FlightDataError::InsufficientData),
+        }
+    } else {
+        let columns: Vec<&str> = data.get_column_names().iter().map(|n| n.as_str()).collect();
+        let dref_map = column_drefs(&columns, units, &config.profile);
+        // get the datarefs for the columns we care about, None for entries that dont map
+        let drefs: Vec<Option<DataRef>> = data
+            .get_column_names()
+            .iter()
+            .skip(MANDATORY_COLS)
+            .map(|name| dref_map.get(name.as_str()).map_or(None, |dref| Some(dref.clone())))
+            .collect();
 
-            let data = self.data.clone().drop_many(missing_names);
-            let drefs = drefs.into_iter().filter_map(|x| x).collect();
-            Ok(FlightDataBlock::new(drefs, data)?)
-        };
+        // indices of missing drefs
+        let mising_idx: Vec<usize> = drefs
+            .iter()
+            .enumerate()
+            .filter(|(_, dref)| dref.is_none())
+            .map(|(idx, _)| idx + MANDATORY_COLS)
+            .collect();
+
+        // columns derived without a dataref are dropped, as strict only concerns the fields of the log
+        let derived: Vec<&str> = config.profile.derive.iter().flat_map(|d| d.names()).collect();
+        let unmapped: Vec<String> = mising_idx
+            .iter()
+            .map(|i| data.get_column_names()[*i].to_string())
+            .filter(|name| !derived.contains(&name.as_str()))
+            .collect();
+        if config.strict && !unmapped.is_empty() {
+            return Err(FlightDataError::MissingDrefs(unmapped));
+        }
+
+        // remove missing drefs and missing columns
+        let missing_names: Vec<&str> = mising_idx
+            .iter()
+            .map(|i| data.get_column_names()[*i].as_str())
+            .collect();
+
+        let data = data.clone().drop_many(missing_names);
+        let drefs = drefs.into_iter().filter_map(|x| x).collect();
+        Ok(FlightDataBlock::new(drefs, data)?)
     }
 }
 
@@ -196,6 +504,47 @@ fn clean_strings(mut df: DataFrame) -> PolarsResult<DataFrame> {
     Ok(df)
 }
 
+/// Convert the magnetic "HDG" column of a dataframe to a true heading
+///
+/// The variation logged in the "MagVar" column (west negative) is used when present, otherwise the variation is
+/// calculated with the World Magnetic Model from the logged position and time.
+fn true_heading(df: &DataFrame) -> PolarsResult<DataFrame> {
+    let f64_column = |name: &str| -> PolarsResult<Vec<Option<f64>>> {
+        match df.column(name) {
+            Ok(c) => Ok(c.cast(&DataType::Float64)?.f64()?.into_iter().collect()),
+            Err(_) => Ok(vec![None; df.height()]),
+        }
+    };
+
+    let heading = f64_column("HDG")?;
+    let magvar = f64_column("MagVar")?;
+    let latitude = f64_column("Latitude")?;
+    let longitude = f64_column("Longitude")?;
+    let altitude = f64_column("AltB")?;
+    let timestamps: Vec<Option<i64>> = df
+        .column("timestamp")?
+        .datetime()?
+        .cast_time_unit(TimeUnit::Microseconds)
+        .into_iter()
+        .collect();
+
+    let wmm = wmm::Declination::default();
+    let corrected: Vec<Option<f64>> = (0..df.height())
+        .map(|i| {
+            let hdg = heading[i]?;
+            let variation = magvar[i].or_else(|| {
+                let ts = chrono::DateTime::<Utc>::from_timestamp_micros(timestamps[i]?)?;
+                Some(wmm.at(latitude[i]?, longitude[i]?, altitude[i].unwrap_or(0.0), &ts))
+            });
+            Some(variation.map_or(hdg, |v| (hdg + v).rem_euclid(360.0)))
+        })
+        .collect();
+
+    let mut df = df.clone();
+    df.replace("HDG", Series::new("HDG".into(), corrected))?;
+    Ok(df)
+}
+
 pub fn clean_dataframe(mut df: DataFrame) -> Result<DataFrame, PolarsError> {
     df = strip_column_names(df)?;
     df = clean_strings(df)?;
@@ -225,19 +574,68 @@ impl GarminEISColumn {
     }
 }
 
+/// The departure airport that Garmin appends to the log file name, e.g. "KPOU" in "log_231104_084813_KPOU.csv"
+pub fn airport_from_file_name(path: &Path) -> Option<String> {
+    let stem = path.file_stem()?.to_str()?;
+    let (_, airport) = stem.rsplit_once('_')?;
+    let valid = (3..=4).contains(&airport.len()) && airport.chars().all(|c| c.is_ascii_alphanumeric());
+    // the time of day is also in the name, don't mistake it for an airport
+    (valid && !airport.chars().all(|c| c.is_ascii_digit())).then(|| airport.to_uppercase())
+}
+
+/// The error of a log too short to hold the three lines of its header, such as a csv file that isn't a Garmin log
+fn missing_header_line(message: &str) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, message)
+}
+
 struct GarminEISLogHeader {
     pub metadata: HashMap<String, String>,
     pub columns: Vec<GarminEISColumn>,
+    /// The line of column names, which the data rows are parsed with
+    pub names_line: String,
 }
 
 struct GarminEISLog {
     pub header: GarminEISLogHeader,
     pub data: DataFrame,
+    pub logcheck: Option<ChecksumReport>,
 }
 
 impl GarminEISLogHeader {
+    /// The metadata entries of the header, sorted by key
+    pub fn metadata(&self) -> BTreeMap<String, String> {
+        self.metadata.iter().map(|(k, v)| (k.clone(), v.clone())).collect()
+    }
+
+    /// The cleaned name and unit of every column
+    pub fn column_units(&self) -> Vec<(String, String)> {
+        self.columns
+            .iter()
+            .map(|c| (c.name().to_string(), c.unit().to_string()))
+            .collect()
+    }
+
     pub fn from_csv(path: &std::path::Path) -> Result<Self, std::io::Error> {
         let file = std::fs::File::open(path)?;
+        Self::read(&mut BufReader::new(file).split(b'\n'))
+    }
+
+    /// Read the header from the first lines of a log, leaving the data rows to be read
+    ///
+    /// The lines are decoded lossily, as a corrupt log may hold bytes that are not UTF-8.
+    fn read(lines: &mut impl Iterator<Item = std::io::Result<Vec<u8>>>) -> Result<Self, std::io::Error> {
+        let mut next_line = |missing: &str| -> std::io::Result<String> {
+            let line = lines.next().ok_or_else(|| missing_header_line(missing))??;
+            Ok(String::from_utf8_lossy(&line).trim_end().to_string())
+        };
+        let metadata_line = next_line("No lines in file")?;
+        let units_line = next_line("No units line in file")?;
+        let names_line = next_line("No names line in file")?;
+        Ok(Self::parse(&metadata_line, &units_line, &names_line))
+    }
+
+    /// Parse the metadata, units and names lines of a header
+    fn parse(metadata_line: &str, units_line: &str, names_line: &str) -> Self {
         let mut metadata = HashMap::new();
         let mut columns = Vec::new();
 
@@ -246,13 +644,7 @@ impl GarminEISLogHeader {
         // row 2 starts with a comment char and has column units separated by commas
         // row 3 lists the column names separated by commas
 
-        let mut lines = std::io::BufReader::new(file).lines();
-        let metadata_line = lines.next().expect("No lines in file")?;
-
-        let units_line = lines.next().expect("No units line in file")?;
         let units = units_line.trim_start_matches('#').split(",");
-
-        let names_line = lines.next().expect("No names line in file")?;
         let names = names_line.split(',');
 
         for entry in metadata_line.trim_start_matches('#').split(',') {
@@ -271,7 +663,11 @@ impl GarminEISLogHeader {
             });
         }
 
-        Ok(Self { metadata, columns })
+        Self {
+            metadata,
+            columns,
+            names_line: names_line.to_string(),
+        }
     }
 
     pub fn build_schema(&self) -> Schema {
@@ -325,7 +721,7 @@ impl GarminEISLogHeader {
 }
 
 impl GarminEISLog {
-    fn read_bytes(path: &std::path::Path) -> std::io::Result<std::io::Cursor<Vec<u8>>> {
+    fn read_bytes(path: &std::path::Path) -> std::io::Result<Vec<u8>> {
         let mut file = std::fs::File::open(path)?;
         let mut buffer = Vec::new();
         file.read_to_end(&mut buffer)?;
@@ -333,29 +729,349 @@ impl GarminEISLog {
         while buffer.last() == Some(&0) {
             buffer.pop();
         }
-        Ok(std::io::Cursor::new(buffer))
+        Ok(buffer)
     }
 
-    fn read_df(path: &std::path::Path, schema: &Schema) -> PolarsResult<LazyFrame> {
-        // read the file bytes into a buffer
-        const SKIP_ROWS: usize = 2;
-        // read into dataframe
+    /// Parse the rows in a buffer, whose first line holds the column names, into a cleaned dataframe, flagging
+    /// whether each row passed its LogCheck when given
+    fn read_rows(buffer: Vec<u8>, schema: &Schema, valid: Option<Vec<bool>>) -> PolarsResult<DataFrame> {
         let reader = CsvReadOptions::default()
             .with_has_header(true)
             .with_schema(Some(Arc::new(schema.clone())))
-            .with_skip_rows(SKIP_ROWS)
-            .into_reader_with_file_handle(Self::read_bytes(path)?);
-        Ok(reader.finish()?.lazy())
+            .into_reader_with_file_handle(std::io::Cursor::new(buffer));
+        let mut data = reader.finish()?;
+        if let Some(valid) = valid {
+            data.with_column(Series::new(LOGCHECK_VALID_COLUMN.into(), valid))?;
+        }
+        Self::finish(data.lazy())
     }
 
-    pub fn from_csv(path: &std::path::Path) -> PolarsResult<Self> {
-        let header = GarminEISLogHeader::from_csv(path)?;
-        let schema = header.build_schema();
-        let data = Self::read_df(path, &schema)?;
+    /// Build the timestamp and clean up a freshly read dataframe
+    fn finish(data: LazyFrame) -> PolarsResult<DataFrame> {
         let data = parse_datetime(data, "Lcl Date", "Lcl Time", "UTCOfst", "timestamp", true)?;
         let data = data.collect()?;
-        let data = clean_dataframe(data)?;
-        Ok(Self { header, data })
+        clean_dataframe(data)
+    }
+
+    pub fn from_csv(path: &std::path::Path, logcheck: LogCheckMode) -> Result<Self, GarminLogFileParseError> {
+        // corrupt bytes are replaced rather than failing the whole log, the rows holding them fail their LogCheck
+        let buffer = Self::read_bytes(path)?;
+        let text = String::from_utf8_lossy(&buffer);
+        let mut lines = text.lines();
+        // the header is on the first three lines, the data rows follow
+        let mut next_line = |missing: &str| lines.next().ok_or_else(|| missing_header_line(missing));
+        let header = GarminEISLogHeader::parse(
+            next_line("No lines in file")?,
+            next_line("No units line in file")?,
+            next_line("No names line in file")?,
+        );
+        let schema = header.build_schema();
+
+        let mut checker = LogChecker::new(&header, logcheck);
+        let mut rows = checker.rows(&header.names_line);
+        for (idx, line) in lines.enumerate() {
+            checker.push(&mut rows, idx + 4, line)?;
+        }
+        let data = rows.parse(&schema)?;
+        Ok(Self {
+            header,
+            data,
+            logcheck: checker.report(),
+        })
+    }
+}
+
+/// The column holding the checksum of each row of a Garmin log
+const LOGCHECK_COLUMN: &str = "LogCheck";
+
+/// The column added when flagging rows, true when the row matched its LogCheck
+pub const LOGCHECK_VALID_COLUMN: &str = "LogCheckValid";
+
+/// What to do with rows of a Garmin log that do not match their LogCheck, as written by a corrupt SD card
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum LogCheckMode {
+    /// Do not check rows
+    Off,
+    /// Drop rows that fail their check
+    #[default]
+    Drop,
+    /// Keep every row, adding a LogCheckValid column
+    Flag,
+    /// Stop reading the log at the first row that fails its check
+    Fail,
+}
+
+/// The CRC-16/CCITT-FALSE checksum that Garmin writes at the end of each row
+pub fn crc16(bytes: &[u8]) -> u16 {
+    bytes.iter().fold(0xffff, |crc, byte| {
+        (0..8).fold(crc ^ ((*byte as u16) << 8), |crc, _| {
+            if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x1021
+            } else {
+                crc << 1
+            }
+        })
+    })
+}
+
+/// Whether the LogCheck at the end of a row matches the checksum of the text before it, including the separator
+pub fn logcheck_matches(row: &str) -> bool {
+    let row = row.trim_end();
+    let check = row.rsplit_once(',').map_or("", |(_, check)| check.trim_start());
+    let text = &row[..row.len() - check.len()];
+    check.len() == 4 && u16::from_str_radix(check, 16).is_ok_and(|check| check == crc16(text.as_bytes()))
+}
+
+/// The text of a row of a log, or None for a blank line
+fn log_row(line: &str) -> Option<&str> {
+    // the end of a log may be padded with null bytes
+    let row = line.trim_end().trim_end_matches('\0');
+    (!row.trim().is_empty()).then_some(row)
+}
+
+/// Rows of a log gathered to be parsed together
+struct LogRows {
+    buffer: Vec<u8>,
+    rows: usize,
+    valid: Option<Vec<bool>>,
+}
+
+impl LogRows {
+    fn len(&self) -> usize {
+        self.rows
+    }
+
+    fn is_empty(&self) -> bool {
+        self.rows == 0
+    }
+
+    fn parse(self, schema: &Schema) -> PolarsResult<DataFrame> {
+        GarminEISLog::read_rows(self.buffer, schema, self.valid)
+    }
+}
+
+/// Checks rows against their LogCheck as they are read, keeping the lines of those that fail
+struct LogChecker {
+    mode: LogCheckMode,
+    report: ChecksumReport,
+}
+
+impl LogChecker {
+    fn new(header: &GarminEISLogHeader, mode: LogCheckMode) -> Self {
+        // logs without a LogCheck, such as those recorded from X-Plane by earlier versions, cannot be checked
+        let logged = header.columns.last().is_some_and(|c| c.name() == LOGCHECK_COLUMN);
+        Self {
+            mode: if logged { mode } else { LogCheckMode::Off },
+            report: ChecksumReport::default(),
+        }
+    }
+
+    /// An empty set of rows, with the column names on the first line
+    fn rows(&self, names_line: &str) -> LogRows {
+        LogRows {
+            buffer: format!("{}\n", names_line).into_bytes(),
+            rows: 0,
+            valid: (self.mode == LogCheckMode::Flag).then(Vec::new),
+        }
+    }
+
+    /// Check a row, recording it in the report, and return whether it matched its LogCheck
+    fn check(&mut self, line_number: usize, row: &str) -> bool {
+        if self.mode == LogCheckMode::Off {
+            return true;
+        }
+        self.report.rows += 1;
+        let valid = logcheck_matches(row);
+        if !valid {
+            self.report.invalid_lines.push(line_number);
+        }
+        valid
+    }
+
+    /// Check the row on a line of the log, and add it to the rows to parse unless it is blank or dropped
+    fn push(&mut self, rows: &mut LogRows, line_number: usize, line: &str) -> Result<(), GarminLogFileParseError> {
+        let Some(row) = log_row(line) else {
+            return Ok(());
+        };
+        let valid = self.check(line_number, row);
+        match self.mode {
+            LogCheckMode::Drop if !valid => return Ok(()),
+            LogCheckMode::Fail if !valid => return Err(GarminLogFileParseError::LogCheck(line_number)),
+            _ => {}
+        }
+        rows.buffer.extend_from_slice(row.as_bytes());
+        rows.buffer.push(b'\n');
+        rows.rows += 1;
+        if let Some(flags) = rows.valid.as_mut() {
+            flags.push(valid);
+        }
+        Ok(())
+    }
+
+    /// Check every row of a log file without parsing it
+    fn scan(mut self, path: &Path) -> std::io::Result<Option<ChecksumReport>> {
+        if self.mode == LogCheckMode::Off {
+            return Ok(None);
+        }
+        for (idx, line) in BufReader::new(File::open(path)?).split(b'\n').enumerate().skip(3) {
+            if let Some(row) = log_row(&String::from_utf8_lossy(&line?)) {
+                self.check(idx + 1, row);
+            }
+        }
+        Ok(self.report())
+    }
+
+    /// The rows checked so far, or None when rows are not checked
+    fn report(&self) -> Option<ChecksumReport> {
+        (self.mode != LogCheckMode::Off).then(|| self.report.clone())
+    }
+}
+
+/// Iterator over the rows of a Garmin EIS log in cleaned batches, holding at most one batch in memory
+pub struct GarminEISLogBatches {
+    names_line: String,
+    schema: Schema,
+    lines: std::io::Split<BufReader<File>>,
+    line_number: usize,
+    batch_size: usize,
+    checker: LogChecker,
+}
+
+impl GarminEISLogBatches {
+    fn open(
+        path: &Path,
+        header: &GarminEISLogHeader,
+        batch_size: usize,
+        logcheck: LogCheckMode,
+    ) -> Result<Self, GarminLogFileParseError> {
+        let mut lines = BufReader::new(File::open(path)?).split(b'\n');
+        // skip the header, the data rows follow
+        lines.nth(2).transpose()?;
+        Ok(Self::new(lines, header, batch_size, logcheck))
+    }
+
+    /// Batches of the data rows that follow a header already read from the lines
+    fn new(
+        lines: std::io::Split<BufReader<File>>,
+        header: &GarminEISLogHeader,
+        batch_size: usize,
+        logcheck: LogCheckMode,
+    ) -> Self {
+        Self {
+            names_line: header.names_line.clone(),
+            schema: header.build_schema(),
+            lines,
+            line_number: 3,
+            batch_size,
+            checker: LogChecker::new(header, logcheck),
+        }
+    }
+}
+
+/// Iterator over the rows appended to a Garmin EIS log, which waits for more rows until the log is idle
+pub struct GarminEISLogTail {
+    names_line: String,
+    schema: Schema,
+    reader: BufReader<File>,
+    line_number: usize,
+    checker: LogChecker,
+    // the start of a line that has not been completely written yet
+    partial: Vec<u8>,
+    poll_interval: std::time::Duration,
+    idle_timeout: Option<std::time::Duration>,
+    last_rows: std::time::Instant,
+}
+
+impl GarminEISLogTail {
+    fn open(
+        path: &Path,
+        header: &GarminEISLogHeader,
+        poll_interval: std::time::Duration,
+        idle_timeout: Option<std::time::Duration>,
+        logcheck: LogCheckMode,
+    ) -> Result<Self, GarminLogFileParseError> {
+        let mut reader = BufReader::new(File::open(path)?);
+        // skip the header, the data rows follow
+        for _ in 0..3 {
+            reader.read_until(b'\n', &mut Vec::new())?;
+        }
+        Ok(Self {
+            names_line: header.names_line.clone(),
+            schema: header.build_schema(),
+            reader,
+            line_number: 3,
+            checker: LogChecker::new(header, logcheck),
+            partial: Vec::new(),
+            poll_interval,
+            idle_timeout,
+            last_rows: std::time::Instant::now(),
+        })
+    }
+
+    /// Read the complete rows that have been appended since the last poll, without waiting for more
+    pub fn poll(&mut self) -> Result<Option<DataFrame>, GarminLogFileParseError> {
+        let mut rows = self.checker.rows(&self.names_line);
+
+        loop {
+            if self.reader.read_until(b'\n', &mut self.partial)? == 0 || !self.partial.ends_with(b"\n") {
+                // the end of the file, possibly part way through a line that is still being written
+                break;
+            }
+            self.line_number += 1;
+            self.checker
+                .push(&mut rows, self.line_number, &String::from_utf8_lossy(&self.partial))?;
+            self.partial.clear();
+        }
+
+        if rows.is_empty() {
+            return Ok(None);
+        }
+        self.last_rows = std::time::Instant::now();
+        Ok(Some(rows.parse(&self.schema)?))
+    }
+}
+
+impl Iterator for GarminEISLogTail {
+    type Item = Result<DataFrame, GarminLogFileParseError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            match self.poll() {
+                Ok(Some(df)) => return Some(Ok(df)),
+                Ok(None) if self.idle_timeout.is_some_and(|t| self.last_rows.elapsed() >= t) => return None,
+                Ok(None) => std::thread::sleep(self.poll_interval),
+                Err(e) => return Some(Err(e)),
+            }
+        }
+    }
+}
+
+impl Iterator for GarminEISLogBatches {
+    type Item = Result<DataFrame, GarminLogFileParseError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut rows = self.checker.rows(&self.names_line);
+
+        while rows.len() < self.batch_size {
+            match self.lines.next() {
+                Some(Ok(line)) => {
+                    self.line_number += 1;
+                    if let Err(e) = self
+                        .checker
+                        .push(&mut rows, self.line_number, &String::from_utf8_lossy(&line))
+                    {
+                        return Some(Err(e));
+                    }
+                }
+                Some(Err(e)) => return Some(Err(e.into())),
+                None => break,
+            }
+        }
+
+        if rows.is_empty() {
+            return None;
+        }
+        Some(rows.parse(&self.schema).map_err(GarminLogFileParseError::from))
     }
 }
 
@@ -398,6 +1114,450 @@ fn parse_datetime(
     Ok(lazy)
 }
 
+/// X-Plane's HSI sources: 0 is NAV1, 1 is NAV2 and 2 is GPS
+const HSI_SOURCES: [(&str, f64); 8] = [
+    ("NAV1", 0.0),
+    ("LOC1", 0.0),
+    ("NAV2", 1.0),
+    ("LOC2", 1.0),
+    ("GPS", 2.0),
+    ("GPS1", 2.0),
+    ("GPS2", 2.0),
+    ("FMS", 2.0),
+];
+
+/// The Garmin autopilot lateral modes as X-Plane's heading modes: 1 heading, 2 wing leveler, 6 localizer or VOR, 13
+/// GPS steering and 14 roll hold
+const ROLL_MODES: [(&str, f64); 9] = [
+    ("NONE", 0.0),
+    ("HDG", 1.0),
+    ("LVL", 2.0),
+    ("VOR", 6.0),
+    ("LOC", 6.0),
+    ("BC", 6.0),
+    ("VAPP", 6.0),
+    ("GPS", 13.0),
+    ("ROL", 14.0),
+];
+
+/// The Garmin autopilot vertical modes as X-Plane's altitude modes: 4 vertical speed, 5 flight level change, 6
+/// altitude hold, 8 glideslope, 9 vertical path and 17 pitch hold
+const PITCH_MODES: [(&str, f64); 9] = [
+    ("NONE", 0.0),
+    ("VS", 4.0),
+    ("FLC", 5.0),
+    ("ALT", 6.0),
+    ("ALTS", 6.0),
+    ("GS", 8.0),
+    ("GP", 8.0),
+    ("VPTH", 9.0),
+    ("PIT", 17.0),
+];
+
+/// The names Garmin logs in the enum columns mapped to datarefs, with the number written for each
+const ENUM_COLUMNS: [(&str, &[(&str, f64)]); 3] =
+    [("HSIS", &HSI_SOURCES), ("RollM", &ROLL_MODES), ("PitchM", &PITCH_MODES)];
+
+/// The translations of the Garmin enum columns mapped to datarefs
+pub fn enum_tables() -> BTreeMap<String, EnumTable> {
+    ENUM_COLUMNS
+        .iter()
+        .map(|(column, values)| (column.to_string(), EnumTable::new(values)))
+        .collect()
+}
+
+/// The name Garmin usually logs in an enum column for a number, the first of those that translate to it
+pub fn enum_name(column: &str, value: f64) -> Option<&'static str> {
+    let (_, values) = ENUM_COLUMNS.iter().find(|(c, _)| *c == column)?;
+    values
+        .iter()
+        .find(|(_, v)| (v - value).abs() < 0.5)
+        .map(|(name, _)| *name)
+}
+
+/// The number added to the values of a Garmin column to give the value its dataref expects
+pub fn column_offset(column: &str) -> Option<f64> {
+    match column {
+        // normal acceleration is logged as the difference from 1 G
+        "NormAc" => Some(1.0),
+        _ => None,
+    }
+}
+
+/// Translate the enum columns of a dataframe with the Garmin tables and those of a profile, and offset the columns
+/// that have a [column_offset], into the numbers their datarefs expect
+fn translate_columns(df: DataFrame, profile: &AircraftProfile) -> PolarsResult<(DataFrame, UnknownEnumValues)> {
+    let (mut df, unknown) = profile::translate_enums(df, &profile.enum_tables(enum_tables()))?;
+    let names: Vec<String> = df.get_column_names().iter().map(|n| n.to_string()).collect();
+    for name in names {
+        let Some(offset) = column_offset(&name) else {
+            continue;
+        };
+        let column = df.column(&name)?;
+        if !column.dtype().is_numeric() {
+            continue;
+        }
+        let values: Vec<Option<f64>> = column
+            .cast(&DataType::Float64)?
+            .f64()?
+            .into_iter()
+            .map(|v| v.map(|v| v + offset))
+            .collect();
+        df.replace(&name, Series::new(name.as_str().into(), values))?;
+    }
+    Ok((df, unknown))
+}
+
+/// The names logged in the enum columns of a dataframe that have no number in the Garmin tables or the profile
+fn unknown_enum_values(data: &DataFrame, config: &FDRConfiguration) -> Option<UnknownEnumValues> {
+    let tables = config.profile.enum_tables(enum_tables());
+    let columns: Vec<&str> = tables
+        .keys()
+        .map(|c| c.as_str())
+        .filter(|c| data.column(c).is_ok())
+        .collect();
+    let (_, unknown) = profile::translate_enums(data.select(columns).ok()?, &tables).ok()?;
+    Some(unknown)
+}
+
+/// The mapping from Garmin column names to X-Plane DREFs used when automatically mapping fields, sorted by column
+pub fn dref_map(profile: &AircraftProfile) -> Vec<(String, DataRef)> {
+    let mut map: Vec<(String, DataRef)> = build_dref_map()
+        .into_iter()
+        .map(|(name, dref)| (name.to_string(), dref))
+        .collect();
+    let cylinders = profile.cylinders.unwrap_or(DEFAULT_CYLINDERS);
+    for engine in 1..=profile.engines.unwrap_or(1) {
+        for (pattern, path, scale) in engine_rules(profile.engine_type.unwrap_or_default()) {
+            let cylinder_numbers = if pattern.contains("{m}") {
+                (1..=cylinders).map(Some).collect()
+            } else {
+                vec![None]
+            };
+            for cylinder in cylinder_numbers {
+                let column = pattern
+                    .replace("{n}", &engine.to_string())
+                    .replace("{m}", &cylinder.unwrap_or_default().to_string());
+                map.push((column, engine_dref(path, scale.listed(), engine, cylinder, cylinders)));
+            }
+        }
+    }
+    let derived: Vec<&str> = profile.derive.iter().flat_map(|d| d.names()).collect();
+    map.extend(
+        derive::derived_drefs(fuel_burned_scale(
+            engine_rules(profile.engine_type.unwrap_or_default()),
+            None,
+        ))
+        .into_iter()
+        .filter(|(name, _)| derived.contains(name))
+        .map(|(name, dref)| (name.to_string(), dref)),
+    );
+    for (name, table) in &profile.enums {
+        if let Some(path) = &table.dref {
+            map.retain(|(column, _)| column != name);
+            map.push((name.clone(), DataRef::new(path.clone())));
+        }
+    }
+    map.sort_by(|a, b| a.0.cmp(&b.0));
+    map
+}
+
+/// The number of cylinders of each engine listed by [dref_map] when the profile doesn't give it
+const DEFAULT_CYLINDERS: usize = 4;
+
+/// Kilograms in a pound
+const KG_PER_LB: f64 = 0.45359237;
+
+/// Pounds in a gallon of avgas
+const AVGAS_LB_PER_GAL: f64 = 6.0;
+
+/// Pounds in a gallon of jet fuel
+const JET_FUEL_LB_PER_GAL: f64 = 6.7;
+
+/// How the values of an engine column are scaled to the unit of its dataref
+#[derive(Debug, Clone, Copy)]
+enum RuleScale {
+    /// The same scale in every log
+    Fixed(f64),
+    /// The scale of the unit the column is logged in, as the log header gives it. Columns logged in any other unit
+    /// are not written, and [dref_map] lists the scale of the first.
+    Unit(&'static [(&'static str, f64)]),
+}
+
+impl RuleScale {
+    /// The scale of a column logged in a unit, if it is one the rule knows
+    fn of(&self, unit: Option<&str>) -> Option<f64> {
+        match self {
+            RuleScale::Fixed(scale) => Some(*scale),
+            RuleScale::Unit(units) => {
+                let unit = unit?.trim();
+                units
+                    .iter()
+                    .find(|(name, _)| name.eq_ignore_ascii_case(unit))
+                    .map(|(_, scale)| *scale)
+            }
+        }
+    }
+
+    /// The scale listed for the rule, when no log gives the unit
+    fn listed(&self) -> f64 {
+        match self {
+            RuleScale::Fixed(scale) => *scale,
+            RuleScale::Unit(units) => units[0].1,
+        }
+    }
+}
+
+/// The units Garmin logs the fuel flow of a piston engine in, as kg/s: gallons of avgas or pounds an hour
+const PISTON_FUEL_FLOW_UNITS: [(&str, f64); 3] = [
+    ("gph", AVGAS_LB_PER_GAL * KG_PER_LB / 3600.0),
+    ("pph", KG_PER_LB / 3600.0),
+    ("lbs/hr", KG_PER_LB / 3600.0),
+];
+
+/// The units Garmin logs the fuel flow of a turbine engine in, as kg/s: pounds or gallons of jet fuel an hour
+const TURBINE_FUEL_FLOW_UNITS: [(&str, f64); 3] = [
+    ("pph", KG_PER_LB / 3600.0),
+    ("lbs/hr", KG_PER_LB / 3600.0),
+    ("gph", JET_FUEL_LB_PER_GAL * KG_PER_LB / 3600.0),
+];
+
+/// The units Garmin logs the torque of a turbine engine in, as N m. Torque logged as a percent of the maximum is not
+/// written, as the maximum of the engine is not known.
+const TORQUE_UNITS: [(&str, f64); 2] = [("ft-lb", 1.35581795), ("ft-lbs", 1.35581795)];
+
+/// Families of piston engine columns, where `{n}` is the number of the engine and `{m}` of the cylinder, with the
+/// dataref array they are written to and its scale. Engine columns are written at the index of their engine, and
+/// cylinder columns at the index of their cylinder after the cylinders of the engines before.
+const PISTON_RULES: [(&str, &str, RuleScale); 9] = [
+    (
+        "E{n} FFlow",
+        "sim/cockpit2/engine/indicators/fuel_flow_kg_sec",
+        RuleScale::Unit(&PISTON_FUEL_FLOW_UNITS),
+    ),
+    (
+        "E{n} FPres",
+        "sim/cockpit2/engine/indicators/fuel_pressure_psi",
+        RuleScale::Fixed(1.0),
+    ),
+    (
+        "E{n} OilT",
+        "sim/cockpit2/engine/indicators/oil_temperature_deg_C",
+        RuleScale::Fixed(1.0),
+    ),
+    (
+        "E{n} OilP",
+        "sim/cockpit2/engine/indicators/oil_pressure_psi",
+        RuleScale::Fixed(1.0),
+    ),
+    (
+        "E{n} MAP",
+        "sim/cockpit2/engine/indicators/MPR_in_hg",
+        RuleScale::Fixed(1.0),
+    ),
+    (
+        "E{n} RPM",
+        "sim/cockpit2/engine/indicators/engine_speed_rpm",
+        RuleScale::Fixed(1.0),
+    ),
+    (
+        "E{n} %Pwr",
+        "sim/cockpit2/engine/indicators/N1_percent",
+        RuleScale::Fixed(1.0),
+    ),
+    (
+        "E{n} CHT{m}",
+        "sim/cockpit2/engine/indicators/CHT_CYL_deg_F",
+        RuleScale::Fixed(1.0),
+    ),
+    (
+        "E{n} EGT{m}",
+        "sim/cockpit2/engine/indicators/EGT_CYL_deg_F",
+        RuleScale::Fixed(1.0),
+    ),
+];
+
+/// Families of turbine engine columns, as [PISTON_RULES]. Garmin logs the gas generator speed of a turboprop as `NG`,
+/// which X-Plane calls N1, and its propeller speed as `NP`.
+const TURBINE_RULES: [(&str, &str, RuleScale); 10] = [
+    (
+        "E{n} FFlow",
+        "sim/cockpit2/engine/indicators/fuel_flow_kg_sec",
+        RuleScale::Unit(&TURBINE_FUEL_FLOW_UNITS),
+    ),
+    (
+        "E{n} FPres",
+        "sim/cockpit2/engine/indicators/fuel_pressure_psi",
+        RuleScale::Fixed(1.0),
+    ),
+    (
+        "E{n} OilT",
+        "sim/cockpit2/engine/indicators/oil_temperature_deg_C",
+        RuleScale::Fixed(1.0),
+    ),
+    (
+        "E{n} OilP",
+        "sim/cockpit2/engine/indicators/oil_pressure_psi",
+        RuleScale::Fixed(1.0),
+    ),
+    (
+        "E{n} ITT",
+        "sim/cockpit2/engine/indicators/ITT_deg_C",
+        RuleScale::Fixed(1.0),
+    ),
+    (
+        "E{n} Torq",
+        "sim/cockpit2/engine/indicators/torque_n_mtr",
+        RuleScale::Unit(&TORQUE_UNITS),
+    ),
+    (
+        "E{n} NG",
+        "sim/cockpit2/engine/indicators/N1_percent",
+        RuleScale::Fixed(1.0),
+    ),
+    (
+        "E{n} N1",
+        "sim/cockpit2/engine/indicators/N1_percent",
+        RuleScale::Fixed(1.0),
+    ),
+    (
+        "E{n} N2",
+        "sim/cockpit2/engine/indicators/N2_percent",
+        RuleScale::Fixed(1.0),
+    ),
+    (
+        "E{n} NP",
+        "sim/cockpit2/engine/indicators/prop_speed_rpm",
+        RuleScale::Fixed(1.0),
+    ),
+];
+
+/// The kilograms in the unit of the fuel burned by engines whose fuel flow is logged in a unit, as the fuel flow
+/// rule of their engine type scales it, or the unit it lists when the log doesn't give one
+fn fuel_burned_scale(rules: &[(&str, &str, RuleScale)], fuel_flow_unit: Option<&str>) -> Option<f64> {
+    let (_, _, scale) = rules.iter().find(|(pattern, _, _)| *pattern == "E{n} FFlow")?;
+    // the flow is per hour, and the fuel flow dataref per second
+    match fuel_flow_unit {
+        Some(unit) => scale.of(Some(unit)),
+        None => Some(scale.listed()),
+    }
+    .map(|scale| scale * 3600.0)
+}
+
+/// The engine column families of a type of engine
+fn engine_rules(engine_type: EngineType) -> &'static [(&'static str, &'static str, RuleScale)] {
+    match engine_type {
+        EngineType::Piston => &PISTON_RULES,
+        EngineType::Turbine => &TURBINE_RULES,
+    }
+}
+
+/// The type of engine that logged some columns: turbine if any engine logs its ITT or torque, otherwise piston
+pub(crate) fn logged_engine_type(columns: &[&str]) -> EngineType {
+    let turbine = columns.iter().any(|c| {
+        ["E{n} ITT", "E{n} Torq"]
+            .iter()
+            .any(|p| match_engine_pattern(p, c).is_some())
+    });
+    if turbine {
+        EngineType::Turbine
+    } else {
+        EngineType::Piston
+    }
+}
+
+/// Match a column against an engine rule pattern such as `E{n} CHT{m}`, returning its engine and cylinder numbers
+pub fn match_engine_pattern(pattern: &str, column: &str) -> Option<(usize, Option<usize>)> {
+    let (mut pattern, mut rest) = (pattern, column);
+    let (mut engine, mut cylinder) = (None, None);
+    while let Some(start) = pattern.find('{') {
+        rest = rest.strip_prefix(&pattern[..start])?;
+        let digits = rest.find(|c: char| !c.is_ascii_digit()).unwrap_or(rest.len());
+        let number = rest[..digits].parse::<usize>().ok().filter(|n| *n > 0)?;
+        match &pattern[start..start + 3] {
+            "{n}" => engine = Some(number),
+            _ => cylinder = Some(number),
+        }
+        rest = &rest[digits..];
+        pattern = &pattern[start + 3..];
+    }
+    (rest == pattern).then_some((engine?, cylinder))
+}
+
+/// The dataref of an engine rule for an engine, or a cylinder of an engine with the given number of cylinders
+fn engine_dref(path: &str, scale: f64, engine: usize, cylinder: Option<usize>, cylinders: usize) -> DataRef {
+    let index = match cylinder {
+        Some(cylinder) => (engine - 1) * cylinders + cylinder - 1,
+        None => engine - 1,
+    };
+    DataRef::new(format!("{}[{}]", path, index)).with_scale(scale)
+}
+
+/// The datarefs the columns of a log are written to: the Garmin mapping, the engine rules for the engine type and
+/// engines of the profile (those logged if it doesn't give them), and the datarefs the profile gives enum columns.
+/// `units` are the units of the columns, from the log header, which choose the scale of some engine columns.
+pub fn column_drefs(
+    columns: &[&str],
+    units: &[(String, String)],
+    profile: &AircraftProfile,
+) -> HashMap<String, DataRef> {
+    let engines = profile.engines.unwrap_or(usize::MAX);
+    let engine_type = profile.engine_type.unwrap_or_else(|| logged_engine_type(columns));
+    let rules = engine_rules(engine_type);
+    let unit = |column: &str| {
+        units
+            .iter()
+            .find(|(name, _)| name == column)
+            .map(|(_, unit)| unit.as_str())
+    };
+    let matches: Vec<(&str, &str, f64, usize, Option<usize>)> = columns
+        .iter()
+        .filter_map(|column| {
+            let (path, scale, engine, cylinder) = rules.iter().find_map(|(pattern, path, scale)| {
+                let (engine, cylinder) = match_engine_pattern(pattern, column)?;
+                Some((*path, scale, engine, cylinder))
+            })?;
+            // a column logged in a unit the rule doesn't know is not written, rather than written at the wrong scale
+            Some((*column, path, scale.of(unit(column))?, engine, cylinder))
+        })
+        .filter(|(_, _, _, engine, _)| *engine <= engines)
+        .collect();
+    // without a count from the profile, every engine has as many cylinders as the most logged
+    let cylinders = profile
+        .cylinders
+        .or_else(|| matches.iter().filter_map(|m| m.4).max())
+        .unwrap_or(DEFAULT_CYLINDERS);
+
+    let mut map: HashMap<String, DataRef> = build_dref_map()
+        .into_iter()
+        .map(|(name, dref)| (name.to_string(), dref))
+        .collect();
+    for (column, path, scale, engine, cylinder) in matches {
+        if cylinder.is_none_or(|c| c <= cylinders) {
+            map.insert(
+                column.to_string(),
+                engine_dref(path, scale, engine, cylinder, cylinders),
+            );
+        }
+    }
+    let fuel_flow_unit = columns
+        .iter()
+        .find(|column| match_engine_pattern("E{n} FFlow", column).is_some())
+        .and_then(|column| unit(column));
+    map.extend(
+        derive::derived_drefs(fuel_burned_scale(rules, fuel_flow_unit))
+            .into_iter()
+            .map(|(name, dref)| (name.to_string(), dref)),
+    );
+    // a profile may write enum columns to other datarefs, or to those of columns without one
+    for (name, table) in &profile.enums {
+        if let Some(path) = &table.dref {
+            map.insert(name.clone(), DataRef::new(path.clone()));
+        }
+    }
+    map
+}
+
 fn build_dref_map() -> HashMap<&'static str, DataRef> {
     let mut map = HashMap::new();
     // map.insert("AtvWpt", DataRef::new("sim/cockpit2/gauges/actuators/placeholder".to_string()));
@@ -443,124 +1603,61 @@ fn build_dref_map() -> HashMap<&'static str, DataRef> {
     );
     map.insert(
         "FQtyLlbs",
-        DataRef::new("sim/flightmodel/weight/m_fuel[0]".to_string()).with_scale(0.45359237),
+        DataRef::new("sim/flightmodel/weight/m_fuel[0]".to_string()).with_scale(KG_PER_LB),
     ); // lbs -> kg
     map.insert(
         "FQtyRlbs",
-        DataRef::new("sim/flightmodel/weight/m_fuel[1]".to_string()).with_scale(0.45359237),
+        DataRef::new("sim/flightmodel/weight/m_fuel[1]".to_string()).with_scale(KG_PER_LB),
     ); // lbs -> kg
     map.insert(
         "FQtyL",
-        DataRef::new("sim/cockpit2/fuel/fuel_quantity[0]".to_string()).with_scale(2.73062384),
+        DataRef::new("sim/cockpit2/fuel/fuel_quantity[0]".to_string()).with_scale(AVGAS_LB_PER_GAL * KG_PER_LB),
     ); // gal -> kg
     map.insert(
         "FQtyR",
-        DataRef::new("sim/cockpit2/fuel/fuel_quantity[1]".to_string()).with_scale(2.73062384),
+        DataRef::new("sim/cockpit2/fuel/fuel_quantity[1]".to_string()).with_scale(AVGAS_LB_PER_GAL * KG_PER_LB),
     ); // gal -> kg
-       // insert the rest with a placeholder path
-       // map.insert("LatAc", DataRef::new("???".to_string()));
-       // map.insert("NormAc", DataRef::new("???".to_string()));
-    map.insert(
-        "E1 FFlow",
-        DataRef::new("sim/cockpit2/engine/indicators/fuel_flow_kg_sec[0]".to_string()).with_scale(1.0),
-    ); // gph -> kg/s
-    map.insert(
-        "E1 FPres",
-        DataRef::new("sim/cockpit2/engine/indicators/fuel_pressure_psi[0]".to_string()),
-    );
-    map.insert(
-        "E1 OilT",
-        DataRef::new("sim/cockpit2/engine/indicators/oil_temperature_deg_C[0]".to_string()),
-    );
-    map.insert(
-        "E1 OilP",
-        DataRef::new("sim/cockpit2/engine/indicators/oil_pressure_psi[0]".to_string()),
-    );
+    map.insert("LatAc", DataRef::new("sim/flightmodel/forces/g_side".to_string()));
+    // translated from the difference from 1 G
+    map.insert("NormAc", DataRef::new("sim/flightmodel/forces/g_nrml".to_string()));
+    // the engine columns are mapped by PISTON_RULES and TURBINE_RULES
     // map.insert(
-    //     "E1 MAP",
+    //     "AltGPS",
     //     DataRef::new("???".to_string()),
     // );
     map.insert(
-        "E1 RPM",
-        DataRef::new("sim/cockpit2/engine/indicators/engine_speed_rpm[0]".to_string()),
-    );
-    map.insert(
-        "E1 %Pwr",
-        DataRef::new("sim/cockpit2/engine/indicators/N1_percent".to_string()),
-    );
+        "HSIS",
+        DataRef::new("sim/cockpit2/radios/actuators/HSI_source_select_pilot".to_string()),
+    ); // translated from the source name
     map.insert(
-        "E1 CHT1",
-        DataRef::new("sim/cockpit2/engine/indicators/CHT_CYL_deg_F[0]".to_string()),
+        "CRS",
+        DataRef::new("sim/cockpit2/radios/actuators/hsi_obs_deg_mag_pilot".to_string()),
     );
     map.insert(
-        "E1 CHT2",
-        DataRef::new("sim/cockpit2/engine/indicators/CHT_CYL_deg_F[1]".to_string()),
-    );
-    map.insert(
-        "E1 CHT3",
-        DataRef::new("sim/cockpit2/engine/indicators/CHT_CYL_deg_F[2]".to_string()),
-    );
+        "NAV1",
+        DataRef::new("sim/cockpit2/radios/actuators/nav1_frequency_hz".to_string()).with_scale(100.0),
+    ); // MHz -> 10 kHz
     map.insert(
-        "E1 CHT4",
-        DataRef::new("sim/cockpit2/engine/indicators/CHT_CYL_deg_F[3]".to_string()),
-    );
-    // map.insert(
-    //     "E1 CHT CLD",
-    //     DataRef::new("???".to_string()),
-    // );
+        "NAV2",
+        DataRef::new("sim/cockpit2/radios/actuators/nav2_frequency_hz".to_string()).with_scale(100.0),
+    ); // MHz -> 10 kHz
     map.insert(
-        "E1 EGT1",
-        DataRef::new("sim/cockpit2/engine/indicators/EGT_CYL_deg_F[0]".to_string()),
-    );
+        "HCDI",
+        DataRef::new("sim/cockpit2/radios/indicators/hsi_hdef_dots_pilot".to_string()).with_scale(2.5),
+    ); // full scale -> dots
     map.insert(
-        "E1 EGT2",
-        DataRef::new("sim/cockpit2/engine/indicators/EGT_CYL_deg_F[1]".to_string()),
-    );
+        "VCDI",
+        DataRef::new("sim/cockpit2/radios/indicators/hsi_vdef_dots_pilot".to_string()).with_scale(2.5),
+    ); // full scale -> dots
     map.insert(
-        "E1 EGT3",
-        DataRef::new("sim/cockpit2/engine/indicators/EGT_CYL_deg_F[2]".to_string()),
+        "WndSpd",
+        DataRef::new("sim/cockpit2/gauges/indicators/wind_speed_kts".to_string()),
     );
     map.insert(
-        "E1 EGT4",
-        DataRef::new("sim/cockpit2/engine/indicators/EGT_CYL_deg_F[3]".to_string()),
+        "WndDr",
+        DataRef::new("sim/cockpit2/gauges/indicators/wind_heading_deg_mag".to_string()),
     );
     // map.insert(
-    //     "AltGPS",
-    //     DataRef::new("???".to_string()),
-    // );
-    // map.insert(
-    //     "HSIS",
-    //     DataRef::new("???".to_string()),
-    // );
-    // map.insert(
-    //     "CRS",
-    //     DataRef::new("sim/cockpit2/gauges/actuators/placeholder".to_string()),
-    // );
-    // map.insert(
-    //     "NAV1",
-    //     DataRef::new("sim/cockpit2/gauges/actuators/placeholder".to_string()),
-    // );
-    // map.insert(
-    //     "NAV2",
-    //     DataRef::new("sim/cockpit2/gauges/actuators/placeholder".to_string()),
-    // );
-    // map.insert(
-    //     "HCDI",
-    //     DataRef::new("sim/cockpit2/gauges/actuators/placeholder".to_string()),
-    // );
-    // map.insert(
-    //     "VCDI",
-    //     DataRef::new("sim/cockpit2/gauges/actuators/placeholder".to_string()),
-    // );
-    // map.insert(
-    //     "WndSpd",
-    //     DataRef::new("sim/cockpit2/gauges/actuators/placeholder".to_string()),
-    // );
-    // map.insert(
-    //     "WndDr",
-    //     DataRef::new("sim/cockpit2/gauges/actuators/placeholder".to_string()),
-    // );
-    // map.insert(
     //     "WptDst",
     //     DataRef::new("sim/cockpit2/gauges/actuators/placeholder".to_string()),
     // );
@@ -572,30 +1669,21 @@ fn build_dref_map() -> HashMap<&'static str, DataRef> {
     //     "MagVar",
     //     DataRef::new("sim/cockpit2/gauges/actuators/placeholder".to_string()),
     // );
-    // map.insert(
-    //     "AfcsOn",
-    //     DataRef::new("sim/cockpit2/gauges/actuators/placeholder".to_string()),
-    // );
-    // map.insert(
-    //     "RollM",
-    //     DataRef::new("sim/cockpit2/gauges/actuators/placeholder".to_string()),
-    // );
-    // map.insert(
-    //     "PitchM",
-    //     DataRef::new("sim/cockpit2/gauges/actuators/placeholder".to_string()),
-    // );
-    // map.insert(
-    //     "RollC",
-    //     DataRef::new("sim/cockpit2/gauges/actuators/placeholder".to_string()),
-    // );
-    // map.insert(
-    //     "PitchC",
-    //     DataRef::new("sim/cockpit2/gauges/actuators/placeholder".to_string()),
-    // );
-    // map.insert(
-    //     "VSpdG",
-    //     DataRef::new("sim/cockpit2/gauges/actuators/placeholder".to_string()),
-    // );
+    map.insert("AfcsOn", DataRef::new("sim/cockpit2/autopilot/servos_on".to_string()));
+    map.insert("RollM", DataRef::new("sim/cockpit2/autopilot/heading_mode".to_string())); // translated from the mode name
+    map.insert(
+        "PitchM",
+        DataRef::new("sim/cockpit2/autopilot/altitude_mode".to_string()),
+    ); // translated from the mode name
+    map.insert(
+        "RollC",
+        DataRef::new("sim/cockpit2/autopilot/flight_director_roll_deg".to_string()),
+    );
+    map.insert(
+        "PitchC",
+        DataRef::new("sim/cockpit2/autopilot/flight_director_pitch_deg".to_string()),
+    );
+    map.insert("VSpdG", DataRef::new("sim/cockpit2/autopilot/vvi_dial_fpm".to_string()));
     // map.insert(
     //     "GPSfix",
     //     DataRef::new("sim/cockpit2/gauges/actuators/placeholder".to_string()),
@@ -646,3 +1734,265 @@ fn build_dref_map() -> HashMap<&'static str, DataRef> {
     // );
     map
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        detection::{read_avionics_log, stream_avionics_log},
+        fdr::{FDRConfigurationBuilder, FlightDataSource},
+        profile::EngineType,
+        AviationLogSourceOption,
+    };
+    use std::{collections::HashMap, path::PathBuf};
+
+    fn sample_csv() -> String {
+        crate::resource_path("log_231104_084813_KPOU.csv")
+            .to_str()
+            .unwrap()
+            .to_string()
+    }
+
+    #[test]
+    fn test_follow_growing_log() -> Result<(), Box<dyn std::error::Error>> {
+        use std::io::Write;
+
+        let sample = std::fs::read_to_string(sample_csv())?;
+        let lines: Vec<&str> = sample.lines().collect();
+        let mut log = tempfile::NamedTempFile::new()?;
+        writeln!(log, "{}", lines[..3 + 300].join("\n"))?;
+
+        let follow = GarminLogFollow::new(
+            log.path(),
+            std::time::Duration::from_millis(10),
+            None,
+            LogCheckMode::Drop,
+        )?;
+        assert_eq!(follow.tail_number().as_deref(), Some("N12345"));
+        let mut tail = follow.tail()?;
+        assert_eq!(tail.poll()?.map(|df| df.height()), Some(300));
+        assert!(tail.poll()?.is_none());
+
+        // a row that is still being written is read once it is complete
+        writeln!(log, "{}", lines[3 + 300..3 + 310].join("\n"))?;
+        write!(log, "{}", &lines[3 + 310][..20])?;
+        assert_eq!(tail.poll()?.map(|df| df.height()), Some(10));
+        writeln!(log, "{}", &lines[3 + 310][20..])?;
+        let df = tail.poll()?.unwrap();
+        assert_eq!(df.height(), 1);
+
+        let expected = stream_avionics_log(&AviationLogSourceOption::Garmin, &PathBuf::from(sample_csv()), 311)?
+            .data_blocks(&FDRConfigurationBuilder::default().build())
+            .next()
+            .unwrap()?;
+        let block = follow.data_block(&FDRConfigurationBuilder::default().build())?;
+        assert!(block.data.equals_missing(&expected.data));
+
+        // the glitch filter needs the whole log, so it refuses to filter the records as they are written
+        let filtered = FDRConfigurationBuilder::default()
+            .glitch_filter(crate::filter::GlitchFilter::default().max_speed(Some(250.0)))
+            .build();
+        assert!(follow.data_blocks(&filtered).next().unwrap().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_logcheck_modes() -> Result<(), Box<dyn std::error::Error>> {
+        use crate::detection::open_avionics_log;
+        use std::io::Write;
+
+        // corrupt a digit of the latitude of the row on line 1004, as a bad SD card might
+        let sample = std::fs::read_to_string(sample_csv())?;
+        let mut lines: Vec<String> = sample.lines().map(String::from).collect();
+        assert!(logcheck_matches(&lines[1003]));
+        let latitude = lines[1003].find(" 41.").unwrap() + 2;
+        lines[1003].replace_range(latitude..latitude + 1, "7");
+        assert!(!logcheck_matches(&lines[1003]));
+        let mut log = tempfile::NamedTempFile::new()?;
+        writeln!(log, "{}", lines.join("\n"))?;
+
+        let garmin = AviationLogSourceOption::Garmin;
+        for batch_size in [None, Some(500)] {
+            let source = open_avionics_log(&garmin, log.path(), batch_size, LogCheckMode::Off)?;
+            assert_eq!(source.data()?.height(), 3676);
+            assert_eq!(source.checksum_report(), None);
+
+            let source = open_avionics_log(&garmin, log.path(), batch_size, LogCheckMode::Drop)?;
+            assert_eq!(source.data()?.height(), 3675);
+            let report = source.checksum_report().unwrap();
+            assert_eq!((report.rows, report.invalid_lines.clone()), (3676, vec![1004]));
+            assert_eq!(report.to_string(), "1 of 3676 records invalid (line 1004)");
+
+            let source = open_avionics_log(&garmin, log.path(), batch_size, LogCheckMode::Flag)?;
+            let valid = source.data()?.column("LogCheckValid")?.bool()?.clone();
+            assert_eq!((valid.len(), valid.sum()), (3676, Some(3675)));
+        }
+
+        assert!(open_avionics_log(&garmin, log.path(), None, LogCheckMode::Fail).is_err());
+        let source = open_avionics_log(&garmin, log.path(), Some(500), LogCheckMode::Fail)?;
+        assert!(source.data().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_unknown_enum_values() -> Result<(), Box<dyn std::error::Error>> {
+        use crate::detection::open_avionics_log;
+        use std::io::Write;
+
+        // an HSI source the Garmin table lacks, as a newer software version might log
+        let sample = std::fs::read_to_string(sample_csv())?;
+        let mut lines: Vec<String> = sample.lines().map(String::from).collect();
+        let hsis = lines[2].split(',').position(|name| name.trim() == "HSIS").unwrap();
+        for line in &mut lines[1000..1010] {
+            let mut fields: Vec<&str> = line.split(',').collect();
+            fields[hsis] = " DME";
+            *line = fields.join(",");
+        }
+        let mut log = tempfile::NamedTempFile::new()?;
+        writeln!(log, "{}", lines.join("\n"))?;
+
+        let garmin = AviationLogSourceOption::Garmin;
+        let config = FDRConfigurationBuilder::default().auto_drefs(true).build();
+        for batch_size in [None, Some(500)] {
+            let source = open_avionics_log(&garmin, log.path(), batch_size, LogCheckMode::Off)?;
+            let unknown = source.unknown_enum_values(&config).unwrap();
+            assert_eq!(unknown.to_string(), "HSIS=DME (10 records)");
+        }
+
+        // the records are kept, written as the default of the table
+        let source = open_avionics_log(&garmin, log.path(), None, LogCheckMode::Off)?;
+        let hsis = source.data_block(&config)?.data.column("HSIS")?.f64()?.clone();
+        assert_eq!((hsis.get(997), hsis.get(1006)), (Some(0.0), Some(0.0)));
+        let expected = read_avionics_log(&garmin, &PathBuf::from(sample_csv()))?.data_block(&config)?;
+        assert_eq!(hsis.null_count(), expected.data.column("HSIS")?.null_count());
+        Ok(())
+    }
+
+    #[test]
+    fn test_translated_drefs() -> Result<(), Box<dyn std::error::Error>> {
+        let data = read_avionics_log(&AviationLogSourceOption::Garmin, &PathBuf::from(sample_csv()))?;
+        let block = data.data_block(&FDRConfigurationBuilder::default().auto_drefs(true).build())?;
+        let dref = |column: &str| block.drefs[block.data.get_column_index(column).unwrap() - 7].clone();
+
+        // enum columns are written as the numbers of their datarefs
+        assert_eq!(
+            dref("HSIS").path,
+            "sim/cockpit2/radios/actuators/HSI_source_select_pilot"
+        );
+        assert_eq!(block.data.column("HSIS")?.f64()?.get(0), Some(2.0));
+        assert_eq!(block.data.column("PitchM")?.f64()?.max(), Some(0.0));
+        let normal = block.data.column("NormAc")?.f64()?.mean().unwrap();
+        assert!((normal - 1.0).abs() < 0.01);
+        assert_eq!(dref("NAV1").scale, 100.0);
+        assert_eq!(dref("E1 MAP").path, "sim/cockpit2/engine/indicators/MPR_in_hg[0]");
+        // fuel flow is logged in gallons of avgas an hour: 10 gph is 60 lb/h, or 0.00756 kg/s
+        assert!((dref("E1 FFlow").scale * 10.0 - 0.00756).abs() < 1e-5);
+        Ok(())
+    }
+
+    #[test]
+    fn test_engine_column_drefs() {
+        let columns = [
+            "E1 RPM",
+            "E2 RPM",
+            "E1 CHT6",
+            "E2 CHT1",
+            "E2 CHT6",
+            "E3 MAP",
+            "E1 CHT CLD",
+        ];
+        let path = |map: &HashMap<String, DataRef>, column: &str| map.get(column).map(|d| d.path.clone());
+
+        // every engine logged, with as many cylinders as the most logged
+        let map = column_drefs(&columns, &[], &AircraftProfile::default());
+        assert_eq!(
+            path(&map, "E2 RPM").as_deref(),
+            Some("sim/cockpit2/engine/indicators/engine_speed_rpm[1]")
+        );
+        assert_eq!(
+            path(&map, "E2 CHT1").as_deref(),
+            Some("sim/cockpit2/engine/indicators/CHT_CYL_deg_F[6]")
+        );
+        assert_eq!(
+            path(&map, "E2 CHT6").as_deref(),
+            Some("sim/cockpit2/engine/indicators/CHT_CYL_deg_F[11]")
+        );
+        assert_eq!(
+            path(&map, "E3 MAP").as_deref(),
+            Some("sim/cockpit2/engine/indicators/MPR_in_hg[2]")
+        );
+        assert_eq!(path(&map, "E1 CHT CLD"), None);
+
+        // a profile limits the engines mapped
+        let profile = AircraftProfile {
+            engines: Some(2),
+            ..Default::default()
+        };
+        let map = column_drefs(&columns, &[], &profile);
+        assert!(map.contains_key("E2 CHT6"));
+        assert!(!map.contains_key("E3 MAP"));
+        let listed = dref_map(&profile);
+        assert!(listed.iter().any(|(column, _)| column == "E2 EGT4"));
+
+        // fuel flow is scaled by the unit it is logged in, and not written in a unit without a scale
+        let units = |unit: &str| vec![("E1 FFlow".to_string(), unit.to_string())];
+        let map = column_drefs(&["E1 FFlow"], &units("pph"), &AircraftProfile::default());
+        assert!((map["E1 FFlow"].scale * 3600.0 - 0.45359237).abs() < 1e-9);
+        let map = column_drefs(&["E1 FFlow"], &units("l/h"), &AircraftProfile::default());
+        assert!(!map.contains_key("E1 FFlow"));
+
+        // so is the fuel burned, which is in the unit of fuel of the fuel flow
+        let columns = ["E1 FFlow", "FUsed"];
+        let map = column_drefs(&columns, &units("pph"), &AircraftProfile::default());
+        assert!((map["FUsed"].scale - 0.45359237).abs() < 1e-9);
+        let map = column_drefs(&columns, &units("gph"), &AircraftProfile::default());
+        assert!((map["FUsed"].scale - 6.0 * 0.45359237).abs() < 1e-9);
+        let map = column_drefs(&columns, &units("l/h"), &AircraftProfile::default());
+        assert!(!map.contains_key("FUsed"));
+        assert_eq!(
+            crate::derive::fuel_unit(&units("pph"), &AircraftProfile::default()),
+            "lbs"
+        );
+    }
+
+    #[test]
+    fn test_turbine_column_drefs() {
+        let columns = ["E1 ITT", "E1 Torq", "E1 NG", "E1 NP", "E1 FFlow", "E2 ITT"];
+        let dref = |map: &HashMap<String, DataRef>, column: &str| map.get(column).cloned().unwrap();
+        let header = |units: &str| {
+            GarminEISLogHeader::parse(
+                "#airframe_info, log_version=\"1.03\"",
+                &format!("#deg C,{},%,rpm,pph,deg C", units),
+                &columns.join(","),
+            )
+            .column_units()
+        };
+
+        // turbine columns are recognized without a profile
+        let map = column_drefs(&columns, &header("ft-lb"), &AircraftProfile::default());
+        assert_eq!(dref(&map, "E2 ITT").path, "sim/cockpit2/engine/indicators/ITT_deg_C[1]");
+        assert_eq!(dref(&map, "E1 NG").path, "sim/cockpit2/engine/indicators/N1_percent[0]");
+        assert_eq!(
+            dref(&map, "E1 NP").path,
+            "sim/cockpit2/engine/indicators/prop_speed_rpm[0]"
+        );
+        assert!((dref(&map, "E1 Torq").scale - 1.3558).abs() < 1e-4);
+        // fuel flow in pph
+        assert!((dref(&map, "E1 FFlow").scale * 3600.0 - 0.45359237).abs() < 1e-9);
+
+        // torque logged as a percent has no scale to N m, so it is not written
+        let map = column_drefs(&columns, &header("%"), &AircraftProfile::default());
+        assert!(!map.contains_key("E1 Torq"));
+        assert!(map.contains_key("E1 FFlow"));
+
+        // or selected by the profile, with fuel flow in gallons of jet fuel
+        let profile = AircraftProfile {
+            engine_type: Some(EngineType::Turbine),
+            ..Default::default()
+        };
+        let units = [("E1 FFlow".to_string(), "gph".to_string())];
+        let map = column_drefs(&["E1 FFlow"], &units, &profile);
+        assert!((dref(&map, "E1 FFlow").scale * 3600.0 - 6.7 * 0.45359237).abs() < 1e-9);
+        assert!(dref_map(&profile).iter().any(|(column, _)| column == "E1 Torq"));
+    }
+}
@@ -0,0 +1,112 @@
+//! Export of a flight to GeoJSON, for mapping tools.
+//!
+//! The flight is written as a feature collection with one LineString feature. As GeoJSON has no per-point properties,
+//! the time and auxiliary fields of each point are written as arrays in the order of the coordinates, following the
+//! common `coordTimes` convention.
+
+use crate::export::ExportBlock;
+use crate::fdr::FDRWriteError;
+use crate::track::{self, FEET_PER_METER};
+use chrono::SecondsFormat;
+use serde_json::{json, Map, Value};
+use std::io::Write;
+
+/// Writes a data block as a GeoJSON feature collection
+#[derive(Default)]
+pub struct GeoJsonWriter {
+    name: Option<String>,
+    aux_columns: Vec<String>,
+}
+
+impl GeoJsonWriter {
+    /// The name of the flight, written as a property of the feature
+    pub fn name(mut self, name: String) -> Self {
+        self.name = Some(name);
+        self
+    }
+
+    /// Columns of the data block written as properties of each point, when present
+    pub fn aux_columns(mut self, columns: Vec<String>) -> Self {
+        self.aux_columns = columns;
+        self
+    }
+
+    pub fn write<W: Write>(&self, block: &ExportBlock, writer: &mut W) -> Result<(), FDRWriteError> {
+        let points = track::indexed_track_points(&block.block)?;
+
+        let coordinates: Vec<Value> = points
+            .iter()
+            .map(|(_, p)| {
+                json!([
+                    p.longitude,
+                    p.latitude,
+                    (p.altitude / FEET_PER_METER * 10.0).round() / 10.0
+                ])
+            })
+            .collect();
+        let times: Vec<String> = points
+            .iter()
+            .map(|(_, p)| p.time.to_rfc3339_opts(SecondsFormat::AutoSi, true))
+            .collect();
+
+        let mut properties = Map::new();
+        if let Some(name) = &self.name {
+            properties.insert("name".to_string(), json!(name));
+        }
+        properties.insert("coordTimes".to_string(), json!(times));
+        properties.insert(
+            "heading".to_string(),
+            json!(points.iter().map(|(_, p)| p.heading).collect::<Vec<f64>>()),
+        );
+        for name in &self.aux_columns {
+            if let Some(values) = block.column_values(name) {
+                let values: Vec<Option<f64>> = points.iter().map(|(i, _)| values[*i]).collect();
+                properties.insert(name.clone(), json!(values));
+            }
+        }
+
+        let collection = json!({
+            "type": "FeatureCollection",
+            "features": [{
+                "type": "Feature",
+                "geometry": {
+                    "type": "LineString",
+                    "coordinates": coordinates,
+                },
+                "properties": properties,
+            }],
+        });
+        serde_json::to_writer(&mut *writer, &collection).map_err(std::io::Error::from)?;
+        writeln!(writer)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{detection::read_avionics_log, export, fdr::FDRConfigurationBuilder, AviationLogSourceOption};
+
+    #[test]
+    fn test_geojson_writer() -> Result<(), Box<dyn std::error::Error>> {
+        let path = crate::resource_path("log_231104_084813_KPOU.csv");
+        let source = read_avionics_log(&AviationLogSourceOption::Garmin, &path)?;
+        let columns: Vec<String> = export::DEFAULT_AUX_COLUMNS.iter().map(|s| s.to_string()).collect();
+        let block = export::export_block(source.as_ref(), &FDRConfigurationBuilder::default().build(), &columns)?;
+        let samples = 3676 - 149;
+        // the extra columns are kept apart from the block, whose fields all have a DREF
+        assert_eq!(block.extra.height(), block.block.data.height());
+        assert!(block.extra.column("IAS").is_ok() && block.block.data.column("IAS").is_err());
+
+        let mut buffer = Vec::new();
+        GeoJsonWriter::default()
+            .aux_columns(columns)
+            .write(&block, &mut buffer)?;
+        let geojson: Value = serde_json::from_slice(&buffer)?;
+        let feature = &geojson["features"][0];
+        assert_eq!(feature["geometry"]["coordinates"].as_array().unwrap().len(), samples);
+        assert_eq!(feature["properties"]["E1 RPM"].as_array().unwrap().len(), samples);
+        assert_eq!(feature["properties"]["coordTimes"][0], "2023-11-04T12:50:01Z");
+        Ok(())
+    }
+}
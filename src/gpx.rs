@@ -0,0 +1,133 @@
+//! Export of a flight to a GPX 1.1 track, for mapping tools and logbooks.
+//!
+//! Auxiliary fields such as airspeed are written as extensions of each track point, in the xfdr namespace.
+
+use crate::export::ExportBlock;
+use crate::fdr::FDRWriteError;
+use crate::kml::escape_xml;
+use crate::track::{self, FEET_PER_METER};
+use chrono::SecondsFormat;
+use std::io::Write;
+
+/// The namespace of the extension elements written for auxiliary fields
+pub const EXTENSION_NAMESPACE: &str = "urn:xfdr:gpx:extensions:1";
+
+/// Make a field name, such as "E1 RPM", usable as an XML element name
+fn element_name(name: &str) -> String {
+    let name: String = name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '-' { c } else { '_' })
+        .collect();
+    if name.starts_with(|c: char| c.is_ascii_alphabetic()) {
+        name
+    } else {
+        format!("_{}", name)
+    }
+}
+
+/// Writes a data block as a GPX document with a single track
+#[derive(Default)]
+pub struct GpxWriter {
+    name: Option<String>,
+    aux_columns: Vec<String>,
+}
+
+impl GpxWriter {
+    /// The name of the track
+    pub fn name(mut self, name: String) -> Self {
+        self.name = Some(name);
+        self
+    }
+
+    /// Columns of the data block written as extensions of each point, when present
+    pub fn aux_columns(mut self, columns: Vec<String>) -> Self {
+        self.aux_columns = columns;
+        self
+    }
+
+    pub fn write<W: Write>(&self, block: &ExportBlock, writer: &mut W) -> Result<(), FDRWriteError> {
+        let points = track::indexed_track_points(&block.block)?;
+        let aux: Vec<(String, Vec<Option<f64>>)> = self
+            .aux_columns
+            .iter()
+            .filter_map(|name| block.column_values(name).map(|v| (element_name(name), v)))
+            .collect();
+
+        writeln!(writer, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+        writeln!(
+            writer,
+            r#"<gpx version="1.1" creator="xfdr" xmlns="http://www.topografix.com/GPX/1/1" xmlns:xfdr="{}">"#,
+            EXTENSION_NAMESPACE
+        )?;
+        writeln!(writer, "<metadata>")?;
+        if let Some(name) = &self.name {
+            writeln!(writer, "<name>{}</name>", escape_xml(name))?;
+        }
+        if let Some((_, first)) = points.first() {
+            writeln!(
+                writer,
+                "<time>{}</time>",
+                first.time.to_rfc3339_opts(SecondsFormat::AutoSi, true)
+            )?;
+        }
+        writeln!(writer, "</metadata>")?;
+
+        writeln!(writer, "<trk>")?;
+        if let Some(name) = &self.name {
+            writeln!(writer, "<name>{}</name>", escape_xml(name))?;
+        }
+        writeln!(writer, "<trkseg>")?;
+        for (i, p) in &points {
+            writeln!(writer, r#"<trkpt lat="{}" lon="{}">"#, p.latitude, p.longitude)?;
+            writeln!(writer, "<ele>{:.1}</ele>", p.altitude / FEET_PER_METER)?;
+            writeln!(
+                writer,
+                "<time>{}</time>",
+                p.time.to_rfc3339_opts(SecondsFormat::AutoSi, true)
+            )?;
+
+            let values: Vec<(&String, f64)> = aux
+                .iter()
+                .filter_map(|(name, values)| values[*i].map(|v| (name, v)))
+                .collect();
+            if !values.is_empty() {
+                writeln!(writer, "<extensions>")?;
+                for (name, value) in values {
+                    writeln!(writer, "<xfdr:{0}>{1}</xfdr:{0}>", name, value)?;
+                }
+                writeln!(writer, "</extensions>")?;
+            }
+            writeln!(writer, "</trkpt>")?;
+        }
+        writeln!(writer, "</trkseg>")?;
+        writeln!(writer, "</trk>")?;
+        writeln!(writer, "</gpx>")?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{detection::read_avionics_log, export, fdr::FDRConfigurationBuilder, AviationLogSourceOption};
+
+    #[test]
+    fn test_gpx_writer() -> Result<(), Box<dyn std::error::Error>> {
+        let path = crate::resource_path("log_231104_084813_KPOU.csv");
+        let source = read_avionics_log(&AviationLogSourceOption::Garmin, &path)?;
+        let columns: Vec<String> = export::DEFAULT_AUX_COLUMNS.iter().map(|s| s.to_string()).collect();
+        let block = export::export_block(source.as_ref(), &FDRConfigurationBuilder::default().build(), &columns)?;
+        let samples = 3676 - 149;
+
+        let mut buffer = Vec::new();
+        GpxWriter::default()
+            .name("N12345".to_string())
+            .aux_columns(columns)
+            .write(&block, &mut buffer)?;
+        let gpx = String::from_utf8(buffer)?;
+        assert_eq!(gpx.matches("<trkpt ").count(), samples);
+        assert!(gpx.contains("<xfdr:E1_RPM>"));
+        assert!(gpx.contains("<xfdr:IAS>"));
+        Ok(())
+    }
+}
@@ -0,0 +1,428 @@
+//! Inspection of avionics logs: header metadata, logged fields, summary statistics and validation.
+
+use crate::fdr::{ChecksumReport, FDRConfiguration, FlightDataError, FlightDataSource};
+use chrono::{DateTime, Utc};
+use polars::prelude::*;
+use serde::Serialize;
+use std::{collections::BTreeMap, fmt::Display};
+
+/// The required fields of a data block, in the order of its first columns
+const REQUIRED_FIELDS: [&str; 7] = [
+    "timestamp",
+    "longitude",
+    "latitude",
+    "altitude",
+    "heading",
+    "pitch",
+    "roll",
+];
+
+/// The first and last timestamp in a dataframe with a "timestamp" column
+pub fn time_span(df: &DataFrame) -> Option<(DateTime<Utc>, DateTime<Utc>)> {
+    let ts = df.column("timestamp").ok()?.datetime().ok()?;
+    let to_utc = |v: i64| match ts.time_unit() {
+        TimeUnit::Nanoseconds => DateTime::<Utc>::from_timestamp_nanos(v),
+        TimeUnit::Microseconds => DateTime::<Utc>::from_timestamp_micros(v).unwrap_or_default(),
+        TimeUnit::Milliseconds => DateTime::<Utc>::from_timestamp_millis(v).unwrap_or_default(),
+    };
+    Some((to_utc(ts.min()?), to_utc(ts.max()?)))
+}
+
+/// The default interval between records, in seconds, above which the log is considered to have a gap
+pub const DEFAULT_GAP_SECS: f64 = 5.0;
+
+/// A period in which no records were logged
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Gap {
+    /// The time of the last record before the gap
+    pub start: DateTime<Utc>,
+    /// The time of the first record after the gap
+    pub end: DateTime<Utc>,
+    pub seconds: f64,
+}
+
+/// The timestamps of a dataframe with a "timestamp" column, in microseconds, skipping nulls
+fn timestamps_micros(df: &DataFrame) -> Vec<i64> {
+    let Some(ts) = df.column("timestamp").ok().and_then(|c| c.datetime().ok()) else {
+        return Vec::new();
+    };
+    let scale = |v: i64| match ts.time_unit() {
+        TimeUnit::Nanoseconds => v / 1000,
+        TimeUnit::Microseconds => v,
+        TimeUnit::Milliseconds => v * 1000,
+    };
+    ts.into_iter().flatten().map(scale).collect()
+}
+
+/// The median interval between consecutive records, in seconds
+pub fn sample_interval(df: &DataFrame) -> Option<f64> {
+    let ts = timestamps_micros(df);
+    let mut intervals: Vec<i64> = ts.windows(2).map(|w| w[1] - w[0]).filter(|d| *d > 0).collect();
+    intervals.sort_unstable();
+    intervals.get(intervals.len() / 2).map(|d| *d as f64 / 1e6)
+}
+
+/// Find the periods where consecutive records are more than `max_gap_secs` apart
+pub fn find_gaps(df: &DataFrame, max_gap_secs: f64) -> Vec<Gap> {
+    let ts = timestamps_micros(df);
+    ts.windows(2)
+        .filter(|w| (w[1] - w[0]) as f64 / 1e6 > max_gap_secs)
+        .map(|w| Gap {
+            start: DateTime::<Utc>::from_timestamp_micros(w[0]).unwrap_or_default(),
+            end: DateTime::<Utc>::from_timestamp_micros(w[1]).unwrap_or_default(),
+            seconds: (w[1] - w[0]) as f64 / 1e6,
+        })
+        .collect()
+}
+
+/// A logged field, its unit and statistics
+#[derive(Debug, Clone, Serialize)]
+pub struct ColumnInfo {
+    pub unit: Option<String>,
+    pub null_percent: f64,
+    #[serde(flatten)]
+    pub stats: ColumnStats,
+}
+
+/// Header metadata, the logged fields and the timing of an avionics log
+#[derive(Debug, Serialize)]
+pub struct LogInfo {
+    pub tail_number: Option<String>,
+    pub metadata: BTreeMap<String, String>,
+    pub columns: Vec<ColumnInfo>,
+    pub records: usize,
+    pub start: Option<DateTime<Utc>>,
+    pub end: Option<DateTime<Utc>>,
+    /// The median interval between records, in seconds
+    pub sample_interval: Option<f64>,
+    pub gaps: Vec<Gap>,
+    /// The check of each record against its checksum, when the source logs one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub checksums: Option<ChecksumReport>,
+}
+
+impl LogInfo {
+    /// Inspect a source, reporting intervals between records longer than `max_gap_secs` as gaps
+    pub fn from_source(source: &dyn FlightDataSource, max_gap_secs: f64) -> Result<Self, FlightDataError> {
+        let data = source.data()?;
+        let span = time_span(&data);
+        let units = source.columns();
+        let columns = column_stats(&data)
+            .into_iter()
+            .map(|stats| ColumnInfo {
+                unit: units
+                    .iter()
+                    .find(|(name, _)| *name == stats.name)
+                    .map(|(_, unit)| unit.clone()),
+                null_percent: stats.null_percent(),
+                stats,
+            })
+            .collect();
+        Ok(Self {
+            tail_number: source.tail_number(),
+            metadata: source.metadata(),
+            columns,
+            records: data.height(),
+            start: span.map(|s| s.0),
+            end: span.map(|s| s.1),
+            sample_interval: sample_interval(&data),
+            gaps: find_gaps(&data, max_gap_secs),
+            checksums: source.checksum_report(),
+        })
+    }
+
+    /// The number of records per second
+    pub fn sample_rate(&self) -> Option<f64> {
+        self.sample_interval.filter(|i| *i > 0.0).map(|i| 1.0 / i)
+    }
+}
+
+impl Display for LogInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "Tail number: {}", self.tail_number.as_deref().unwrap_or("unknown"))?;
+        writeln!(f, "Records:     {}", self.records)?;
+        if let (Some(start), Some(end)) = (self.start, self.end) {
+            writeln!(f, "Start:       {}", start.to_rfc3339())?;
+            writeln!(f, "End:         {}", end.to_rfc3339())?;
+            writeln!(f, "Duration:    {}s", (end - start).num_seconds())?;
+        }
+        if let (Some(interval), Some(rate)) = (self.sample_interval, self.sample_rate()) {
+            writeln!(f, "Sample rate: {:.2} Hz ({}s interval)", rate, interval)?;
+        }
+        if let Some(checksums) = &self.checksums {
+            writeln!(f, "Checksums:   {}", checksums)?;
+        }
+
+        writeln!(f, "\nMetadata:")?;
+        for (key, value) in &self.metadata {
+            writeln!(f, "  {:<28} {}", key, value)?;
+        }
+
+        let fmt_value = |v: Option<f64>| v.map_or("-".to_string(), |v| format!("{:.2}", v));
+        writeln!(f, "\nColumns:")?;
+        writeln!(
+            f,
+            "  {:<12} {:<10} {:<14} {:>7} {:>12} {:>12}",
+            "name", "unit", "type", "null %", "min", "max"
+        )?;
+        for c in &self.columns {
+            writeln!(
+                f,
+                "  {:<12} {:<10} {:<14} {:>7.1} {:>12} {:>12}",
+                c.stats.name,
+                c.unit.as_deref().unwrap_or("-"),
+                c.stats.dtype,
+                c.null_percent,
+                fmt_value(c.stats.min),
+                fmt_value(c.stats.max)
+            )?;
+        }
+
+        writeln!(f, "\nGaps:")?;
+        if self.gaps.is_empty() {
+            writeln!(f, "  none")?;
+        }
+        for gap in &self.gaps {
+            writeln!(
+                f,
+                "  {} to {} ({}s)",
+                gap.start.to_rfc3339(),
+                gap.end.to_rfc3339(),
+                gap.seconds
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Summary statistics of one column of flight data
+#[derive(Debug, Clone, Serialize)]
+pub struct ColumnStats {
+    pub name: String,
+    pub dtype: String,
+    pub count: usize,
+    pub nulls: usize,
+    pub min: Option<f64>,
+    pub max: Option<f64>,
+    pub mean: Option<f64>,
+}
+
+impl ColumnStats {
+    /// The percentage of values that are null
+    pub fn null_percent(&self) -> f64 {
+        if self.count == 0 {
+            0.0
+        } else {
+            self.nulls as f64 * 100.0 / self.count as f64
+        }
+    }
+}
+
+/// Summary statistics of every column of a dataframe; min, max and mean are only given for numeric columns
+pub fn column_stats(df: &DataFrame) -> Vec<ColumnStats> {
+    df.get_columns()
+        .iter()
+        .map(|c| {
+            let numeric = c.dtype().is_numeric();
+            let values = numeric
+                .then(|| c.cast(&DataType::Float64).ok())
+                .flatten()
+                .and_then(|c| c.f64().ok().map(|v| (v.min(), v.max(), v.mean())));
+            ColumnStats {
+                name: c.name().to_string(),
+                dtype: c.dtype().to_string(),
+                count: c.len(),
+                nulls: c.null_count(),
+                min: values.and_then(|v| v.0),
+                max: values.and_then(|v| v.1),
+                mean: values.and_then(|v| v.2),
+            }
+        })
+        .collect()
+}
+
+/// Format summary statistics as a table
+pub struct StatsTable<'a>(pub &'a [ColumnStats]);
+
+impl Display for StatsTable<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let fmt_value = |v: Option<f64>| v.map_or("-".to_string(), |v| format!("{:.2}", v));
+        writeln!(
+            f,
+            "{:<12} {:>8} {:>8} {:>12} {:>12} {:>12}",
+            "column", "count", "nulls", "min", "max", "mean"
+        )?;
+        for s in self.0 {
+            writeln!(
+                f,
+                "{:<12} {:>8} {:>8} {:>12} {:>12} {:>12}",
+                s.name,
+                s.count,
+                s.nulls,
+                fmt_value(s.min),
+                fmt_value(s.max),
+                fmt_value(s.mean)
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Problems found while checking that a log can be converted
+#[derive(Debug, Default)]
+pub struct ValidationReport {
+    /// Problems that prevent a usable FDR file from being written
+    pub errors: Vec<String>,
+    /// Problems that are worked around during conversion, such as records that will be dropped
+    pub warnings: Vec<String>,
+}
+
+impl ValidationReport {
+    pub fn is_valid(&self) -> bool {
+        self.errors.is_empty()
+    }
+}
+
+impl Display for ValidationReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for e in &self.errors {
+            writeln!(f, "error: {}", e)?;
+        }
+        for w in &self.warnings {
+            writeln!(f, "warning: {}", w)?;
+        }
+        write!(f, "{} errors, {} warnings", self.errors.len(), self.warnings.len())
+    }
+}
+
+/// Check that a source can be converted to a FDR file with the given configuration
+pub fn validate(source: &dyn FlightDataSource, config: &FDRConfiguration) -> ValidationReport {
+    let mut report = ValidationReport::default();
+
+    let block = match source.data_block(config) {
+        Ok(block) => block,
+        Err(e) => {
+            report.errors.push(e.to_string());
+            return report;
+        }
+    };
+    let data = &block.data;
+
+    if data.width() < REQUIRED_FIELDS.len() {
+        report.errors.push("Missing required fields".to_string());
+        return report;
+    }
+
+    // records missing any required field are dropped unless nulls are allowed
+    let required = data.select_by_range(0..REQUIRED_FIELDS.len()).unwrap_or_default();
+    let complete = required.drop_nulls::<String>(None).map_or(0, |df| df.height());
+    if complete == 0 {
+        report
+            .errors
+            .push("No records have all of the required fields".to_string());
+    } else if complete < data.height() {
+        let incomplete = data.height() - complete;
+        report.warnings.push(format!(
+            "{} of {} records are missing a required field{}",
+            incomplete,
+            data.height(),
+            if config.allow_nulls { "" } else { " and will be dropped" }
+        ));
+    }
+
+    // positions out of range are a sign of a corrupt log
+    for (idx, limit) in [(1, 180.0), (2, 90.0)] {
+        let count = required
+            .select_at_idx(idx)
+            .and_then(|c| {
+                c.f64()
+                    .ok()
+                    .map(|v| v.into_iter().flatten().filter(|v| v.abs() > limit).count())
+            })
+            .unwrap_or(0);
+        if count > 0 {
+            report
+                .errors
+                .push(format!("{} records have an invalid {}", count, REQUIRED_FIELDS[idx]));
+        }
+    }
+
+    // records should be in time order
+    if let Some(Ok(ts)) = data.select_at_idx(0).map(|c| c.datetime().cloned()) {
+        let values: Vec<i64> = ts.into_iter().flatten().collect();
+        let backwards = values.windows(2).filter(|w| w[1] < w[0]).count();
+        let repeated = values.windows(2).filter(|w| w[1] == w[0]).count();
+        if backwards > 0 {
+            report.warnings.push(format!("{} records go back in time", backwards));
+        }
+        if repeated > 0 {
+            report
+                .warnings
+                .push(format!("{} records repeat the previous timestamp", repeated));
+        }
+    }
+
+    // records that fail their checksum were corrupted when the log was written or copied
+    if let Some(checksums) = source.checksum_report().filter(|c| c.invalid() > 0) {
+        report.warnings.push(format!("Checksums: {}", checksums));
+    }
+
+    if let Some(unknown) = source.unknown_enum_values(config).filter(|u| !u.is_empty()) {
+        report.warnings.push(format!(
+            "Enum values without a number are written as 0 or the profile's default: {}",
+            unknown
+        ));
+    }
+
+    if config.auto_drefs {
+        let all_fields = source.data().map(|df| df.get_column_names_owned()).unwrap_or_default();
+        let unmapped: Vec<String> = all_fields
+            .iter()
+            .filter(|name| data.column(name).is_err())
+            .map(|name| name.to_string())
+            .collect();
+        if !unmapped.is_empty() {
+            report.warnings.push(format!(
+                "Fields without a DREF are not written: {}",
+                unmapped.join(", ")
+            ));
+        }
+    }
+
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{detection::read_avionics_log, fdr::FDRConfigurationBuilder, AviationLogSourceOption};
+
+    #[test]
+    fn test_info_and_validate_sample() -> Result<(), Box<dyn std::error::Error>> {
+        let path = crate::resource_path("log_231104_084813_KPOU.csv");
+        let source = read_avionics_log(&AviationLogSourceOption::Garmin, &path)?;
+
+        let info = LogInfo::from_source(source.as_ref(), DEFAULT_GAP_SECS)?;
+        assert_eq!(info.tail_number.as_deref(), Some("N12345"));
+        assert_eq!(
+            info.metadata.get("airframe_name").map(|s| s.as_str()),
+            Some("Mooney M20J")
+        );
+        assert!(info
+            .columns
+            .iter()
+            .any(|c| c.stats.name == "IAS" && c.unit.as_deref() == Some("kt")));
+        assert!(info.start < info.end);
+        assert_eq!(info.sample_interval, Some(1.0));
+        let ias = info.columns.iter().find(|c| c.stats.name == "IAS").unwrap();
+        assert_eq!(ias.stats.dtype, "f64");
+        assert!(ias.stats.max.unwrap() > 100.0);
+        let json = serde_json::to_value(&info)?;
+        assert_eq!(json["metadata"]["system_id"], "71D00611C4E8A");
+
+        let config = FDRConfigurationBuilder::default().auto_drefs(true).build();
+        let report = validate(source.as_ref(), &config);
+        assert!(report.is_valid(), "{}", report);
+        Ok(())
+    }
+}
@@ -0,0 +1,286 @@
+//! Export of a flight to KML, for viewing in Google Earth without X-Plane.
+//!
+//! The flight is written as a `gx:Track` with an absolute altitude and the heading, pitch and roll of every sample,
+//! along with line segments colored by phase of flight or altitude. An optional `gx:Tour` flies a chase camera along
+//! the track.
+
+use crate::fdr::{FDRWriteError, FlightDataBlock};
+use crate::track::{self, FlightPhase, TrackPoint, FEET_PER_METER};
+use chrono::SecondsFormat;
+use std::io::Write;
+
+/// How the line segments of a track are colored
+#[derive(Copy, Clone, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum KmlColorMode {
+    /// Color by phase of flight: ground, climb, cruise or descent
+    Phase,
+    /// Color by altitude, from blue at the lowest to red at the highest
+    Altitude,
+}
+
+/// Colors of the altitude bands, lowest first, as KML aabbggrr
+const ALTITUDE_COLORS: [&str; 5] = ["ffff0000", "ffffff00", "ff00ff00", "ff00ffff", "ff0000ff"];
+
+fn phase_color(phase: FlightPhase) -> &'static str {
+    match phase {
+        FlightPhase::Ground => "ff808080",
+        FlightPhase::Climb => "ff00c000",
+        FlightPhase::Cruise => "ffff8000",
+        FlightPhase::Descent => "ff00a5ff",
+    }
+}
+
+/// Escape text for use in XML content
+pub fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Writes a data block as a KML document
+pub struct KmlWriter {
+    name: String,
+    color_by: KmlColorMode,
+    tour: bool,
+    camera_distance: f64,
+}
+
+impl Default for KmlWriter {
+    fn default() -> Self {
+        Self {
+            name: "Flight".to_string(),
+            color_by: KmlColorMode::Phase,
+            tour: false,
+            camera_distance: 150.0,
+        }
+    }
+}
+
+impl KmlWriter {
+    /// The name of the document, such as the tail number and date of the flight
+    pub fn name(mut self, name: String) -> Self {
+        self.name = name;
+        self
+    }
+
+    /// How the line segments of the track are colored
+    pub fn color_by(mut self, color_by: KmlColorMode) -> Self {
+        self.color_by = color_by;
+        self
+    }
+
+    /// If set, include a tour that flies a chase camera along the track
+    pub fn tour(mut self, tour: bool) -> Self {
+        self.tour = tour;
+        self
+    }
+
+    /// The distance of the chase camera behind the aircraft, in meters
+    pub fn camera_distance(mut self, meters: f64) -> Self {
+        self.camera_distance = meters;
+        self
+    }
+
+    pub fn write<W: Write>(&self, block: &FlightDataBlock, writer: &mut W) -> Result<(), FDRWriteError> {
+        let points = track::track_points(block)?;
+
+        writeln!(writer, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+        writeln!(
+            writer,
+            r#"<kml xmlns="http://www.opengis.net/kml/2.2" xmlns:gx="http://www.google.com/kml/ext/2.2">"#
+        )?;
+        writeln!(writer, "<Document>")?;
+        writeln!(writer, "<name>{}</name>", escape_xml(&self.name))?;
+
+        self.write_track(&points, writer)?;
+        self.write_segments(&points, writer)?;
+        if self.tour {
+            self.write_tour(&points, writer)?;
+        }
+
+        writeln!(writer, "</Document>")?;
+        writeln!(writer, "</kml>")?;
+        Ok(())
+    }
+
+    /// Write the track, with the time, position and attitude of every sample
+    fn write_track<W: Write>(&self, points: &[TrackPoint], writer: &mut W) -> Result<(), FDRWriteError> {
+        writeln!(writer, "<Placemark id=\"track\">")?;
+        writeln!(writer, "<name>{}</name>", escape_xml(&self.name))?;
+        writeln!(writer, "<gx:Track>")?;
+        writeln!(writer, "<altitudeMode>absolute</altitudeMode>")?;
+        for p in points {
+            writeln!(
+                writer,
+                "<when>{}</when>",
+                p.time.to_rfc3339_opts(SecondsFormat::AutoSi, true)
+            )?;
+        }
+        for p in points {
+            writeln!(
+                writer,
+                "<gx:coord>{} {} {:.1}</gx:coord>",
+                p.longitude,
+                p.latitude,
+                p.altitude / FEET_PER_METER
+            )?;
+        }
+        // the tilt of a model is its pitch, 0 in level flight
+        for p in points {
+            writeln!(writer, "<gx:angles>{} {} {}</gx:angles>", p.heading, p.pitch, p.roll)?;
+        }
+        writeln!(writer, "</gx:Track>")?;
+        writeln!(writer, "</Placemark>")?;
+        Ok(())
+    }
+
+    /// Write the track as line segments, colored by phase of flight or altitude
+    fn write_segments<W: Write>(&self, points: &[TrackPoint], writer: &mut W) -> Result<(), FDRWriteError> {
+        let (styles, classes): (Vec<(String, &str)>, Vec<usize>) = match self.color_by {
+            KmlColorMode::Phase => {
+                let phases = [
+                    FlightPhase::Ground,
+                    FlightPhase::Climb,
+                    FlightPhase::Cruise,
+                    FlightPhase::Descent,
+                ];
+                let styles = phases.iter().map(|p| (p.name().to_string(), phase_color(*p))).collect();
+                let classes = track::phases(points)
+                    .iter()
+                    .map(|p| phases.iter().position(|c| c == p).unwrap_or(0))
+                    .collect();
+                (styles, classes)
+            }
+            KmlColorMode::Altitude => {
+                let min = points.iter().map(|p| p.altitude).fold(f64::INFINITY, f64::min);
+                let max = points.iter().map(|p| p.altitude).fold(f64::NEG_INFINITY, f64::max);
+                let bands = ALTITUDE_COLORS.len();
+                let band_size = ((max - min) / bands as f64).max(1.0);
+                let styles = ALTITUDE_COLORS
+                    .iter()
+                    .enumerate()
+                    .map(|(i, color)| (format!("{:.0} ft", min + i as f64 * band_size), *color))
+                    .collect();
+                let classes = points
+                    .iter()
+                    .map(|p| (((p.altitude - min) / band_size) as usize).min(bands - 1))
+                    .collect();
+                (styles, classes)
+            }
+        };
+
+        for (i, (_, color)) in styles.iter().enumerate() {
+            writeln!(
+                writer,
+                "<Style id=\"segment{}\"><LineStyle><color>{}</color><width>3</width></LineStyle></Style>",
+                i, color
+            )?;
+        }
+
+        writeln!(writer, "<Folder>")?;
+        writeln!(writer, "<name>Segments</name>")?;
+        for (class, start, len) in track::runs(&classes) {
+            // include the first point of the next segment, so the segments join up
+            let end = (start + len + 1).min(points.len());
+            writeln!(writer, "<Placemark>")?;
+            writeln!(writer, "<name>{}</name>", escape_xml(&styles[class].0))?;
+            writeln!(writer, "<styleUrl>#segment{}</styleUrl>", class)?;
+            writeln!(writer, "<LineString>")?;
+            writeln!(writer, "<altitudeMode>absolute</altitudeMode>")?;
+            write!(writer, "<coordinates>")?;
+            for p in &points[start..end] {
+                write!(
+                    writer,
+                    "{},{},{:.1} ",
+                    p.longitude,
+                    p.latitude,
+                    p.altitude / FEET_PER_METER
+                )?;
+            }
+            writeln!(writer, "</coordinates>")?;
+            writeln!(writer, "</LineString>")?;
+            writeln!(writer, "</Placemark>")?;
+        }
+        writeln!(writer, "</Folder>")?;
+        Ok(())
+    }
+
+    /// Write a tour with a camera that follows behind and above the aircraft
+    fn write_tour<W: Write>(&self, points: &[TrackPoint], writer: &mut W) -> Result<(), FDRWriteError> {
+        // meters per degree of latitude
+        const METERS_PER_DEGREE: f64 = 111_320.0;
+
+        writeln!(writer, "<gx:Tour>")?;
+        writeln!(writer, "<name>Chase camera</name>")?;
+        writeln!(writer, "<gx:Playlist>")?;
+        for (i, p) in points.iter().enumerate() {
+            let duration = if i == 0 { 0.0 } else { p.seconds_since(&points[i - 1]) };
+            let heading = p.heading.to_radians();
+            let latitude = p.latitude - self.camera_distance * heading.cos() / METERS_PER_DEGREE;
+            let longitude = p.longitude
+                - self.camera_distance * heading.sin() / (METERS_PER_DEGREE * p.latitude.to_radians().cos());
+            writeln!(writer, "<gx:FlyTo>")?;
+            writeln!(writer, "<gx:duration>{}</gx:duration>", duration)?;
+            writeln!(writer, "<gx:flyToMode>smooth</gx:flyToMode>")?;
+            writeln!(
+                writer,
+                "<Camera><longitude>{}</longitude><latitude>{}</latitude><altitude>{:.1}</altitude>\
+                 <heading>{}</heading><tilt>80</tilt><roll>{}</roll><altitudeMode>absolute</altitudeMode></Camera>",
+                longitude,
+                latitude,
+                p.altitude / FEET_PER_METER + self.camera_distance * 0.2,
+                p.heading,
+                p.roll
+            )?;
+            writeln!(writer, "</gx:FlyTo>")?;
+        }
+        writeln!(writer, "</gx:Playlist>")?;
+        writeln!(writer, "</gx:Tour>")?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{detection::read_avionics_log, fdr::FDRConfigurationBuilder, AviationLogSourceOption};
+
+    #[test]
+    fn test_kml_writer() -> Result<(), Box<dyn std::error::Error>> {
+        let path = crate::resource_path("log_231104_084813_KPOU.csv");
+        let source = read_avionics_log(&AviationLogSourceOption::Garmin, &path)?;
+        let block = source.data_block(&FDRConfigurationBuilder::default().build())?;
+
+        let mut buffer = Vec::new();
+        KmlWriter::default()
+            .name("N12345 & co".to_string())
+            .tour(true)
+            .write(&block, &mut buffer)?;
+        let kml = String::from_utf8(buffer)?;
+
+        let samples = 3676 - 149;
+        assert_eq!(kml.matches("<when>").count(), samples);
+        assert_eq!(kml.matches("<gx:angles>").count(), samples);
+        assert_eq!(kml.matches("<gx:FlyTo>").count(), samples);
+        assert!(kml.contains("<name>N12345 &amp; co</name>"));
+        assert!(kml.contains("<when>2023-11-04T12:50:01Z</when>"));
+        assert!(kml.contains("<styleUrl>#segment1</styleUrl>"));
+        assert!(kml.trim_end().ends_with("</kml>"));
+
+        // a model in level flight is not tilted
+        let level = TrackPoint {
+            time: chrono::Utc::now(),
+            longitude: -73.9,
+            latitude: 41.6,
+            altitude: 3000.0,
+            heading: 90.0,
+            pitch: 0.0,
+            roll: 0.0,
+        };
+        let mut buffer = Vec::new();
+        KmlWriter::default().write_track(&[level], &mut buffer)?;
+        assert!(String::from_utf8(buffer)?.contains("<gx:angles>90 0 0</gx:angles>"));
+        Ok(())
+    }
+}
@@ -1,8 +1,31 @@
+pub mod acmi;
+pub mod airports;
+pub mod batch;
+pub mod derive;
 pub mod detection;
+pub mod engine;
+pub mod export;
 pub mod fdr;
+pub mod filter;
 pub mod garmin;
+pub mod geojson;
+pub mod gpx;
+pub mod inspect;
+pub mod kml;
+pub mod profile;
+pub mod record;
+pub mod replay;
+pub mod report;
+pub mod split;
+pub mod summary;
+pub mod track;
+pub mod trend;
+pub mod watch;
+pub mod wmm;
+pub mod xplane;
 
-use clap::{Parser, ValueEnum};
+use clap::{Parser, Subcommand, ValueEnum};
+use fdr::{FDRConfiguration, FDRConfigurationBuilder};
 use std::path::PathBuf;
 
 #[doc(hidden)]
@@ -18,8 +41,67 @@ pub fn resource_path(filename: &str) -> std::path::PathBuf {
 /// FDR files may be replayed in X-Plane to visualize flight path and telemetry data. This is useful as a post-flight
 /// debriefing and analysis tool, for creating videos, or for sharing flight data with others.
 #[derive(Parser, Debug)]
-#[command(version, about, long_about)]
+#[command(
+    version,
+    about,
+    long_about,
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true
+)]
 pub struct Args {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    /// Convert avionics logs to FDR files, the default when no command is given
+    #[command(flatten)]
+    pub convert: ConvertArgs,
+}
+
+impl Args {
+    /// The command to run, which is to convert when no command was given
+    pub fn command(self) -> Command {
+        self.command.unwrap_or(Command::Convert(self.convert))
+    }
+}
+
+/// The commands of the xfdr binary
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Convert avionics logs to FDR files (the default command)
+    Convert(ConvertArgs),
+    /// Print the header metadata, logged fields and time span of an avionics log
+    Info(InfoArgs),
+    /// Check that an avionics log can be converted, reporting any problems
+    Validate(ValidateArgs),
+    /// Split an avionics log into one FDR file per segment, separated by gaps in recording
+    Split(SplitArgs),
+    /// Print summary statistics of every field in an avionics log
+    Stats(LogArgs),
+    /// Export the cleaned data of an avionics log as a table, or its track as KML, GPX, GeoJSON or ACMI
+    Export(ExportArgs),
+    /// List the mapping of avionics log fields to X-Plane DREFs
+    Drefs(DrefsArgs),
+    /// Watch a directory and convert avionics logs to FDR files as they appear
+    Watch(WatchArgs),
+    /// Replay an avionics log live to a running X-Plane over UDP
+    Play(PlayArgs),
+    /// Record a running X-Plane over UDP into a Garmin style avionics log
+    Record(RecordArgs),
+    /// Follow an avionics log as it is written, converting new records to FDR or sending them to X-Plane
+    Follow(FollowArgs),
+    /// Summarize the flights of avionics logs as logbook entries
+    Summary(SummaryArgs),
+    /// Write a self-contained HTML debrief of an avionics log, with a map, charts and exceedances
+    Report(ReportArgs),
+    /// Analyze the cylinder temperatures of avionics logs: leaning, GAMI spread, CHT spread and shock cooling
+    Engine(EngineArgs),
+    /// Collect stabilized cruise engine snapshots of avionics logs into a trend dataset per aircraft
+    Trend(TrendArgs),
+}
+
+/// Options that control how an avionics log is converted
+#[derive(clap::Args, Debug, Clone)]
+pub struct ConversionOptions {
     /// The source of the avionics log file, otherwise auto-detect source
     #[arg(short, long, value_enum)]
     pub source: Option<AviationLogSourceOption>,
@@ -32,12 +114,6 @@ pub struct Args {
     #[arg(short, long)]
     pub tail_number: Option<String>,
 
-    /// Path to an avionics log file
-    pub input: PathBuf,
-
-    /// Path to output a FDR file. If not specified, output is written to stdout
-    pub output: Option<PathBuf>,
-
     /// If set, do not ignore unknown data fields in the avionics log
     #[arg(long, default_value = "false")]
     pub strict: bool,
@@ -49,6 +125,432 @@ pub struct Args {
     /// If set, allow data records with null values to be written to the FDR file
     #[arg(long, default_value = "false")]
     pub allow_nulls: bool,
+
+    /// If set, read the avionics log in batches of this many records. Conversions write each batch as it is read, so
+    /// memory use does not grow with the log length, while commands that analyze the whole flight gather the batches.
+    /// The glitch filter needs the whole log, so it can't be used with batches
+    #[arg(long, conflicts_with_all = ["max_speed", "max_attitude_rate", "median_window", "kalman_smoothing"])]
+    pub batch_size: Option<usize>,
+
+    /// What to do with records that do not match the checksum logged with them
+    #[arg(long, value_enum, default_value_t = garmin::LogCheckMode::Drop)]
+    pub logcheck: garmin::LogCheckMode,
+
+    /// If set, write the heading as logged (magnetic) instead of converting it to true heading
+    #[arg(long, default_value = "false")]
+    pub magnetic_heading: bool,
+
+    /// If set, positions implying a ground speed above this many knots from the last good fix are glitches
+    #[arg(long)]
+    pub max_speed: Option<f64>,
+
+    /// If set, pitch, roll or heading changing faster than this many degrees per second are glitches
+    #[arg(long)]
+    pub max_attitude_rate: Option<f64>,
+
+    /// How position and attitude glitches are handled
+    #[arg(long, value_enum, default_value_t = filter::GlitchRepair::Interpolate)]
+    pub glitch_repair: filter::GlitchRepair,
+
+    /// If set, smooth position and attitude with a median filter over this many records
+    #[arg(long)]
+    pub median_window: Option<usize>,
+
+    /// If set, smooth position and attitude with a Kalman smoother, whose measurement noise is this many times its
+    /// process noise. Larger values smooth more
+    #[arg(long)]
+    pub kalman_smoothing: Option<f64>,
+
+    /// Path to an aircraft profile, a JSON file describing how the log of the aircraft is written
+    #[arg(long, value_parser = profile::parse_profile_file)]
+    pub profile: Option<profile::AircraftProfile>,
+
+    /// Path to an airport database in the OurAirports CSV format: a directory with airports.csv and runways.csv, or an
+    /// airports file
+    #[arg(long, value_parser = airports::parse_airports_path)]
+    pub airports: Option<std::sync::Arc<airports::AirportDatabase>>,
+
+    /// Template for the names of files converted from a directory, using {tail}, {date}, {time}, {airport},
+    /// {departure}, {arrival} and {stem}
+    #[arg(long, default_value = batch::DEFAULT_NAME_TEMPLATE)]
+    pub name_template: String,
+}
+
+impl ConversionOptions {
+    /// The writer configuration described by these options
+    pub fn configuration(&self) -> FDRConfiguration {
+        FDRConfigurationBuilder::default()
+            .aircraft_model(self.aircraft.clone())
+            .tail_number_override(self.tail_number.clone())
+            .strict(self.strict)
+            .auto_drefs(self.auto_drefs)
+            .allow_nulls(self.allow_nulls)
+            .true_heading(!self.magnetic_heading)
+            .profile(self.profile.clone().unwrap_or_default())
+            .airports(self.airports.clone())
+            .glitch_filter(
+                filter::GlitchFilter::default()
+                    .max_speed(self.max_speed)
+                    .max_attitude_rate(self.max_attitude_rate)
+                    .median_window(self.median_window)
+                    .kalman_smoothing(self.kalman_smoothing)
+                    .repair(self.glitch_repair),
+            )
+            .build()
+    }
+}
+
+/// Arguments for converting one avionics log, or a directory of them, to FDR files
+#[derive(clap::Args, Debug, Clone)]
+pub struct ConvertArgs {
+    /// Path to an avionics log file, or a directory or glob pattern of log files to convert together
+    // optional only so that other commands may be given instead, clap requires it when converting
+    #[arg(required = true)]
+    pub input: Option<PathBuf>,
+
+    /// Path to output a FDR file, or a directory when converting many logs. If not specified, output is written to
+    /// stdout
+    pub output: Option<PathBuf>,
+
+    #[command(flatten)]
+    pub options: ConversionOptions,
+
+    /// Number of logs to convert in parallel when converting a directory, 0 uses one per CPU
+    #[arg(short, long, default_value = "0")]
+    pub jobs: usize,
+
+    /// If set, convert logs from a directory even when their output file already exists
+    #[arg(long, default_value = "false")]
+    pub overwrite: bool,
+}
+
+/// Arguments for commands that read a single avionics log
+#[derive(clap::Args, Debug, Clone)]
+pub struct LogArgs {
+    /// Path to an avionics log file
+    pub input: PathBuf,
+
+    /// The source of the avionics log file, otherwise auto-detect source
+    #[arg(short, long, value_enum)]
+    pub source: Option<AviationLogSourceOption>,
+
+    /// What to do with records that do not match the checksum logged with them
+    #[arg(long, value_enum, default_value_t = garmin::LogCheckMode::Drop)]
+    pub logcheck: garmin::LogCheckMode,
+}
+
+/// Arguments for inspecting an avionics log
+#[derive(clap::Args, Debug, Clone)]
+pub struct InfoArgs {
+    /// Path to an avionics log file
+    pub input: PathBuf,
+
+    /// The source of the avionics log file, otherwise auto-detect source
+    #[arg(short, long, value_enum)]
+    pub source: Option<AviationLogSourceOption>,
+
+    /// What to do with records that do not match the checksum logged with them
+    #[arg(long, value_enum, default_value_t = garmin::LogCheckMode::Drop)]
+    pub logcheck: garmin::LogCheckMode,
+
+    /// If set, print the information as JSON
+    #[arg(long, default_value = "false")]
+    pub json: bool,
+
+    /// Report a gap when no records were logged for more than this many seconds
+    #[arg(long, default_value_t = inspect::DEFAULT_GAP_SECS)]
+    pub gap_secs: f64,
+}
+
+/// Arguments for validating an avionics log
+#[derive(clap::Args, Debug, Clone)]
+pub struct ValidateArgs {
+    /// Path to an avionics log file
+    pub input: PathBuf,
+
+    #[command(flatten)]
+    pub options: ConversionOptions,
+}
+
+/// Arguments for splitting an avionics log into several FDR files
+#[derive(clap::Args, Debug, Clone)]
+pub struct SplitArgs {
+    /// Path to an avionics log file
+    pub input: PathBuf,
+
+    /// The directory to write the FDR files to
+    pub output: PathBuf,
+
+    #[command(flatten)]
+    pub options: ConversionOptions,
+
+    /// Start a new file when no records were logged for this many seconds
+    #[arg(long, default_value = "300")]
+    pub gap_secs: f64,
+}
+
+/// Arguments for exporting the data of an avionics log
+#[derive(clap::Args, Debug, Clone)]
+pub struct ExportArgs {
+    /// Path to an avionics log file
+    pub input: PathBuf,
+
+    /// Path to output the exported data. If not specified, output is written to stdout
+    pub output: Option<PathBuf>,
+
+    /// The format to export
+    #[arg(short, long, value_enum, default_value = "csv")]
+    pub format: ExportFormat,
+
+    #[command(flatten)]
+    pub options: ConversionOptions,
+
+    /// If set, add columns derived from the track (elapsed time, distance, speeds and phase) to exported tables
+    #[arg(long, default_value = "false")]
+    pub derived: bool,
+
+    /// How to color the track when exporting KML
+    #[arg(long, value_enum, default_value = "phase")]
+    pub color_by: kml::KmlColorMode,
+
+    /// If set, include a chase camera tour of the flight when exporting KML
+    #[arg(long, default_value = "false")]
+    pub tour: bool,
+
+    /// Auxiliary fields to include with each point when exporting GPX or GeoJSON
+    #[arg(long, value_delimiter = ',', default_values_t = export::DEFAULT_AUX_COLUMNS.map(String::from))]
+    pub columns: Vec<String>,
+}
+
+/// Arguments for summarizing flights as logbook entries
+#[derive(clap::Args, Debug, Clone)]
+pub struct SummaryArgs {
+    /// Paths to avionics log files, one flight each
+    #[arg(required = true)]
+    pub inputs: Vec<PathBuf>,
+
+    /// Path to output the summaries, after `--` as the inputs may be many. If not specified, output is written to
+    /// stdout
+    #[arg(last = true)]
+    pub output: Option<PathBuf>,
+
+    /// The format of the summaries
+    #[arg(short, long, value_enum, default_value = "text")]
+    pub format: SummaryFormat,
+
+    #[command(flatten)]
+    pub options: ConversionOptions,
+}
+
+/// Formats that flight summaries can be written as
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+pub enum SummaryFormat {
+    /// A readable summary of each flight
+    Text,
+    /// A JSON array of summaries
+    Json,
+    /// Logbook rows, one per flight
+    Csv,
+}
+
+/// Arguments for writing an HTML debrief report
+#[derive(clap::Args, Debug, Clone)]
+pub struct ReportArgs {
+    /// Path to an avionics log file
+    pub input: PathBuf,
+
+    /// Path to output the report. If not specified, output is written to stdout
+    pub output: Option<PathBuf>,
+
+    #[command(flatten)]
+    pub options: ConversionOptions,
+}
+
+/// Arguments for analyzing the cylinder temperatures of flights
+#[derive(clap::Args, Debug, Clone)]
+pub struct EngineArgs {
+    /// Paths to avionics log files, one flight each
+    #[arg(required = true)]
+    pub inputs: Vec<PathBuf>,
+
+    /// Path to output the analyses, after `--` as the inputs may be many. If not specified, output is written to stdout
+    #[arg(last = true)]
+    pub output: Option<PathBuf>,
+
+    /// The format of the analyses
+    #[arg(short, long, value_enum, default_value = "text")]
+    pub format: EngineFormat,
+
+    #[command(flatten)]
+    pub options: ConversionOptions,
+}
+
+/// Formats that engine analyses can be written as
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+pub enum EngineFormat {
+    /// A readable report of each flight
+    Text,
+    /// A JSON array of reports
+    Json,
+}
+
+/// Arguments for engine trend monitoring
+#[derive(clap::Args, Debug, Clone)]
+pub struct TrendArgs {
+    /// Paths to avionics log files, directories of them, or glob patterns
+    #[arg(required = true)]
+    pub inputs: Vec<PathBuf>,
+
+    /// Directory of the trend datasets, one per tail number, after the inputs. Snapshots are added to the dataset
+    /// already there
+    #[arg(required = true)]
+    pub output: PathBuf,
+
+    /// The format of the trend datasets
+    #[arg(short, long, value_enum, default_value_t = trend::TrendFormat::Parquet)]
+    pub format: trend::TrendFormat,
+
+    #[command(flatten)]
+    pub options: ConversionOptions,
+}
+
+/// Arguments for listing DREF mappings
+#[derive(clap::Args, Debug, Clone)]
+pub struct DrefsArgs {
+    /// If given, show how each field of this avionics log maps to a DREF
+    pub input: Option<PathBuf>,
+
+    /// The source whose mapping to list, otherwise auto-detect the source of the input, or Garmin
+    #[arg(short, long, value_enum)]
+    pub source: Option<AviationLogSourceOption>,
+
+    /// What to do with records that do not match the checksum logged with them
+    #[arg(long, value_enum, default_value_t = garmin::LogCheckMode::Drop)]
+    pub logcheck: garmin::LogCheckMode,
+
+    /// Path to an aircraft profile, giving the engines and cylinders to list and DREFs of enum fields
+    #[arg(long, value_parser = profile::parse_profile_file)]
+    pub profile: Option<profile::AircraftProfile>,
+}
+
+/// Parse a multiple of real time or a rate named on the command line, which must be a positive number
+pub fn parse_positive(value: &str) -> Result<f64, String> {
+    match value.parse::<f64>() {
+        Ok(v) if v.is_finite() && v > 0.0 => Ok(v),
+        Ok(_) => Err(format!("{} is not a positive number", value)),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+/// Arguments for replaying an avionics log to X-Plane
+#[derive(clap::Args, Debug, Clone)]
+pub struct PlayArgs {
+    /// Path to an avionics log file
+    pub input: PathBuf,
+
+    /// The address X-Plane listens on for UDP commands
+    #[arg(long, default_value = "127.0.0.1:49000")]
+    pub host: String,
+
+    /// Replay at this multiple of real time
+    #[arg(long, default_value = "1.0", value_parser = parse_positive)]
+    pub speed: f64,
+
+    #[command(flatten)]
+    pub options: ConversionOptions,
+}
+
+/// Arguments for recording X-Plane into an avionics log
+#[derive(clap::Args, Debug, Clone)]
+pub struct RecordArgs {
+    /// Path to write the Garmin style log to
+    pub output: PathBuf,
+
+    /// The address X-Plane listens on for UDP commands
+    #[arg(long, default_value = "127.0.0.1:49000")]
+    pub host: String,
+
+    /// Samples recorded each second
+    #[arg(long, default_value = "1.0", value_parser = parse_positive)]
+    pub rate: f64,
+
+    /// If set, stop recording after this many seconds
+    #[arg(long, value_parser = parse_positive)]
+    pub duration: Option<f64>,
+
+    /// The tail number written to the log header
+    #[arg(short, long, default_value = "XPLANE")]
+    pub tail_number: String,
+
+    /// The airframe name written to the log header
+    #[arg(long, default_value = "X-Plane")]
+    pub airframe: String,
+}
+
+/// Arguments for following an avionics log as it is written
+#[derive(clap::Args, Debug, Clone)]
+pub struct FollowArgs {
+    /// Path to an avionics log file that is being written
+    pub input: PathBuf,
+
+    /// Path to write the FDR file to, or stdout if omitted
+    #[arg(conflicts_with = "udp")]
+    pub output: Option<PathBuf>,
+
+    /// Send new records to X-Plane at this address, such as "127.0.0.1:49000", instead of writing a FDR file
+    #[arg(long)]
+    pub udp: Option<String>,
+
+    /// Milliseconds between checks for new records
+    #[arg(long, default_value = "1000")]
+    pub poll_ms: u64,
+
+    /// If set, stop once no records have been written for this many seconds
+    #[arg(long)]
+    pub idle_secs: Option<u64>,
+
+    #[command(flatten)]
+    pub options: ConversionOptions,
+}
+
+/// Arguments for watching a directory for new avionics logs
+#[derive(clap::Args, Debug, Clone)]
+pub struct WatchArgs {
+    /// The directory to watch, including its subdirectories
+    pub dir: PathBuf,
+
+    /// The directory to write FDR files and their JSON sidecar files to
+    pub output: PathBuf,
+
+    #[command(flatten)]
+    pub options: ConversionOptions,
+
+    /// Seconds a log must go unchanged before it is considered completely written
+    #[arg(long, default_value = "5")]
+    pub settle_secs: u64,
+
+    /// File recording the logs that have been processed, defaults to a hidden file in the output directory
+    #[arg(long)]
+    pub state_file: Option<PathBuf>,
+}
+
+/// Formats that flight data can be exported to
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+pub enum ExportFormat {
+    /// Every cleaned field of the log as csv, with units in the column names
+    Csv,
+    /// Every cleaned field of the log as Parquet, with units in the column names
+    Parquet,
+    /// Every cleaned field of the log as a JSON object of units and records
+    Json,
+    /// A 3D track for Google Earth
+    Kml,
+    /// A GPX 1.1 track, with auxiliary fields as extensions
+    Gpx,
+    /// A GeoJSON LineString, with auxiliary fields as properties
+    Geojson,
+    /// A Tacview ACMI recording, zipped when the output file name ends in .zip.acmi
+    Acmi,
 }
 
 /// Supported avionics log sources that can be used as command line arguments
@@ -66,10 +568,97 @@ mod tests {
 
     #[test]
     fn test_args_parse() -> Result<(), String> {
-        let args = Args::parse_from(vec![APP_NAME, "input.csv"]);
+        let args = Args::parse_from(vec![APP_NAME, "input.csv"]).convert;
 
-        assert_eq!(args.input.to_str().unwrap(), "input.csv");
+        assert_eq!(args.input.unwrap().to_str().unwrap(), "input.csv");
         assert_eq!(args.output, None);
         Ok(())
     }
+
+    #[test]
+    fn test_args_parse_watch() {
+        let args = Args::parse_from(vec![APP_NAME, "watch", "logs", "fdrs", "--auto-drefs"]);
+
+        match args.command() {
+            Command::Watch(watch) => {
+                assert_eq!(watch.dir.to_str().unwrap(), "logs");
+                assert!(watch.options.auto_drefs);
+            }
+            _ => panic!("expected the watch command"),
+        }
+    }
+
+    #[test]
+    fn test_args_parse_default_command() {
+        let explicit = Args::parse_from(vec![APP_NAME, "convert", "input.csv", "--auto-drefs"]).command();
+        let implicit = Args::parse_from(vec![APP_NAME, "input.csv", "--auto-drefs"]).command();
+
+        for command in [explicit, implicit] {
+            match command {
+                Command::Convert(args) => {
+                    assert_eq!(args.input.unwrap().to_str().unwrap(), "input.csv");
+                    assert!(args.options.auto_drefs);
+                }
+                _ => panic!("expected the convert command"),
+            }
+        }
+    }
+
+    #[test]
+    fn test_args_parse_summary_output() {
+        match Args::parse_from(vec![APP_NAME, "summary", "a.csv", "b.csv", "--", "logbook.csv"]).command() {
+            Command::Summary(summary) => {
+                assert_eq!(summary.inputs.len(), 2);
+                assert_eq!(summary.output.unwrap().to_str().unwrap(), "logbook.csv");
+            }
+            _ => panic!("expected the summary command"),
+        }
+    }
+
+    #[test]
+    fn test_args_parse_trend_output() {
+        match Args::parse_from(vec![APP_NAME, "trend", "a.csv", "b.csv", "trends"]).command() {
+            Command::Trend(trend) => {
+                assert_eq!(trend.inputs.len(), 2);
+                assert_eq!(trend.output.to_str().unwrap(), "trends");
+            }
+            _ => panic!("expected the trend command"),
+        }
+    }
+
+    #[test]
+    fn test_args_parse_engine_output() {
+        match Args::parse_from(vec![APP_NAME, "engine", "a.csv", "b.csv", "--", "engines.txt"]).command() {
+            Command::Engine(engine) => {
+                assert_eq!(engine.inputs.len(), 2);
+                assert_eq!(engine.output.unwrap().to_str().unwrap(), "engines.txt");
+            }
+            _ => panic!("expected the engine command"),
+        }
+    }
+
+    #[test]
+    fn test_args_parse_batch_size_without_filter() {
+        assert!(Args::try_parse_from(vec![APP_NAME, "input.csv", "--batch-size", "500"]).is_ok());
+        assert!(
+            Args::try_parse_from(vec![APP_NAME, "input.csv", "--batch-size", "500", "--max-speed", "250"]).is_err()
+        );
+    }
+
+    #[test]
+    fn test_args_parse_speed_and_rate() {
+        match Args::parse_from(vec![APP_NAME, "play", "input.csv", "--speed", "4"]).command() {
+            Command::Play(play) => assert_eq!(play.speed, 4.0),
+            _ => panic!("expected the play command"),
+        }
+        for speed in ["0", "-2", "inf", "NaN", "fast"] {
+            assert!(Args::try_parse_from(vec![APP_NAME, "play", "input.csv", "--speed", speed]).is_err());
+            assert!(Args::try_parse_from(vec![APP_NAME, "record", "output.csv", "--rate", speed]).is_err());
+            assert!(Args::try_parse_from(vec![APP_NAME, "record", "output.csv", "--duration", speed]).is_err());
+        }
+        match Args::parse_from(vec![APP_NAME, "record", "output.csv", "--duration", "90"]).command() {
+            Command::Record(record) => assert_eq!(record.duration, Some(90.0)),
+            _ => panic!("expected the record command"),
+        }
+    }
 }
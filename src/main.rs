@@ -4,70 +4,666 @@
 //! directory of the X-Plane installation.
 
 use clap::Parser;
+use std::collections::BTreeMap;
+use std::error::Error;
 use std::fs::File;
-use std::io::ErrorKind;
-use xfdr::detection::{detect_source, read_avionics_log};
-use xfdr::fdr::{self, FDRConfigurationBuilder, FDRWriter};
-use xfdr::Args;
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use xfdr::acmi::{AcmiWriter, ACMI_PROPERTIES};
+use xfdr::batch::{self, BatchConverter, BatchOutcome};
+use xfdr::detection::{self, detect_source, open_avionics_log};
+use xfdr::engine::EngineAnalysis;
+use xfdr::export::TableFormat;
+use xfdr::fdr::{self, FDRConfiguration, FDRWriter, FlightDataSource};
+use xfdr::garmin::LogCheckMode;
+use xfdr::geojson::GeoJsonWriter;
+use xfdr::gpx::GpxWriter;
+use xfdr::kml::KmlWriter;
+use xfdr::record::{self, GarminLogWriter, Recorder};
+use xfdr::replay::{Replay, ReplayCommand};
+use xfdr::report::HtmlReportWriter;
+use xfdr::summary::{self, FlightSummary};
+use xfdr::trend::{self, TrendDataset};
+use xfdr::watch::WatchFolder;
+use xfdr::xplane::XPlaneConnection;
+use xfdr::{export, inspect, split};
+use xfdr::{Args, AviationLogSourceOption, Command, ConvertArgs, DrefsArgs, ExportArgs, InfoArgs, LogArgs, SplitArgs};
+use xfdr::{EngineArgs, EngineFormat, ReportArgs, WatchArgs};
+use xfdr::{ExportFormat, FollowArgs, PlayArgs, RecordArgs, SummaryArgs, SummaryFormat, TrendArgs, ValidateArgs};
 
 /// Entrypoint for the xfdr binary
 fn main() {
-    let args = Args::parse();
+    match Args::parse().command() {
+        Command::Convert(args) => convert(args),
+        Command::Info(args) => info(args),
+        Command::Validate(args) => validate(args),
+        Command::Split(args) => split(args),
+        Command::Stats(args) => stats(args),
+        Command::Export(args) => export(args),
+        Command::Drefs(args) => drefs(args),
+        Command::Watch(args) => watch(args),
+        Command::Play(args) => play(args),
+        Command::Record(args) => record(args),
+        Command::Follow(args) => follow(args),
+        Command::Summary(args) => summary(args),
+        Command::Report(args) => report(args),
+        Command::Engine(args) => engine(args),
+        Command::Trend(args) => trend(args),
+    }
+}
+
+/// Print an error message and exit with a failure status
+fn exit_with_error(message: String) -> ! {
+    eprintln!("{}", message);
+    std::process::exit(1);
+}
 
+/// Print to stdout, ignoring broken pipes (created when stdout piped to `head` or `tail` in linux, etc.)
+fn print(value: impl std::fmt::Display) {
+    match write!(std::io::stdout(), "{}", value) {
+        Err(e) if e.kind() == ErrorKind::BrokenPipe => std::process::exit(0),
+        Err(e) => exit_with_error(format!("IO error: {}", e)),
+        Ok(()) => {}
+    }
+}
+
+/// Detect the source of a log if it wasn't provided, then open it
+fn open_log(
+    input: &Path,
+    source: Option<AviationLogSourceOption>,
+    batch_size: Option<usize>,
+    logcheck: LogCheckMode,
+) -> (AviationLogSourceOption, Box<dyn FlightDataSource>) {
     // auto-detect the source if it wasn't provided
-    let source = args.source.unwrap_or_else(|| {
-        detect_source(&args.input).unwrap_or_else(|e| {
-            eprintln!("Unable to detect source: {}", e);
-            std::process::exit(1);
-        })
+    let source = source.unwrap_or_else(|| {
+        detect_source(input).unwrap_or_else(|e| exit_with_error(format!("Unable to detect source: {}", e)))
     });
 
-    // read the avionics log file into a data structure
-    let data = read_avionics_log(&source, &args.input).unwrap_or_else(|e| {
-        eprintln!("Unable to read avionics log: {}", e);
-        std::process::exit(1);
-    });
+    // read the avionics log file into a data structure, or open it for reading in batches
+    let data = open_avionics_log(&source, input, batch_size, logcheck)
+        .unwrap_or_else(|e| exit_with_error(format!("Unable to read avionics log: {}", e)));
+    (source, data)
+}
 
-    // config tells the writer how to format the output
-    let config = FDRConfigurationBuilder::default()
-        .aircraft_model(args.aircraft)
-        .tail_number_override(args.tail_number)
-        .strict(args.strict)
-        .auto_drefs(args.auto_drefs)
-        .allow_nulls(args.allow_nulls)
-        .build();
-
-    // open the output file for writing
-    let mut output: Box<dyn std::io::Write> = args.output.as_ref().map_or_else(
-        || Box::new(std::io::stdout()) as Box<dyn std::io::Write>,
+/// Open the output file for writing, or stdout if there is none
+fn open_output(output: Option<&PathBuf>) -> Box<dyn Write> {
+    output.map_or_else(
+        || Box::new(std::io::stdout()) as Box<dyn Write>,
         |p| {
-            Box::new(File::create(&p).unwrap_or_else(|e| {
-                eprintln!("Unable to create output file: {}", e);
-                std::process::exit(1);
-            }))
+            Box::new(
+                File::create(p).unwrap_or_else(|e| exit_with_error(format!("Unable to create output file: {}", e))),
+            )
         },
+    )
+}
+
+/// Handle an error while writing output, ignoring broken pipes when writing to stdout
+fn handle_write_error(e: fdr::FDRWriteError, to_stdout: bool) {
+    use fdr::FDRWriteError;
+    match e {
+        FDRWriteError::IO(ref e) if to_stdout && e.kind() == ErrorKind::BrokenPipe => {
+            // ignore broken pipe errors (created when stdout piped to `head` or `tail` in linux, etc.)
+            std::process::exit(0);
+        }
+        FDRWriteError::IO(e) => exit_with_error(format!("IO error: {}", e)),
+        FDRWriteError::Polars(e) => exit_with_error(format!("Data handling error: {}", e)),
+        FDRWriteError::FlightDataError(err) => exit_with_error(format!("Flight data error: {}", err)),
+    }
+}
+
+/// Convert an avionics log, or a directory of them, to FDR
+fn convert(args: ConvertArgs) {
+    // config tells the writer how to format the output
+    let config = args.options.configuration();
+    let input = args.input.clone().expect("clap requires an input when converting");
+
+    if batch::is_batch_input(&input) {
+        convert_batch(&input, &args, config);
+        return;
+    }
+
+    let (_, data) = open_log(
+        &input,
+        args.options.source,
+        args.options.batch_size,
+        args.options.logcheck,
     );
+    let mut output = open_output(args.output.as_ref());
+
+    // enum names without a number are written as a default, which the user may want to add to a profile
+    if config.auto_drefs {
+        if let Some(unknown) = data.unknown_enum_values(&config).filter(|u| !u.is_empty()) {
+            eprintln!(
+                "Warning: enum values without a number are written as 0 or the profile's default: {}",
+                unknown
+            );
+        }
+    }
 
     // write the FDR file or handle errors
     if let Err(e) = FDRWriter::new(config).write(data, &mut output) {
-        use fdr::FDRWriteError;
-        match e {
-            FDRWriteError::IO(ref e) if (args.output.is_none()) && e.kind() == ErrorKind::BrokenPipe => {
-                // ignore broken pipe errors (created when stdout piped to `head` or `tail` in linux, etc.)
-                std::process::exit(0);
+        handle_write_error(e, args.output.is_none());
+    }
+}
+
+/// Convert every log in a directory or matching a glob pattern into the output directory, then print a summary
+fn convert_batch(input: &Path, args: &ConvertArgs, config: FDRConfiguration) {
+    let Some(output_dir) = args.output.clone() else {
+        exit_with_error("An output directory is required when converting many logs".to_string());
+    };
+
+    let inputs = batch::collect_inputs(input)
+        .unwrap_or_else(|e| exit_with_error(format!("Unable to find avionics logs: {}", e)));
+
+    let summary = BatchConverter::new(config, output_dir)
+        .source(args.options.source)
+        .name_template(args.options.name_template.clone())
+        .jobs(args.jobs)
+        .overwrite(args.overwrite)
+        .batch_size(args.options.batch_size)
+        .logcheck(args.options.logcheck)
+        .convert(&inputs)
+        .unwrap_or_else(|e| exit_with_error(format!("Unable to convert avionics logs: {}", e)));
+
+    println!("{}", summary);
+    if summary.failed() > 0 {
+        std::process::exit(1);
+    }
+}
+
+/// Print the metadata and fields of a log
+fn info(args: InfoArgs) {
+    let (_, data) = open_log(&args.input, args.source, None, args.logcheck);
+    let info = inspect::LogInfo::from_source(data.as_ref(), args.gap_secs)
+        .unwrap_or_else(|e| exit_with_error(format!("Unable to inspect avionics log: {}", e)));
+    if args.json {
+        let json = serde_json::to_string_pretty(&info)
+            .unwrap_or_else(|e| exit_with_error(format!("Unable to format information: {}", e)));
+        print(json + "\n");
+    } else {
+        print(info);
+    }
+}
+
+/// Check that a log can be converted
+fn validate(args: ValidateArgs) {
+    let (_, data) = open_log(
+        &args.input,
+        args.options.source,
+        args.options.batch_size,
+        args.options.logcheck,
+    );
+    let report = inspect::validate(data.as_ref(), &args.options.configuration());
+    println!("{}", report);
+    if !report.is_valid() {
+        std::process::exit(1);
+    }
+}
+
+/// Summarize the flights of logs as logbook entries
+fn summary(args: SummaryArgs) {
+    let config = args.options.configuration();
+    let summaries: Vec<FlightSummary> = args
+        .inputs
+        .iter()
+        .map(|input| {
+            let (_, data) = open_log(
+                input,
+                args.options.source,
+                args.options.batch_size,
+                args.options.logcheck,
+            );
+            let mut summary = FlightSummary::from_source(data.as_ref(), &config)
+                .unwrap_or_else(|e| exit_with_error(format!("Unable to summarize {}: {}", input.display(), e)));
+            if args.options.tail_number.is_some() {
+                summary.tail_number = args.options.tail_number.clone();
             }
-            FDRWriteError::IO(e) => {
-                eprintln!("IO error: {}", e);
-                std::process::exit(1);
+            summary
+        })
+        .collect();
+
+    let mut output = open_output(args.output.as_ref());
+    let result = match args.format {
+        SummaryFormat::Text => summaries.iter().try_for_each(|s| writeln!(output, "{}", s)),
+        SummaryFormat::Json => serde_json::to_writer_pretty(&mut output, &summaries)
+            .map_err(std::io::Error::from)
+            .and_then(|_| writeln!(output)),
+        SummaryFormat::Csv => summary::write_logbook_csv(&summaries, &mut output),
+    };
+    if let Err(e) = result {
+        handle_write_error(e.into(), args.output.is_none());
+    }
+}
+
+/// Write an HTML debrief report of a log
+fn report(args: ReportArgs) {
+    let config = args.options.configuration();
+    let (_, data) = open_log(
+        &args.input,
+        args.options.source,
+        args.options.batch_size,
+        args.options.logcheck,
+    );
+    let name = match data.timestamp() {
+        Some(t) => format!("{} {}", config.tail_number(data.as_ref()), t.format("%Y-%m-%d %H:%M")),
+        None => config.tail_number(data.as_ref()),
+    };
+    let units = export::converted_columns(data.as_ref(), &config.profile);
+    let writer = HtmlReportWriter::default()
+        .name(name)
+        .limits(&config.profile)
+        .units(units.clone());
+
+    let converted: Vec<&str> = units.iter().map(|(name, _)| name.as_str()).collect();
+    let block = writer
+        .columns(&converted)
+        .and_then(|columns| export::export_block(data.as_ref(), &config, &columns))
+        .unwrap_or_else(|e| exit_with_error(format!("Flight data error: {}", e)));
+    let mut output = open_output(args.output.as_ref());
+    if let Err(e) = writer.write(&block, &mut output) {
+        handle_write_error(e, args.output.is_none());
+    }
+}
+
+/// Analyze the cylinder temperatures of logs
+fn engine(args: EngineArgs) {
+    let config = args.options.configuration();
+    let analyses: Vec<EngineAnalysis> = args
+        .inputs
+        .iter()
+        .map(|input| {
+            let (_, data) = open_log(
+                input,
+                args.options.source,
+                args.options.batch_size,
+                args.options.logcheck,
+            );
+            EngineAnalysis::from_source(data.as_ref(), &config)
+                .unwrap_or_else(|e| exit_with_error(format!("Unable to analyze {}: {}", input.display(), e)))
+        })
+        .collect();
+
+    let mut output = open_output(args.output.as_ref());
+    let result = match args.format {
+        EngineFormat::Text => analyses.iter().try_for_each(|a| writeln!(output, "{}", a)),
+        EngineFormat::Json => serde_json::to_writer_pretty(&mut output, &analyses)
+            .map_err(std::io::Error::from)
+            .and_then(|_| writeln!(output)),
+    };
+    if let Err(e) = result {
+        handle_write_error(e.into(), args.output.is_none());
+    }
+}
+
+/// Add the cruise snapshots of logs to the trend dataset of each aircraft, printing the anomalies of new snapshots
+fn trend(args: TrendArgs) {
+    let config = args.options.configuration();
+    let mut inputs = Vec::new();
+    for input in &args.inputs {
+        if batch::is_batch_input(input) {
+            inputs.extend(batch::collect_inputs(input).unwrap_or_else(|e| exit_with_error(format!("{}", e))));
+        } else {
+            inputs.push(input.clone());
+        }
+    }
+
+    // a log that can't be read is skipped, so one bad file doesn't stop the rest of the trend
+    let mut snapshots: BTreeMap<String, Vec<trend::CruiseSnapshot>> = BTreeMap::new();
+    for input in &inputs {
+        let log = input
+            .file_name()
+            .map_or_else(String::new, |n| n.to_string_lossy().to_string());
+        let result = args
+            .options
+            .source
+            .map_or_else(|| detect_source(input), Ok)
+            .map_err(Box::<dyn Error>::from)
+            .and_then(|source| open_avionics_log(&source, input, args.options.batch_size, args.options.logcheck))
+            .and_then(|data| {
+                let found = trend::cruise_snapshots(data.as_ref(), &log)?;
+                Ok((config.tail_number(data.as_ref()), found))
+            });
+        match result {
+            Ok((tail_number, found)) => snapshots.entry(tail_number).or_default().extend(found),
+            Err(e) => eprintln!("Skipping {}: {}", input.display(), e),
+        }
+    }
+
+    if let Err(e) = std::fs::create_dir_all(&args.output) {
+        exit_with_error(format!("Unable to create output directory: {}", e));
+    }
+    for (tail_number, found) in snapshots {
+        let path = args.output.join(format!(
+            "{}.{}",
+            batch::sanitize_file_name(&tail_number),
+            args.format.extension()
+        ));
+        let mut dataset = if path.exists() {
+            TrendDataset::load(tail_number.clone(), &path, args.format)
+                .unwrap_or_else(|e| exit_with_error(format!("Unable to read {}: {}", path.display(), e)))
+        } else {
+            TrendDataset::new(tail_number.clone())
+        };
+        let added: Vec<_> = found.iter().map(|s| (s.log.clone(), s.start)).collect();
+        dataset.merge(found);
+
+        let mut output = open_output(Some(&path));
+        if let Err(e) = dataset.write(args.format, &mut output) {
+            handle_write_error(e, false);
+        }
+        println!(
+            "{}: {} new cruise snapshots, {} in {}",
+            tail_number,
+            added.len(),
+            dataset.snapshots.len(),
+            path.display()
+        );
+        for (snapshot, anomalies) in dataset.snapshots.iter().zip(dataset.anomalies()) {
+            if added.contains(&(snapshot.log.clone(), snapshot.start)) {
+                anomalies.iter().for_each(|a| println!("  {}", a));
             }
-            FDRWriteError::Polars(e) => {
-                eprintln!("Data handling error: {}", e);
-                std::process::exit(1);
+        }
+    }
+}
+
+/// Split a log into several FDR files
+fn split(args: SplitArgs) {
+    let config = args.options.configuration();
+    let (_, data) = open_log(
+        &args.input,
+        args.options.source,
+        args.options.batch_size,
+        args.options.logcheck,
+    );
+    let block = data
+        .data_block(&config)
+        .unwrap_or_else(|e| exit_with_error(format!("Flight data error: {}", e)));
+
+    let stem = args.input.file_stem().and_then(|s| s.to_str()).unwrap_or("flight");
+    let tail_number = config.tail_number(data.as_ref());
+    let writer = FDRWriter::new(config);
+    match split::write_segments(&writer, &tail_number, &block, args.gap_secs, &args.output, stem) {
+        Ok(paths) => paths.iter().for_each(|p| println!("{}", p.display())),
+        Err(e) => handle_write_error(e, false),
+    }
+}
+
+/// Print summary statistics of a log
+fn stats(args: LogArgs) {
+    let (_, data) = open_log(&args.input, args.source, None, args.logcheck);
+    let df = data
+        .data()
+        .unwrap_or_else(|e| exit_with_error(format!("Flight data error: {}", e)));
+    print(inspect::StatsTable(&inspect::column_stats(&df)));
+}
+
+/// Export the cleaned data of a log
+fn export(args: ExportArgs) {
+    let config = args.options.configuration();
+    let (_, data) = open_log(
+        &args.input,
+        args.options.source,
+        args.options.batch_size,
+        args.options.logcheck,
+    );
+    let mut output = open_output(args.output.as_ref());
+
+    let name = match data.timestamp() {
+        Some(t) => format!("{} {}", config.tail_number(data.as_ref()), t.format("%Y-%m-%d %H:%M")),
+        None => config.tail_number(data.as_ref()),
+    };
+    let block = || {
+        export::export_block(data.as_ref(), &config, &args.columns)
+            .unwrap_or_else(|e| exit_with_error(format!("Flight data error: {}", e)))
+    };
+
+    let result = match args.format {
+        ExportFormat::Csv => export::write_table(
+            data.as_ref(),
+            TableFormat::Csv,
+            args.derived,
+            &config.profile,
+            &mut output,
+        ),
+        ExportFormat::Parquet => export::write_table(
+            data.as_ref(),
+            TableFormat::Parquet,
+            args.derived,
+            &config.profile,
+            &mut output,
+        ),
+        ExportFormat::Json => export::write_table(
+            data.as_ref(),
+            TableFormat::Json,
+            args.derived,
+            &config.profile,
+            &mut output,
+        ),
+        ExportFormat::Kml => KmlWriter::default()
+            .name(name)
+            .color_by(args.color_by)
+            .tour(args.tour)
+            .write(&block().block, &mut output),
+        ExportFormat::Gpx => GpxWriter::default()
+            .name(name)
+            .aux_columns(args.columns.clone())
+            .write(&block(), &mut output),
+        ExportFormat::Geojson => GeoJsonWriter::default()
+            .name(name)
+            .aux_columns(args.columns.clone())
+            .write(&block(), &mut output),
+        ExportFormat::Acmi => {
+            let columns: Vec<String> = ACMI_PROPERTIES.iter().map(|p| p.0.to_string()).collect();
+            let block = export::export_block(data.as_ref(), &config, &columns)
+                .unwrap_or_else(|e| exit_with_error(format!("Flight data error: {}", e)));
+            let metadata = data.metadata();
+            let writer = AcmiWriter::default()
+                .title(name)
+                .tail_number(config.tail_number(data.as_ref()))
+                .airframe(metadata.get("airframe_name").cloned())
+                .recorder(metadata.get("Product").cloned());
+
+            let zipped = args
+                .output
+                .as_ref()
+                .and_then(|p| p.to_str())
+                .is_some_and(|p| p.ends_with(".zip.acmi"));
+            if zipped {
+                let mut buffer = std::io::Cursor::new(Vec::new());
+                writer
+                    .write_zip(&block, &mut buffer)
+                    .and_then(|_| Ok(output.write_all(buffer.get_ref())?))
+            } else {
+                writer.write(&block, &mut output)
             }
-            FDRWriteError::FlightDataError(err) => {
-                eprintln!("Flight data error: {}", err);
-                std::process::exit(1);
+        }
+    };
+    if let Err(e) = result {
+        handle_write_error(e, args.output.is_none());
+    }
+}
+
+/// List the DREF mapping of a source, or of the fields of a log
+fn drefs(args: DrefsArgs) {
+    let Some(input) = args.input else {
+        let profile = args.profile.unwrap_or_default();
+        let map = detection::dref_map(&args.source.unwrap_or(AviationLogSourceOption::Garmin), &profile);
+        for (name, dref) in map {
+            print(format!("{:<12} {} (scale {})\n", name, dref.path, dref.scale));
+        }
+        return;
+    };
+
+    let (source, data) = open_log(&input, args.source, None, args.logcheck);
+    let df = data
+        .data()
+        .unwrap_or_else(|e| exit_with_error(format!("Flight data error: {}", e)));
+    let columns: Vec<&str> = df.get_column_names().iter().map(|n| n.as_str()).collect();
+    let map = detection::column_drefs(&source, &columns, &data.columns(), &args.profile.unwrap_or_default());
+    for name in df.get_column_names().iter().skip(7) {
+        match map.get(name.as_str()) {
+            Some(dref) => print(format!("{:<12} {} (scale {})\n", name, dref.path, dref.scale)),
+            None => print(format!("{:<12} (unmapped)\n", name)),
+        }
+    }
+}
+
+/// Watch a directory and convert avionics logs as they finish being written
+fn watch(args: WatchArgs) {
+    let mut folder = WatchFolder::new(args.dir.clone(), args.output.clone(), args.options.configuration())
+        .map(|f| {
+            f.settle(std::time::Duration::from_secs(args.settle_secs))
+                .source(args.options.source)
+                .name_template(args.options.name_template.clone())
+                .batch_size(args.options.batch_size)
+                .logcheck(args.options.logcheck)
+        })
+        .and_then(|f| match args.state_file.clone() {
+            Some(path) => f.state_file(path),
+            None => Ok(f),
+        })
+        .unwrap_or_else(|e| exit_with_error(format!("Unable to watch {}: {}", args.dir.display(), e)));
+
+    eprintln!("Watching {} for avionics logs", args.dir.display());
+    let result = folder.run(|input, outcome| match outcome {
+        BatchOutcome::Converted(output) => println!("converted {} -> {}", input.display(), output.display()),
+        BatchOutcome::Skipped(output) => println!("skipped   {} ({} exists)", input.display(), output.display()),
+        BatchOutcome::Failed(err) => eprintln!("failed    {}: {}", input.display(), err),
+    });
+
+    if let Err(e) = result {
+        exit_with_error(format!("Stopped watching {}: {}", args.dir.display(), e));
+    }
+}
+
+/// Replay a log to X-Plane, with controls typed in the terminal
+fn play(args: PlayArgs) {
+    let config = args.options.configuration();
+    let (_, data) = open_log(
+        &args.input,
+        args.options.source,
+        args.options.batch_size,
+        args.options.logcheck,
+    );
+    let block = data
+        .data_block(&config)
+        .unwrap_or_else(|e| exit_with_error(format!("Flight data error: {}", e)));
+    let mut replay = Replay::new(&block)
+        .unwrap_or_else(|e| exit_with_error(format!("Flight data error: {}", e)))
+        .speed(args.speed);
+    let connection = XPlaneConnection::new(args.host.as_str())
+        .unwrap_or_else(|e| exit_with_error(format!("Unable to connect to {}: {}", args.host, e)));
+
+    // read commands from the terminal without blocking the replay
+    let (tx, rx) = std::sync::mpsc::channel();
+    std::thread::spawn(move || {
+        for line in std::io::stdin().lines().map_while(Result::ok) {
+            match ReplayCommand::parse(&line) {
+                Some(command) if tx.send(command).is_err() => break,
+                Some(_) => {}
+                None => eprintln!("\nUnknown command: use enter or p to pause, +N/-N to seek, xN for speed, q to quit"),
+            }
+        }
+    });
+
+    eprintln!(
+        "Replaying to {} (enter or p: pause, +N/-N: seek seconds, xN: speed, q: quit)",
+        args.host
+    );
+    let fmt_time = |secs: f64| {
+        format!(
+            "{:02}:{:02}:{:02}",
+            secs as u64 / 3600,
+            secs as u64 / 60 % 60,
+            secs as u64 % 60
+        )
+    };
+    let result = replay.run(&connection, &rx, |status| {
+        eprint!(
+            "\r{} / {}  x{:<5} {:<7}",
+            fmt_time(status.position),
+            fmt_time(status.duration),
+            status.speed,
+            if status.paused { "paused" } else { "" }
+        );
+    });
+    eprintln!();
+    if let Err(e) = result {
+        exit_with_error(format!("Unable to send to X-Plane: {}", e));
+    }
+}
+
+/// Follow a log as it is written, writing new records to a FDR file or sending them to X-Plane as they arrive
+fn follow(args: FollowArgs) {
+    let config = args.options.configuration();
+    let source = args.options.source.unwrap_or_else(|| {
+        detect_source(&args.input).unwrap_or_else(|e| exit_with_error(format!("Unable to detect source: {}", e)))
+    });
+    let data = detection::follow_avionics_log(
+        &source,
+        &args.input,
+        std::time::Duration::from_millis(args.poll_ms),
+        args.idle_secs.map(std::time::Duration::from_secs),
+        args.options.logcheck,
+    )
+    .unwrap_or_else(|e| exit_with_error(format!("Unable to read avionics log: {}", e)));
+
+    let Some(host) = args.udp else {
+        let mut output = open_output(args.output.as_ref());
+        if let Err(e) = FDRWriter::new(config).write(data, &mut output) {
+            handle_write_error(e, args.output.is_none());
+        }
+        return;
+    };
+
+    let connection = XPlaneConnection::new(host.as_str())
+        .unwrap_or_else(|e| exit_with_error(format!("Unable to connect to {}: {}", host, e)));
+    eprintln!("Following {} to {}", args.input.display(), host);
+    for (n, block) in data.data_blocks(&config).enumerate() {
+        let block = block.unwrap_or_else(|e| exit_with_error(format!("Flight data error: {}", e)));
+        let replay = match Replay::new(&block) {
+            Ok(replay) => replay,
+            // none of the new records have a position yet
+            Err(fdr::FlightDataError::InsufficientData) => continue,
+            Err(e) => exit_with_error(format!("Flight data error: {}", e)),
+        };
+        // the records logged before following are skipped to the latest, and new records are sent at the pace they
+        // were logged
+        let start = if n == 0 { replay.frames() - 1 } else { 0 };
+        if let Err(e) = replay.send_frames(&connection, start) {
+            exit_with_error(format!("Unable to send to X-Plane: {}", e));
+        }
+    }
+}
+
+/// Record X-Plane into a Garmin style log until the duration passes or q is typed
+fn record(args: RecordArgs) {
+    let connection = XPlaneConnection::new(args.host.as_str())
+        .unwrap_or_else(|e| exit_with_error(format!("Unable to connect to {}: {}", args.host, e)));
+    let file =
+        File::create(&args.output).unwrap_or_else(|e| exit_with_error(format!("Unable to create output file: {}", e)));
+    let mut writer = GarminLogWriter::new(std::io::BufWriter::new(file), record::default_fields());
+    if let Err(e) = writer.write_header(&args.airframe, &args.tail_number) {
+        exit_with_error(format!("Unable to write {}: {}", args.output.display(), e));
+    }
+
+    let (tx, rx) = std::sync::mpsc::channel();
+    std::thread::spawn(move || {
+        for line in std::io::stdin().lines().map_while(Result::ok) {
+            if line.trim() == "q" {
+                let _ = tx.send(());
+                break;
             }
         }
+    });
+
+    eprintln!("Recording {} to {} (q: stop)", args.host, args.output.display());
+    let recorder = Recorder::default().rate(args.rate).duration(args.duration.map(|secs| {
+        std::time::Duration::try_from_secs_f64(secs)
+            .unwrap_or_else(|e| exit_with_error(format!("Invalid duration: {}", e)))
+    }));
+    match recorder.run(&connection, &mut writer, &rx) {
+        Ok(samples) => eprintln!("Recorded {} samples", samples),
+        Err(e) => exit_with_error(format!("Unable to record from X-Plane: {}", e)),
     }
 }
@@ -0,0 +1,273 @@
+//! Aircraft profiles, which describe how the log of a particular aircraft is written to a FDR file.
+//!
+//! A profile is a JSON file. It gives the type of the engines, `piston` or `turbine`, and the number of engines and
+//! cylinders whose columns are written to engine datarefs, such as `E2 CHT3` for the third cylinder of the second
+//! engine. Its `derive` names the parameters computed from the logged columns, see [Derivation]. Its `enums` translate
+//! the names logged in enum columns into the numbers their datarefs expect, adding to or replacing the translations
+//! built into a source, may map the column to a dataref, and may give the number written for names they lack. Its
+//! `limits` give the range of a column, or of every column matching an engine pattern such as `E{n} CHT{m}`, outside
+//! which a report marks an exceedance:
+//!
+//! ```json
+//! {
+//!   "name": "Mooney M20J",
+//!   "engine_type": "piston",
+//!   "engines": 1,
+//!   "cylinders": 4,
+//!   "derive": ["angle_of_attack", "fuel_burned"],
+//!   "enums": {
+//!     "HSIS": { "values": { "GPS1": 2, "NAV1": 0 } },
+//!     "GPSfix": { "dref": "sim/cockpit2/radios/indicators/gps_fix_type", "values": { "3D": 3, "3DDiff": 4 } }
+//!   },
+//!   "limits": {
+//!     "IAS": { "max": 174 },
+//!     "E{n} CHT{m}": { "max": 500 }
+//!   }
+//! }
+//! ```
+
+use crate::derive::Derivation;
+use polars::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, error::Error, fmt::Display, path::Path};
+
+/// The translation of the names logged in an enum column into numbers
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct EnumTable {
+    /// The dataref the column is written to, if not the one the source maps it to
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dref: Option<String>,
+    /// The number written for each name
+    pub values: BTreeMap<String, f64>,
+    /// The number written for names without one, 0 unless given
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub default: Option<f64>,
+}
+
+impl EnumTable {
+    pub fn new(values: &[(&str, f64)]) -> Self {
+        Self {
+            dref: None,
+            values: values.iter().map(|(name, value)| (name.to_string(), *value)).collect(),
+            default: None,
+        }
+    }
+}
+
+/// The range of values of a column, outside which it is an exceedance
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Limit {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max: Option<f64>,
+}
+
+impl Limit {
+    /// The limit a value exceeds, if it is outside the range
+    pub fn exceeded_by(&self, value: f64) -> Option<f64> {
+        match (self.min, self.max) {
+            (Some(min), _) if value < min => Some(min),
+            (_, Some(max)) if value > max => Some(max),
+            _ => None,
+        }
+    }
+}
+
+/// The type of the engines of an aircraft, which decides the engine columns logged and their units
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EngineType {
+    /// Cylinder temperatures, manifold pressure and RPM, with fuel flow in gallons per hour
+    #[default]
+    Piston,
+    /// ITT, torque and spool speeds of turboprops and jets, with fuel flow in pounds per hour
+    Turbine,
+}
+
+/// A description of how the log of an aircraft is written to a FDR file
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AircraftProfile {
+    /// A name for the aircraft or type the profile describes
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// The type of the engines, otherwise turbine if an engine logs its ITT or torque
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub engine_type: Option<EngineType>,
+    /// The number of engines, whose columns are written to engine datarefs, otherwise every engine logged
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub engines: Option<usize>,
+    /// The number of cylinders of each engine, otherwise the most logged for any engine
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cylinders: Option<usize>,
+    /// Parameters derived from the logged columns and written with them
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub derive: Vec<Derivation>,
+    /// Translations of enum columns, by column name
+    pub enums: BTreeMap<String, EnumTable>,
+    /// Limits of columns, by column name or engine pattern
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub limits: BTreeMap<String, Limit>,
+}
+
+#[derive(Debug)]
+pub enum ProfileError {
+    IO(std::io::Error),
+    Json(serde_json::Error),
+}
+
+impl Error for ProfileError {}
+
+impl Display for ProfileError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ProfileError::IO(e) => write!(f, "IO error: {}", e),
+            ProfileError::Json(e) => write!(f, "Invalid profile: {}", e),
+        }
+    }
+}
+
+impl From<std::io::Error> for ProfileError {
+    fn from(e: std::io::Error) -> Self {
+        ProfileError::IO(e)
+    }
+}
+
+impl From<serde_json::Error> for ProfileError {
+    fn from(e: serde_json::Error) -> Self {
+        ProfileError::Json(e)
+    }
+}
+
+impl AircraftProfile {
+    pub fn load(path: &Path) -> Result<Self, ProfileError> {
+        Ok(serde_json::from_str(&std::fs::read_to_string(path)?)?)
+    }
+
+    /// The enum tables of a source with the profile's applied over them: the profile's names are added to the
+    /// source's, replacing the number of any name both have, and its dataref and default replace the source's
+    pub fn enum_tables(&self, source: BTreeMap<String, EnumTable>) -> BTreeMap<String, EnumTable> {
+        let mut tables = source;
+        for (column, table) in &self.enums {
+            let merged = tables.entry(column.clone()).or_default();
+            if table.dref.is_some() {
+                merged.dref = table.dref.clone();
+            }
+            if table.default.is_some() {
+                merged.default = table.default;
+            }
+            merged.values.extend(table.values.iter().map(|(k, v)| (k.clone(), *v)));
+        }
+        tables
+    }
+}
+
+/// Parse a profile file named on the command line
+pub fn parse_profile_file(path: &str) -> Result<AircraftProfile, String> {
+    AircraftProfile::load(Path::new(path)).map_err(|e| e.to_string())
+}
+
+/// Names logged in enum columns that have no number in their table, with the number of records holding each
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct UnknownEnumValues(pub BTreeMap<String, BTreeMap<String, usize>>);
+
+impl UnknownEnumValues {
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Add the names and records of another, such as those of the next batch of a log
+    pub fn extend(&mut self, other: UnknownEnumValues) {
+        for (column, names) in other.0 {
+            let counts = self.0.entry(column).or_default();
+            for (name, count) in names {
+                *counts.entry(name).or_default() += count;
+            }
+        }
+    }
+}
+
+impl Display for UnknownEnumValues {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let values: Vec<String> = self
+            .0
+            .iter()
+            .flat_map(|(column, names)| {
+                names
+                    .iter()
+                    .map(move |(name, count)| format!("{}={} ({} records)", column, name, count))
+            })
+            .collect();
+        write!(f, "{}", values.join(", "))
+    }
+}
+
+/// Translate the enum columns of a dataframe that have a table into numbers. Names without a number are written as
+/// the table's default, so their records are kept, and are returned with the number of records holding them. Empty
+/// values become nulls.
+pub fn translate_enums(
+    mut df: DataFrame,
+    tables: &BTreeMap<String, EnumTable>,
+) -> PolarsResult<(DataFrame, UnknownEnumValues)> {
+    let mut unknown = UnknownEnumValues::default();
+    for (name, table) in tables {
+        let Ok(column) = df.column(name) else {
+            continue;
+        };
+        if column.dtype() != &DataType::String {
+            continue;
+        }
+        let values: Vec<Option<f64>> = column
+            .str()?
+            .into_iter()
+            .map(|v| {
+                let v = v.filter(|v| !v.is_empty())?;
+                let value = table.values.get(v).copied();
+                if value.is_none() {
+                    *unknown
+                        .0
+                        .entry(name.clone())
+                        .or_default()
+                        .entry(v.to_string())
+                        .or_default() += 1;
+                }
+                Some(value.unwrap_or(table.default.unwrap_or(0.0)))
+            })
+            .collect();
+        df.replace(name, Series::new(name.as_str().into(), values))?;
+    }
+    Ok((df, unknown))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_profile_enum_translation() -> Result<(), Box<dyn std::error::Error>> {
+        let profile: AircraftProfile = serde_json::from_str(
+            r#"{"enums": {"HSIS": {"values": {"VLOC1": 0}, "default": 1}, "GPSfix": {"dref": "sim/fix", "values": {"3D": 3}}}}"#,
+        )?;
+        let source = BTreeMap::from([("HSIS".to_string(), EnumTable::new(&[("GPS1", 2.0), ("VLOC1", 5.0)]))]);
+        let tables = profile.enum_tables(source);
+        assert_eq!(tables["HSIS"].values["VLOC1"], 0.0);
+        assert_eq!(tables["HSIS"].values["GPS1"], 2.0);
+        assert_eq!(tables["GPSfix"].dref.as_deref(), Some("sim/fix"));
+
+        let df = df!(
+            "HSIS" => [Some("GPS1"), Some("VLOC1"), Some("DME"), Some("DME"), None],
+            "GPSfix" => [Some("3D"), Some("NoSoln"), Some("3D"), Some("3D"), Some("")],
+        )?;
+        let (df, unknown) = translate_enums(df, &tables)?;
+        let hsis: Vec<Option<f64>> = df.column("HSIS")?.f64()?.into_iter().collect();
+        // names without a number are written as the default, so their records are kept
+        assert_eq!(hsis, vec![Some(2.0), Some(0.0), Some(1.0), Some(1.0), None]);
+        let fix: Vec<Option<f64>> = df.column("GPSfix")?.f64()?.into_iter().collect();
+        assert_eq!(fix, vec![Some(3.0), Some(0.0), Some(3.0), Some(3.0), None]);
+        assert_eq!(unknown.to_string(), "GPSfix=NoSoln (1 records), HSIS=DME (2 records)");
+        Ok(())
+    }
+}
@@ -0,0 +1,336 @@
+//! Recording of a running X-Plane into a Garmin EIS style log, for comparing sim sessions with real flights.
+//!
+//! The recorder subscribes to the datarefs of the Garmin DREF map, and to the position and attitude of the aircraft,
+//! over X-Plane's RREF UDP output. It samples the latest values at a fixed rate and writes them with the three header
+//! lines of a Garmin log and a LogCheck ending each row, so the recording can be read back like a log from the
+//! aircraft.
+
+use crate::fdr::DataRef;
+use crate::garmin;
+use crate::profile::AircraftProfile;
+use crate::xplane::XPlaneConnection;
+use chrono::{DateTime, Utc};
+use std::{
+    io::Write,
+    sync::mpsc::Receiver,
+    time::{Duration, Instant},
+};
+
+/// A column of a recorded log, its unit and the dataref it is sampled from
+#[derive(Debug, Clone)]
+pub struct RecordedField {
+    pub column: String,
+    pub unit: String,
+    pub dref: DataRef,
+}
+
+/// Garmin units of the columns of the Garmin DREF map
+const GARMIN_UNITS: [(&str, &str); 45] = [
+    ("BaroA", "inch"),
+    ("AltMSL", "ft msl"),
+    ("OAT", "deg C"),
+    ("IAS", "kt"),
+    ("GndSpd", "kt"),
+    ("TAS", "kt"),
+    ("VSpd", "fpm"),
+    ("TRK", "deg"),
+    ("bus1volts", "volts"),
+    ("alt1amps", "amps"),
+    ("FQtyL", "gals"),
+    ("FQtyR", "gals"),
+    ("FQtyLlbs", "lbs"),
+    ("FQtyRlbs", "lbs"),
+    ("LatAc", "G"),
+    ("NormAc", "G"),
+    ("E1 FFlow", "gph"),
+    ("E1 FPres", "psi"),
+    ("E1 OilT", "deg F"),
+    ("E1 OilP", "psi"),
+    ("E1 MAP", "Hg"),
+    ("E1 RPM", "rpm"),
+    ("E1 %Pwr", "%"),
+    ("E1 CHT1", "deg F"),
+    ("E1 CHT2", "deg F"),
+    ("E1 CHT3", "deg F"),
+    ("E1 CHT4", "deg F"),
+    ("E1 EGT1", "deg F"),
+    ("E1 EGT2", "deg F"),
+    ("E1 EGT3", "deg F"),
+    ("E1 EGT4", "deg F"),
+    ("HSIS", "enum"),
+    ("CRS", "deg"),
+    ("NAV1", "MHz"),
+    ("NAV2", "MHz"),
+    ("HCDI", "fsd"),
+    ("VCDI", "fsd"),
+    ("WndSpd", "kt"),
+    ("WndDr", "deg"),
+    ("AfcsOn", "bool"),
+    ("RollM", "enum"),
+    ("PitchM", "enum"),
+    ("RollC", "deg"),
+    ("PitchC", "deg"),
+    ("VSpdG", "fpm"),
+];
+
+/// A value in X-Plane units as Garmin logs it, undoing the scale and translation of its dataref
+fn garmin_value(field: &RecordedField, value: f32) -> String {
+    // X-Plane sends single precision values, so write them at that precision
+    let value = value as f64 / field.dref.scale;
+    if garmin::enum_tables().contains_key(&field.column) {
+        return garmin::enum_name(&field.column, value).unwrap_or_default().to_string();
+    }
+    let offset = garmin::column_offset(&field.column).unwrap_or(0.0);
+    ((value - offset) as f32).to_string()
+}
+
+/// The fields recorded by default: the required fields of a Garmin log, its magnetic variation, and every field of
+/// the Garmin DREF map
+pub fn default_fields() -> Vec<RecordedField> {
+    let field = |column: &str, unit: &str, path: &str| RecordedField {
+        column: column.to_string(),
+        unit: unit.to_string(),
+        dref: DataRef::new(path.to_string()),
+    };
+    let mut fields = vec![
+        field("Latitude", "degrees", "sim/flightmodel/position/latitude"),
+        field("Longitude", "degrees", "sim/flightmodel/position/longitude"),
+        field("AltB", "ft Baro", "sim/cockpit2/gauges/indicators/altitude_ft_pilot"),
+        field("HDG", "deg", "sim/flightmodel/position/mag_psi"),
+        field("Pitch", "deg", "sim/flightmodel/position/theta"),
+        field("Roll", "deg", "sim/flightmodel/position/phi"),
+        field("MagVar", "deg", "sim/flightmodel/position/magnetic_variation"),
+    ];
+    for (column, dref) in garmin::dref_map(&AircraftProfile::default()) {
+        let unit = GARMIN_UNITS.iter().find(|(c, _)| *c == column).map_or("", |(_, u)| u);
+        fields.push(RecordedField {
+            column,
+            unit: unit.to_string(),
+            dref,
+        });
+    }
+    fields
+}
+
+/// Writes samples as a Garmin EIS log
+pub struct GarminLogWriter<W: Write> {
+    writer: W,
+    fields: Vec<RecordedField>,
+}
+
+impl<W: Write> GarminLogWriter<W> {
+    pub fn new(writer: W, fields: Vec<RecordedField>) -> Self {
+        Self { writer, fields }
+    }
+
+    /// Write the metadata, units and column names lines
+    pub fn write_header(&mut self, airframe: &str, tail_number: &str) -> std::io::Result<()> {
+        writeln!(
+            self.writer,
+            "#airframe_info, log_version=\"1.03\", log_content_version=\"1.01\", Product=\"X-Plane\", \
+             airframe_name=\"{}\", tail_number=\"{}\", ",
+            airframe.replace(['"', ','], ""),
+            tail_number.replace(['"', ','], "")
+        )?;
+        let units: Vec<&str> = self.fields.iter().map(|f| f.unit.as_str()).collect();
+        writeln!(self.writer, "#yyy-mm-dd, hh:mm:ss, hh:mm, {}, crc16", units.join(", "))?;
+        let names: Vec<&str> = self.fields.iter().map(|f| f.column.as_str()).collect();
+        writeln!(
+            self.writer,
+            "Lcl Date, Lcl Time, UTCOfst, {}, LogCheck",
+            names.join(", ")
+        )?;
+        self.writer.flush()
+    }
+
+    /// Write a sample of the value of each field, in X-Plane units, converting them to Garmin units
+    pub fn write_row(&mut self, time: &DateTime<Utc>, values: &[Option<f32>]) -> std::io::Result<()> {
+        let mut row = format!("{}, +00:00", time.format("%Y-%m-%d, %H:%M:%S"));
+        for (field, value) in self.fields.iter().zip(values) {
+            match value {
+                Some(v) => row.push_str(&format!(", {}", garmin_value(field, *v))),
+                None => row.push_str(", "),
+            }
+        }
+        // the LogCheck covers the row up to and including the separator before it
+        row.push_str(", ");
+        writeln!(self.writer, "{}{:04X}", row, garmin::crc16(row.as_bytes()))?;
+        // flush every row, so the log is complete up to the last sample if the recorder is stopped abruptly
+        self.writer.flush()
+    }
+}
+
+/// Records a running X-Plane into a Garmin EIS log
+pub struct Recorder {
+    fields: Vec<RecordedField>,
+    rate: f64,
+    duration: Option<Duration>,
+}
+
+impl Default for Recorder {
+    fn default() -> Self {
+        Self {
+            fields: default_fields(),
+            rate: 1.0,
+            duration: None,
+        }
+    }
+}
+
+impl Recorder {
+    /// The fields to record
+    pub fn fields(mut self, fields: Vec<RecordedField>) -> Self {
+        self.fields = fields;
+        self
+    }
+
+    /// The number of samples recorded each second
+    pub fn rate(mut self, rate: f64) -> Self {
+        self.rate = rate;
+        self
+    }
+
+    /// If set, stop recording after this long
+    pub fn duration(mut self, duration: Option<Duration>) -> Self {
+        self.duration = duration;
+        self
+    }
+
+    /// Subscribe to the fields and write samples until the duration passes or a message arrives on `stop`, then
+    /// unsubscribe. Returns the number of samples written.
+    pub fn run<W: Write>(
+        &self,
+        connection: &XPlaneConnection,
+        writer: &mut GarminLogWriter<W>,
+        stop: &Receiver<()>,
+    ) -> std::io::Result<usize> {
+        if !(self.rate.is_finite() && self.rate > 0.0) {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("a rate of {} samples a second is not positive", self.rate),
+            ));
+        }
+        // ask for values twice as often as they are sampled, so each sample is recent
+        let frequency = (self.rate * 2.0).ceil().max(1.0) as i32;
+        for (idx, field) in self.fields.iter().enumerate() {
+            connection.subscribe(frequency, idx as i32, &field.dref.path)?;
+        }
+
+        let result = self.sample(connection, writer, stop);
+
+        for (idx, field) in self.fields.iter().enumerate() {
+            connection.subscribe(0, idx as i32, &field.dref.path)?;
+        }
+        result
+    }
+
+    fn sample<W: Write>(
+        &self,
+        connection: &XPlaneConnection,
+        writer: &mut GarminLogWriter<W>,
+        stop: &Receiver<()>,
+    ) -> std::io::Result<usize> {
+        let interval = Duration::from_secs_f64(1.0 / self.rate);
+        let start = Instant::now();
+        let mut next_sample = start + interval;
+        let mut values: Vec<Option<f32>> = vec![None; self.fields.len()];
+        let mut samples = 0;
+
+        loop {
+            // a closed channel does not stop the recording, so it can run without a terminal
+            if stop.try_recv().is_ok() {
+                return Ok(samples);
+            }
+            if self.duration.is_some_and(|d| start.elapsed() >= d) {
+                return Ok(samples);
+            }
+
+            let now = Instant::now();
+            if now >= next_sample {
+                // nothing is recorded until X-Plane has sent some values
+                if values.iter().any(|v| v.is_some()) {
+                    writer.write_row(&Utc::now(), &values)?;
+                    samples += 1;
+                }
+                next_sample += interval;
+                continue;
+            }
+
+            if let Some(received) = connection.receive_values(next_sample - now)? {
+                for (idx, value) in received {
+                    if let Some(v) = values.get_mut(idx as usize) {
+                        *v = Some(value);
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{detection::read_avionics_log, xplane, AviationLogSourceOption};
+    use std::net::UdpSocket;
+
+    #[test]
+    fn test_record_from_fake_xplane() -> Result<(), Box<dyn std::error::Error>> {
+        let xplane = UdpSocket::bind("127.0.0.1:0")?;
+        xplane.set_read_timeout(Some(Duration::from_secs(5)))?;
+        let connection = XPlaneConnection::new(xplane.local_addr()?)?;
+
+        // X-Plane sends values to the address that subscribed
+        let mut packet = b"RREF,".to_vec();
+        for (idx, value) in [
+            (0, 41.62f32),
+            (1, -73.88),
+            (2, 1500.0),
+            (3, 42.0),
+            (4, 2.0),
+            (5, -1.0),
+            (6, -12.8),
+        ] {
+            packet.extend_from_slice(&i32::to_le_bytes(idx));
+            packet.extend_from_slice(&value.to_le_bytes());
+        }
+        let fields = default_fields();
+        for (column, value) in [("IAS", 110.0f32), ("HSIS", 2.0), ("NormAc", 1.25)] {
+            let idx = fields.iter().position(|f| f.column == column).unwrap();
+            packet.extend_from_slice(&(idx as i32).to_le_bytes());
+            packet.extend_from_slice(&value.to_le_bytes());
+        }
+        xplane.send_to(&packet, connection.local_addr()?)?;
+
+        let file = tempfile::NamedTempFile::new()?;
+        let mut writer = GarminLogWriter::new(file.reopen()?, fields.clone());
+        writer.write_header("Cessna 172", "N172SP")?;
+        let (_stop, stop) = std::sync::mpsc::channel();
+        let recorder = Recorder::default()
+            .rate(20.0)
+            .duration(Some(Duration::from_millis(200)));
+        let samples = recorder.run(&connection, &mut writer, &stop)?;
+        assert!(samples > 0);
+
+        // the recorder subscribed to every field
+        let mut buffer = [0u8; 1024];
+        let len = xplane.recv(&mut buffer)?;
+        assert_eq!(
+            &buffer[..len],
+            xplane::rref_request_packet(40, 0, "sim/flightmodel/position/latitude")
+        );
+
+        // the recording reads back as a Garmin log
+        let log = read_avionics_log(&AviationLogSourceOption::Garmin, file.path())?;
+        assert_eq!(log.tail_number().as_deref(), Some("N172SP"));
+        let data = log.data()?;
+        assert_eq!(data.height(), samples);
+        assert_eq!(log.checksum_report().map(|c| c.invalid()), Some(0));
+        assert_eq!(data.column("IAS")?.f64()?.get(0), Some(110.0));
+        assert_eq!(data.column("AltB")?.f64()?.get(0), Some(1500.0));
+        assert_eq!(data.column("MagVar")?.f64()?.get(0), Some(-12.8));
+        // translated columns are written as Garmin logs them
+        assert_eq!(data.column("HSIS")?.str()?.get(0), Some("GPS"));
+        assert_eq!(data.column("NormAc")?.f64()?.get(0), Some(0.25));
+        Ok(())
+    }
+}
@@ -0,0 +1,287 @@
+//! Live replay of a flight to a running X-Plane over UDP, in real time or at a speed multiplier.
+//!
+//! Each record of a data block is sent as a `VEHX` position packet, followed by a `DREF` packet for every dataref
+//! that has a value. The replay may be paused, sought and sped up while it runs.
+
+use crate::fdr::{DataRef, FlightDataBlock, FlightDataError};
+use crate::track::{self, TrackPoint};
+use crate::xplane::XPlaneConnection;
+use polars::prelude::*;
+use std::{
+    sync::mpsc::{Receiver, RecvTimeoutError},
+    time::{Duration, Instant},
+};
+
+/// A command that controls a running replay
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ReplayCommand {
+    /// Pause a running replay, or resume a paused one
+    TogglePause,
+    /// Move forward, or back when negative, by this many seconds of the flight
+    Seek(f64),
+    /// Replay at this multiple of real time
+    Speed(f64),
+    Quit,
+}
+
+impl ReplayCommand {
+    /// Parse a command typed in the terminal: an empty line or "p" toggles pause, "+30" or "-30" seeks, "x4" sets the
+    /// speed and "q" quits
+    pub fn parse(line: &str) -> Option<Self> {
+        let line = line.trim();
+        match line {
+            "" | "p" => Some(ReplayCommand::TogglePause),
+            "q" => Some(ReplayCommand::Quit),
+            _ if line.starts_with(['+', '-']) => line.parse().ok().map(ReplayCommand::Seek),
+            _ => line
+                .strip_prefix('x')
+                .and_then(|s| s.parse().ok())
+                .filter(|s: &f64| s.is_finite() && *s > 0.0)
+                .map(ReplayCommand::Speed),
+        }
+    }
+}
+
+/// The state of a replay, reported as it runs
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ReplayStatus {
+    /// Seconds since the start of the flight
+    pub position: f64,
+    /// Seconds from the start to the end of the flight
+    pub duration: f64,
+    pub speed: f64,
+    pub paused: bool,
+}
+
+/// One record of a replay
+pub struct ReplayFrame {
+    /// Seconds since the first record
+    pub offset: f64,
+    pub point: TrackPoint,
+    /// The scaled value of each dataref of the replay, if it was logged
+    pub values: Vec<Option<f32>>,
+}
+
+/// Frames of a data block, with every required field, and the scaled values of its datarefs
+pub fn replay_frames(block: &FlightDataBlock) -> Result<Vec<ReplayFrame>, FlightDataError> {
+    let points = track::indexed_track_points(block)?;
+    let columns: Vec<Vec<Option<f64>>> = block
+        .data
+        .get_columns()
+        .iter()
+        .skip(7)
+        .map(|c| {
+            c.cast(&DataType::Float64)
+                .ok()
+                .and_then(|c| c.f64().ok().map(|v| v.into_iter().collect()))
+                .unwrap_or_else(|| vec![None; c.len()])
+        })
+        .collect();
+
+    let Some((_, first)) = points.first().copied() else {
+        return Ok(Vec::new());
+    };
+    Ok(points
+        .into_iter()
+        .map(|(i, point)| ReplayFrame {
+            offset: point.seconds_since(&first),
+            point,
+            values: columns
+                .iter()
+                .zip(&block.drefs)
+                .map(|(values, dref)| values[i].map(|v| (v * dref.scale) as f32))
+                .collect(),
+        })
+        .collect())
+}
+
+/// Replays a data block to X-Plane
+pub struct Replay {
+    drefs: Vec<DataRef>,
+    frames: Vec<ReplayFrame>,
+    speed: f64,
+}
+
+impl Replay {
+    pub fn new(block: &FlightDataBlock) -> Result<Self, FlightDataError> {
+        let frames = replay_frames(block)?;
+        if frames.is_empty() {
+            return Err(FlightDataError::InsufficientData);
+        }
+        Ok(Self {
+            drefs: block.drefs.clone(),
+            frames,
+            speed: 1.0,
+        })
+    }
+
+    /// The multiple of real time to replay at
+    pub fn speed(mut self, speed: f64) -> Self {
+        self.speed = speed;
+        self
+    }
+
+    /// Seconds from the first to the last frame
+    pub fn duration(&self) -> f64 {
+        self.frames.last().map_or(0.0, |f| f.offset)
+    }
+
+    /// The number of frames to replay
+    pub fn frames(&self) -> usize {
+        self.frames.len()
+    }
+
+    /// Send one frame to X-Plane
+    pub fn send_frame(&self, connection: &XPlaneConnection, idx: usize) -> std::io::Result<()> {
+        let frame = &self.frames[idx];
+        connection.send_position(&frame.point)?;
+        for (dref, value) in self.drefs.iter().zip(&frame.values) {
+            if let Some(value) = value {
+                connection.send_dref(&dref.path, *value)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Send the frames from the one at `start` to the last at their times, without handling commands
+    pub fn send_frames(&self, connection: &XPlaneConnection, start: usize) -> std::io::Result<()> {
+        let Some(first) = self.frames.get(start) else {
+            return Ok(());
+        };
+        let begin = Instant::now();
+        for (idx, frame) in self.frames.iter().enumerate().skip(start) {
+            let due = begin + Duration::from_secs_f64((frame.offset - first.offset) / self.speed);
+            if let Some(wait) = due.checked_duration_since(Instant::now()) {
+                std::thread::sleep(wait);
+            }
+            self.send_frame(connection, idx)?;
+        }
+        Ok(())
+    }
+
+    /// Replay every frame at its time, handling commands as they arrive, until the end of the flight or a quit
+    /// command. `report` is called with the state of the replay after each frame is sent and each command.
+    pub fn run(
+        &mut self,
+        connection: &XPlaneConnection,
+        commands: &Receiver<ReplayCommand>,
+        mut report: impl FnMut(&ReplayStatus),
+    ) -> std::io::Result<()> {
+        let duration = self.duration();
+        let mut position = 0.0;
+        let mut next = 0;
+        let mut paused = false;
+        let mut commands_open = true;
+        let mut last = Instant::now();
+
+        loop {
+            let now = Instant::now();
+            if !paused {
+                position += now.duration_since(last).as_secs_f64() * self.speed;
+            }
+            last = now;
+
+            let mut sent = false;
+            while next < self.frames.len() && self.frames[next].offset <= position {
+                self.send_frame(connection, next)?;
+                next += 1;
+                sent = true;
+            }
+            let status = ReplayStatus {
+                position: position.min(duration),
+                duration,
+                speed: self.speed,
+                paused,
+            };
+            if sent {
+                report(&status);
+            }
+            if next >= self.frames.len() {
+                return Ok(());
+            }
+
+            // wait for the next frame, or a command
+            let wait = if paused {
+                Duration::from_secs(1)
+            } else {
+                Duration::from_secs_f64(((self.frames[next].offset - position) / self.speed).clamp(0.0, 1.0))
+            };
+            if !commands_open {
+                std::thread::sleep(wait);
+                continue;
+            }
+            let command = match commands.recv_timeout(wait) {
+                Ok(command) => command,
+                Err(RecvTimeoutError::Timeout) => continue,
+                Err(RecvTimeoutError::Disconnected) => {
+                    commands_open = false;
+                    continue;
+                }
+            };
+
+            match command {
+                ReplayCommand::TogglePause => paused = !paused,
+                ReplayCommand::Speed(speed) => self.speed = speed,
+                ReplayCommand::Quit => return Ok(()),
+                ReplayCommand::Seek(seconds) => {
+                    position = (position + seconds).clamp(0.0, duration);
+                    next = self.frames.partition_point(|f| f.offset < position);
+                    // show where the replay moved to straight away, even when paused
+                    if next > 0 {
+                        self.send_frame(connection, next - 1)?;
+                    }
+                }
+            }
+            report(&ReplayStatus {
+                position,
+                duration,
+                speed: self.speed,
+                paused,
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{detection::read_avionics_log, fdr::FDRConfigurationBuilder, AviationLogSourceOption};
+    use std::net::UdpSocket;
+
+    #[test]
+    fn test_replay_to_listener() -> Result<(), Box<dyn std::error::Error>> {
+        let path = crate::resource_path("log_231104_084813_KPOU.csv");
+        let source = read_avionics_log(&AviationLogSourceOption::Garmin, &path)?;
+        let mut block = source.data_block(&FDRConfigurationBuilder::default().auto_drefs(true).build())?;
+        block.data = block.data.slice(200, 5);
+
+        let listener = UdpSocket::bind("127.0.0.1:0")?;
+        listener.set_read_timeout(Some(Duration::from_secs(5)))?;
+        let connection = XPlaneConnection::new(listener.local_addr()?)?;
+        let mut replay = Replay::new(&block)?.speed(100.0);
+        assert_eq!(replay.duration(), 4.0);
+
+        let (_tx, rx) = std::sync::mpsc::channel();
+        let mut reports = 0;
+        replay.run(&connection, &rx, |_| reports += 1)?;
+        assert_eq!(reports, 5);
+
+        let mut buffer = [0u8; 1024];
+        let (mut positions, mut drefs) = (0, 0);
+        listener.set_read_timeout(Some(Duration::from_millis(200)))?;
+        while let Ok(len) = listener.recv(&mut buffer) {
+            match &buffer[..5] {
+                b"VEHX\0" => positions += 1,
+                b"DREF\0" => drefs += 1,
+                _ => panic!("unexpected packet of {} bytes", len),
+            }
+        }
+        assert_eq!(positions, 5);
+        assert!(drefs >= 5 * 10);
+
+        assert_eq!(ReplayCommand::parse("-30"), Some(ReplayCommand::Seek(-30.0)));
+        assert_eq!(ReplayCommand::parse("x4"), Some(ReplayCommand::Speed(4.0)));
+        assert_eq!(ReplayCommand::parse(""), Some(ReplayCommand::TogglePause));
+        Ok(())
+    }
+}
@@ -0,0 +1,845 @@
+//! A self-contained HTML debrief of a flight, for reviewing it without X-Plane.
+//!
+//! The report is a single file without scripts or network assets: summary statistics, an SVG map of the track colored
+//! by phase of flight, a phase timeline, and charts over time of altitude, speed, vertical speed and the engines. Where
+//! a column leaves the range of its limit, the exceedance is marked on the map, timeline and charts, and listed.
+
+use crate::export::ExportBlock;
+use crate::fdr::{FDRWriteError, FlightDataError};
+use crate::garmin::match_engine_pattern;
+use crate::kml::escape_xml;
+use crate::profile::{AircraftProfile, Limit};
+use crate::track::{self, FlightPhase, TrackPoint};
+use chrono::{DateTime, Utc};
+use std::collections::BTreeMap;
+use std::io::Write;
+
+/// Limits checked without a profile: the bank and pitch beyond which flight is aerobatic, 14 CFR 91.307(c)
+const DEFAULT_LIMITS: [(&str, Limit); 2] = [
+    (
+        "Roll",
+        Limit {
+            min: Some(-60.0),
+            max: Some(60.0),
+        },
+    ),
+    (
+        "Pitch",
+        Limit {
+            min: Some(-30.0),
+            max: Some(30.0),
+        },
+    ),
+];
+
+/// The charts of the report, with the columns or engine patterns charted in each. Altitude, and speeds the log lacks,
+/// are charted from the track.
+const CHARTS: [(&str, &[&str]); 10] = [
+    ("Altitude", &[]),
+    ("Speed", &["IAS", "GndSpd"]),
+    ("Vertical speed", &["VSpd"]),
+    ("Engine speed", &["E{n} RPM", "E{n} NP"]),
+    ("Power", &["E{n} MAP", "E{n} Torq", "E{n} NG"]),
+    ("Fuel flow", &["E{n} FFlow"]),
+    ("Cylinder head temperature", &["E{n} CHT{m}"]),
+    ("Exhaust gas temperature", &["E{n} EGT{m}"]),
+    ("Inter-turbine temperature", &["E{n} ITT"]),
+    ("Oil", &["E{n} OilT", "E{n} OilP"]),
+];
+
+/// Charts are drawn with at most this many points of each series
+const MAX_CHART_POINTS: usize = 1500;
+/// The size of a chart, and its margins for the axis labels
+const CHART_WIDTH: f64 = 900.0;
+const CHART_HEIGHT: f64 = 160.0;
+const CHART_LEFT: f64 = 60.0;
+const CHART_BOTTOM: f64 = 20.0;
+const CHART_TOP: f64 = 10.0;
+/// The size of the map
+const MAP_WIDTH: f64 = 900.0;
+const MAP_HEIGHT: f64 = 500.0;
+const MAP_MARGIN: f64 = 20.0;
+/// The colors of the series of a chart
+const SERIES_COLORS: [&str; 6] = ["#1f77b4", "#d62728", "#2ca02c", "#ff7f0e", "#9467bd", "#8c564b"];
+
+const STYLE: &str = "body { font-family: sans-serif; margin: 2em; color: #222; }
+h1 { font-size: 1.6em; }
+h2 { font-size: 1.2em; border-bottom: 1px solid #ccc; }
+table { border-collapse: collapse; }
+td, th { padding: 2px 12px 2px 0; text-align: left; }
+svg { display: block; margin-bottom: 1em; }
+svg text { font-size: 11px; fill: #444; }
+.axis { stroke: #999; stroke-width: 1; }
+.series { fill: none; stroke-width: 1.2; }
+.exceedance { fill: #d62728; fill-opacity: 0.2; }
+.marker { fill: #d62728; stroke: #fff; }
+.legend span { display: inline-block; width: 12px; height: 12px; margin: 0 4px 0 12px; vertical-align: middle; }";
+
+fn phase_color(phase: FlightPhase) -> &'static str {
+    match phase {
+        FlightPhase::Ground => "#808080",
+        FlightPhase::Climb => "#00c000",
+        FlightPhase::Cruise => "#0080ff",
+        FlightPhase::Descent => "#ffa500",
+    }
+}
+
+/// A time a column was outside its limit
+#[derive(Debug, Clone, PartialEq)]
+pub struct Exceedance {
+    pub column: String,
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+    /// The value furthest beyond the limit
+    pub peak: f64,
+    /// The limit that was exceeded
+    pub limit: f64,
+    /// The track points of the start, end and peak
+    first: usize,
+    last: usize,
+    at_peak: usize,
+}
+
+/// A series of a chart: its name, and a value at each track point
+struct Series {
+    name: String,
+    values: Vec<Option<f64>>,
+}
+
+/// The time of day of a point
+fn time_of_day(time: &DateTime<Utc>) -> String {
+    time.format("%H:%M:%SZ").to_string()
+}
+
+/// A duration in seconds as hours, minutes and seconds
+fn duration(seconds: f64) -> String {
+    let seconds = seconds.max(0.0).round() as i64;
+    format!("{}:{:02}:{:02}", seconds / 3600, seconds / 60 % 60, seconds % 60)
+}
+
+/// A number with a precision suited to its size
+fn number(value: f64) -> String {
+    if value.abs() >= 100.0 {
+        format!("{:.0}", value)
+    } else {
+        format!("{:.1}", value)
+    }
+}
+
+/// Writes a data block as a self-contained HTML report
+pub struct HtmlReportWriter {
+    name: String,
+    limits: BTreeMap<String, Limit>,
+    units: BTreeMap<String, String>,
+}
+
+impl Default for HtmlReportWriter {
+    fn default() -> Self {
+        Self {
+            name: "Flight".to_string(),
+            limits: DEFAULT_LIMITS.iter().map(|(c, l)| (c.to_string(), *l)).collect(),
+            units: BTreeMap::new(),
+        }
+    }
+}
+
+impl HtmlReportWriter {
+    /// The title of the report, such as the tail number and date of the flight
+    pub fn name(mut self, name: String) -> Self {
+        self.name = name;
+        self
+    }
+
+    /// Check the limits of a profile, as well as the bank and pitch limits of aerobatic flight, which the profile
+    /// may replace
+    pub fn limits(mut self, profile: &AircraftProfile) -> Self {
+        self.limits.extend(profile.limits.iter().map(|(c, l)| (c.clone(), *l)));
+        self
+    }
+
+    /// The units of the columns, to label the charts
+    pub fn units(mut self, units: Vec<(String, String)>) -> Self {
+        self.units = units.into_iter().collect();
+        self
+    }
+
+    /// The converted columns the report charts or checks against a limit, to add to a data block with
+    /// [crate::export::export_block]. A limit of the profile on a column that matches none of them is an error, rather
+    /// than going unchecked.
+    pub fn columns(&self, converted: &[&str]) -> Result<Vec<String>, FlightDataError> {
+        let matches = |pattern: &str, name: &str| pattern == name || match_engine_pattern(pattern, name).is_some();
+        if let Some(pattern) = self
+            .limits
+            .keys()
+            .find(|pattern| !converted.iter().any(|name| matches(pattern, name)))
+        {
+            return Err(FlightDataError::UnknownColumn(pattern.clone()));
+        }
+        let patterns: Vec<&str> = CHARTS
+            .iter()
+            .flat_map(|(_, columns)| columns.iter().copied())
+            .chain(self.limits.keys().map(String::as_str))
+            .collect();
+        Ok(converted
+            .iter()
+            .filter(|name| patterns.iter().any(|p| matches(p, name)))
+            .map(|name| name.to_string())
+            .collect())
+    }
+
+    /// The columns of a data block matching a column name or engine pattern, in order of engine and cylinder
+    fn matching_columns(block: &ExportBlock, pattern: &str) -> Vec<String> {
+        let mut matches: Vec<String> = block
+            .column_names()
+            .into_iter()
+            .filter(|name| name == pattern || match_engine_pattern(pattern, name).is_some())
+            .collect();
+        matches.sort_by_key(|name| match_engine_pattern(pattern, name));
+        matches
+    }
+
+    /// The values of a column at each track point
+    fn point_values(block: &ExportBlock, name: &str, rows: &[usize]) -> Option<Vec<Option<f64>>> {
+        let values = block.column_values(name)?;
+        Some(rows.iter().map(|i| values[*i]).collect())
+    }
+
+    /// The times each column of the block was outside its limit
+    pub fn exceedances(&self, block: &ExportBlock) -> Result<Vec<Exceedance>, FDRWriteError> {
+        let (rows, points): (Vec<usize>, Vec<TrackPoint>) =
+            track::indexed_track_points(&block.block)?.into_iter().unzip();
+        let mut exceedances = Vec::new();
+        for (pattern, limit) in &self.limits {
+            for column in Self::matching_columns(block, pattern) {
+                let Some(values) = Self::point_values(block, &column, &rows) else {
+                    continue;
+                };
+                let mut current: Option<Exceedance> = None;
+                for (i, value) in values.iter().enumerate() {
+                    let exceeded = value.and_then(|v| limit.exceeded_by(v).map(|l| (v, l)));
+                    match (exceeded, current.as_mut()) {
+                        (Some((value, _)), Some(e)) => {
+                            e.end = points[i].time;
+                            e.last = i;
+                            if (value - e.limit).abs() > (e.peak - e.limit).abs() {
+                                (e.peak, e.at_peak) = (value, i);
+                            }
+                        }
+                        (Some((value, exceeded)), None) => {
+                            current = Some(Exceedance {
+                                column: column.clone(),
+                                start: points[i].time,
+                                end: points[i].time,
+                                peak: value,
+                                limit: exceeded,
+                                first: i,
+                                last: i,
+                                at_peak: i,
+                            })
+                        }
+                        (None, _) => exceedances.extend(current.take()),
+                    }
+                }
+                exceedances.extend(current);
+            }
+        }
+        exceedances.sort_by_key(|e| e.start);
+        Ok(exceedances)
+    }
+
+    pub fn write<W: Write>(&self, block: &ExportBlock, writer: &mut W) -> Result<(), FDRWriteError> {
+        let (rows, points): (Vec<usize>, Vec<TrackPoint>) =
+            track::indexed_track_points(&block.block)?.into_iter().unzip();
+        if points.is_empty() {
+            return Err(crate::fdr::FlightDataError::InsufficientData.into());
+        }
+        let phases = track::phases(&points);
+        let exceedances = self.exceedances(block)?;
+
+        writeln!(writer, "<!DOCTYPE html>")?;
+        writeln!(writer, "<html lang=\"en\">")?;
+        writeln!(writer, "<head>")?;
+        writeln!(writer, "<meta charset=\"utf-8\">")?;
+        writeln!(writer, "<title>{}</title>", escape_xml(&self.name))?;
+        writeln!(writer, "<style>\n{}\n</style>", STYLE)?;
+        writeln!(writer, "</head>")?;
+        writeln!(writer, "<body>")?;
+        writeln!(writer, "<h1>{}</h1>", escape_xml(&self.name))?;
+
+        self.write_stats(block, &rows, &points, &phases, &exceedances, writer)?;
+        self.write_map(&points, &phases, &exceedances, writer)?;
+        self.write_timeline(&points, &phases, &exceedances, writer)?;
+        self.write_charts(block, &rows, &points, &exceedances, writer)?;
+        self.write_exceedances(&exceedances, writer)?;
+
+        writeln!(writer, "</body>")?;
+        writeln!(writer, "</html>")?;
+        Ok(())
+    }
+
+    /// Write the summary statistics of the flight
+    fn write_stats<W: Write>(
+        &self,
+        block: &ExportBlock,
+        rows: &[usize],
+        points: &[TrackPoint],
+        phases: &[FlightPhase],
+        exceedances: &[Exceedance],
+        writer: &mut W,
+    ) -> Result<(), FDRWriteError> {
+        let (first, last) = (&points[0], &points[points.len() - 1]);
+        let speeds = track::speeds(points);
+        let max = |values: &[Option<f64>]| values.iter().flatten().copied().reduce(f64::max);
+        let min = |values: &[Option<f64>]| values.iter().flatten().copied().reduce(f64::min);
+        let column = |name: &str| Self::point_values(block, name, rows);
+        let ground_speed = column("GndSpd").unwrap_or_else(|| speeds.iter().map(|s| Some(s.0)).collect());
+        let vertical_speed = column("VSpd").unwrap_or_else(|| speeds.iter().map(|s| Some(s.1)).collect());
+        let altitude: Vec<Option<f64>> = points.iter().map(|p| Some(p.altitude)).collect();
+        let roll: Vec<Option<f64>> = points.iter().map(|p| Some(p.roll.abs())).collect();
+        let pitch: Vec<Option<f64>> = points.iter().map(|p| Some(p.pitch)).collect();
+        let distance: f64 = points.windows(2).map(|w| w[0].distance_to(&w[1])).sum();
+
+        let mut stats = vec![
+            ("Date".to_string(), first.time.format("%Y-%m-%d").to_string()),
+            (
+                "Recorded".to_string(),
+                format!(
+                    "{} to {} ({})",
+                    time_of_day(&first.time),
+                    time_of_day(&last.time),
+                    duration(last.seconds_since(first))
+                ),
+            ),
+        ];
+        let airborne: Vec<usize> = (0..points.len())
+            .filter(|i| phases[*i] != FlightPhase::Ground)
+            .collect();
+        if let (Some(&takeoff), Some(&landing)) = (airborne.first(), airborne.last()) {
+            stats.push((
+                "Airborne".to_string(),
+                format!(
+                    "{} to {} ({})",
+                    time_of_day(&points[takeoff].time),
+                    time_of_day(&points[landing].time),
+                    duration(points[landing].seconds_since(&points[takeoff]))
+                ),
+            ));
+        }
+        stats.push(("Distance".to_string(), format!("{:.1} nm", distance)));
+        let mut push = |label: &str, value: Option<f64>, unit: &str| {
+            if let Some(value) = value {
+                stats.push((label.to_string(), format!("{} {}", number(value), unit)));
+            }
+        };
+        push("Max altitude", max(&altitude), "ft");
+        push("Max indicated airspeed", column("IAS").and_then(|v| max(&v)), "kt");
+        push("Max ground speed", max(&ground_speed), "kt");
+        push("Max climb", max(&vertical_speed), "fpm");
+        push("Max descent", min(&vertical_speed).map(|v| -v), "fpm");
+        push("Max bank", max(&roll), "deg");
+        push("Max pitch up", max(&pitch), "deg");
+        push("Max pitch down", min(&pitch).map(|v| -v), "deg");
+        let mut phase_seconds: Vec<(FlightPhase, f64)> = Vec::new();
+        for (phase, start, len) in track::runs(phases) {
+            let end = (start + len).min(points.len() - 1);
+            let seconds = points[end].seconds_since(&points[start]);
+            match phase_seconds.iter_mut().find(|(p, _)| *p == phase) {
+                Some((_, total)) => *total += seconds,
+                None => phase_seconds.push((phase, seconds)),
+            }
+        }
+        for (phase, seconds) in phase_seconds {
+            stats.push((format!("{} time", phase.name()), duration(seconds)));
+        }
+        stats.push(("Exceedances".to_string(), exceedances.len().to_string()));
+
+        writeln!(writer, "<h2>Summary</h2>")?;
+        writeln!(writer, "<table class=\"stats\">")?;
+        for (label, value) in stats {
+            writeln!(writer, "<tr><th>{}</th><td>{}</td></tr>", label, escape_xml(&value))?;
+        }
+        writeln!(writer, "</table>")?;
+        Ok(())
+    }
+
+    /// Write a map of the track, colored by phase of flight, with its start, end and exceedances marked
+    fn write_map<W: Write>(
+        &self,
+        points: &[TrackPoint],
+        phases: &[FlightPhase],
+        exceedances: &[Exceedance],
+        writer: &mut W,
+    ) -> Result<(), FDRWriteError> {
+        let bound = |f: fn(&TrackPoint) -> f64| {
+            let values = points.iter().map(f);
+            (
+                values.clone().fold(f64::INFINITY, f64::min),
+                values.fold(f64::NEG_INFINITY, f64::max),
+            )
+        };
+        let (south, north) = bound(|p| p.latitude);
+        let (west, east) = bound(|p| p.longitude);
+        // an equirectangular projection, true to scale at the middle latitude
+        let stretch = ((south + north) / 2.0).to_radians().cos();
+        let (width, height) = (((east - west) * stretch).max(1e-6), (north - south).max(1e-6));
+        let scale = ((MAP_WIDTH - 2.0 * MAP_MARGIN) / width).min((MAP_HEIGHT - 2.0 * MAP_MARGIN) / height);
+        // centered across the map
+        let left = (MAP_WIDTH - width * scale) / 2.0;
+        let project = |p: &TrackPoint| {
+            (
+                left + (p.longitude - west) * stretch * scale,
+                MAP_MARGIN + (north - p.latitude) * scale,
+            )
+        };
+        let map_height = (height * scale + 2.0 * MAP_MARGIN).max(100.0);
+
+        writeln!(writer, "<h2>Track</h2>")?;
+        writeln!(
+            writer,
+            "<svg width=\"{}\" height=\"{:.0}\" viewBox=\"0 0 {} {:.0}\" role=\"img\">",
+            MAP_WIDTH, map_height, MAP_WIDTH, map_height
+        )?;
+        writeln!(
+            writer,
+            "<rect width=\"100%\" height=\"100%\" fill=\"#f8f8f4\" stroke=\"#ccc\"/>"
+        )?;
+        for (phase, start, len) in track::runs(phases) {
+            // include the first point of the next run, so the runs join up
+            let end = (start + len + 1).min(points.len());
+            let coordinates: Vec<String> = points[start..end]
+                .iter()
+                .map(|p| {
+                    let (x, y) = project(p);
+                    format!("{:.1},{:.1}", x, y)
+                })
+                .collect();
+            writeln!(
+                writer,
+                "<polyline class=\"series\" stroke=\"{}\" stroke-width=\"2\" points=\"{}\"><title>{}</title></polyline>",
+                phase_color(phase),
+                coordinates.join(" "),
+                phase.name()
+            )?;
+        }
+
+        // a scale bar of a round number of nautical miles, about a fifth of the map wide
+        let nm_per_pixel = 60.0 / scale;
+        let target = nm_per_pixel * MAP_WIDTH / 5.0;
+        let magnitude = 10f64.powf(target.log10().floor());
+        let bar_nm = [1.0, 2.0, 5.0, 10.0]
+            .iter()
+            .map(|m| m * magnitude)
+            .filter(|nm| *nm <= target)
+            .fold(magnitude, f64::max);
+        let bar = bar_nm / nm_per_pixel;
+        writeln!(
+            writer,
+            "<line class=\"axis\" x1=\"{}\" y1=\"{:.0}\" x2=\"{:.1}\" y2=\"{:.0}\" stroke-width=\"2\"/>",
+            MAP_MARGIN,
+            map_height - 8.0,
+            MAP_MARGIN + bar,
+            map_height - 8.0
+        )?;
+        writeln!(
+            writer,
+            "<text x=\"{:.1}\" y=\"{:.0}\">{} nm</text>",
+            MAP_MARGIN + bar + 4.0,
+            map_height - 4.0,
+            bar_nm
+        )?;
+
+        for (label, point, color) in [
+            ("Start", &points[0], "#2ca02c"),
+            ("End", &points[points.len() - 1], "#222"),
+        ] {
+            let (x, y) = project(point);
+            writeln!(
+                writer,
+                "<circle cx=\"{:.1}\" cy=\"{:.1}\" r=\"5\" fill=\"{}\"><title>{} {}</title></circle>",
+                x,
+                y,
+                color,
+                label,
+                time_of_day(&point.time)
+            )?;
+        }
+        for exceedance in exceedances {
+            let (x, y) = project(&points[exceedance.at_peak]);
+            writeln!(
+                writer,
+                "<circle class=\"marker\" cx=\"{:.1}\" cy=\"{:.1}\" r=\"5\"><title>{}</title></circle>",
+                x,
+                y,
+                escape_xml(&self.describe(exceedance))
+            )?;
+        }
+        writeln!(writer, "</svg>")?;
+        Ok(())
+    }
+
+    /// Write a timeline of the phases of flight, with the exceedances marked
+    fn write_timeline<W: Write>(
+        &self,
+        points: &[TrackPoint],
+        phases: &[FlightPhase],
+        exceedances: &[Exceedance],
+        writer: &mut W,
+    ) -> Result<(), FDRWriteError> {
+        let total = points[points.len() - 1].seconds_since(&points[0]).max(1.0);
+        let x = |p: &TrackPoint| p.seconds_since(&points[0]) / total * CHART_WIDTH;
+
+        writeln!(writer, "<h2>Phases</h2>")?;
+        writeln!(
+            writer,
+            "<svg width=\"{}\" height=\"30\" viewBox=\"0 0 {} 30\" role=\"img\">",
+            CHART_WIDTH, CHART_WIDTH
+        )?;
+        for (phase, start, len) in track::runs(phases) {
+            let end = (start + len).min(points.len() - 1);
+            writeln!(
+                writer,
+                "<rect x=\"{:.1}\" y=\"0\" width=\"{:.1}\" height=\"24\" fill=\"{}\"><title>{} {} to {}</title></rect>",
+                x(&points[start]),
+                (x(&points[end]) - x(&points[start])).max(0.5),
+                phase_color(phase),
+                phase.name(),
+                time_of_day(&points[start].time),
+                time_of_day(&points[end].time)
+            )?;
+        }
+        for exceedance in exceedances {
+            writeln!(
+                writer,
+                "<rect class=\"marker\" x=\"{:.1}\" y=\"24\" width=\"{:.1}\" height=\"6\"><title>{}</title></rect>",
+                x(&points[exceedance.first]),
+                (x(&points[exceedance.last]) - x(&points[exceedance.first])).max(2.0),
+                escape_xml(&self.describe(exceedance))
+            )?;
+        }
+        writeln!(writer, "</svg>")?;
+        write!(writer, "<p class=\"legend\">")?;
+        for phase in [
+            FlightPhase::Ground,
+            FlightPhase::Climb,
+            FlightPhase::Cruise,
+            FlightPhase::Descent,
+        ] {
+            write!(
+                writer,
+                "<span style=\"background: {}\"></span>{}",
+                phase_color(phase),
+                phase.name()
+            )?;
+        }
+        writeln!(writer, "</p>")?;
+        Ok(())
+    }
+
+    /// Write a chart over time of each group of columns the block has
+    fn write_charts<W: Write>(
+        &self,
+        block: &ExportBlock,
+        rows: &[usize],
+        points: &[TrackPoint],
+        exceedances: &[Exceedance],
+        writer: &mut W,
+    ) -> Result<(), FDRWriteError> {
+        let speeds = track::speeds(points);
+        writeln!(writer, "<h2>Charts</h2>")?;
+        for (title, patterns) in CHARTS {
+            let mut series: Vec<Series> = patterns
+                .iter()
+                .flat_map(|p| Self::matching_columns(block, p))
+                .filter_map(|name| {
+                    let values = Self::point_values(block, &name, rows)?;
+                    Some(Series { name, values })
+                })
+                .collect();
+            let mut unit = series
+                .first()
+                .and_then(|s| self.units.get(&s.name))
+                .cloned()
+                .unwrap_or_default();
+            match title {
+                "Altitude" => {
+                    let name = block.block.data.get_column_names()[3].to_string();
+                    unit = self.units.get(&name).cloned().unwrap_or_else(|| "ft".to_string());
+                    let values = points.iter().map(|p| Some(p.altitude)).collect();
+                    series.push(Series { name, values });
+                }
+                "Speed" if !series.iter().any(|s| s.name == "GndSpd") => {
+                    let values = speeds.iter().map(|s| Some(s.0)).collect();
+                    series.push(Series {
+                        name: "Ground speed (track)".to_string(),
+                        values,
+                    });
+                    unit = "kt".to_string();
+                }
+                "Vertical speed" if series.is_empty() => {
+                    let values = speeds.iter().map(|s| Some(s.1)).collect();
+                    series.push(Series {
+                        name: "Vertical speed (track)".to_string(),
+                        values,
+                    });
+                    unit = "fpm".to_string();
+                }
+                _ => {}
+            }
+            if series.iter().all(|s| s.values.iter().all(Option::is_none)) {
+                continue;
+            }
+            // a chart of columns in different units, such as oil temperature and pressure, is not labeled with one
+            if series
+                .iter()
+                .any(|s| self.units.get(&s.name).is_some_and(|u| *u != unit))
+            {
+                unit.clear();
+            }
+            self.write_chart(title, &unit, &series, points, exceedances, writer)?;
+        }
+        Ok(())
+    }
+
+    /// Write a chart of series over time
+    fn write_chart<W: Write>(
+        &self,
+        title: &str,
+        unit: &str,
+        series: &[Series],
+        points: &[TrackPoint],
+        exceedances: &[Exceedance],
+        writer: &mut W,
+    ) -> Result<(), FDRWriteError> {
+        let values = series.iter().flat_map(|s| s.values.iter().flatten().copied());
+        let (mut low, mut high) = values.fold((f64::INFINITY, f64::NEG_INFINITY), |(l, h), v| (l.min(v), h.max(v)));
+        if high - low < 1e-6 {
+            (low, high) = (low - 1.0, high + 1.0);
+        }
+        let total = points[points.len() - 1].seconds_since(&points[0]).max(1.0);
+        let plot_width = CHART_WIDTH - CHART_LEFT - 10.0;
+        let plot_height = CHART_HEIGHT - CHART_TOP - CHART_BOTTOM;
+        let x = |i: usize| CHART_LEFT + points[i].seconds_since(&points[0]) / total * plot_width;
+        let y = |v: f64| CHART_TOP + (high - v) / (high - low) * plot_height;
+
+        match unit {
+            "" => writeln!(writer, "<h3>{}</h3>", escape_xml(title))?,
+            unit => writeln!(writer, "<h3>{} ({})</h3>", escape_xml(title), escape_xml(unit))?,
+        }
+        writeln!(
+            writer,
+            "<svg width=\"{}\" height=\"{}\" viewBox=\"0 0 {} {}\" role=\"img\">",
+            CHART_WIDTH, CHART_HEIGHT, CHART_WIDTH, CHART_HEIGHT
+        )?;
+        for exceedance in exceedances {
+            writeln!(
+                writer,
+                "<rect class=\"exceedance\" x=\"{:.1}\" y=\"{}\" width=\"{:.1}\" height=\"{}\"><title>{}</title></rect>",
+                x(exceedance.first),
+                CHART_TOP,
+                (x(exceedance.last) - x(exceedance.first)).max(2.0),
+                plot_height,
+                escape_xml(&self.describe(exceedance))
+            )?;
+        }
+
+        // axes, labeled with the range of values and the time at five places
+        let bottom = CHART_TOP + plot_height;
+        writeln!(
+            writer,
+            "<line class=\"axis\" x1=\"{}\" y1=\"{}\" x2=\"{}\" y2=\"{}\"/>",
+            CHART_LEFT, CHART_TOP, CHART_LEFT, bottom
+        )?;
+        writeln!(
+            writer,
+            "<line class=\"axis\" x1=\"{}\" y1=\"{}\" x2=\"{}\" y2=\"{}\"/>",
+            CHART_LEFT,
+            bottom,
+            CHART_LEFT + plot_width,
+            bottom
+        )?;
+        for value in [high, (high + low) / 2.0, low] {
+            writeln!(
+                writer,
+                "<text x=\"{}\" y=\"{:.1}\" text-anchor=\"end\">{}</text>",
+                CHART_LEFT - 4.0,
+                y(value) + 4.0,
+                number(value)
+            )?;
+        }
+        for tick in 0..5 {
+            let i = tick * (points.len() - 1) / 4;
+            let anchor = match tick {
+                0 => "start",
+                4 => "end",
+                _ => "middle",
+            };
+            writeln!(
+                writer,
+                "<text x=\"{:.1}\" y=\"{}\" text-anchor=\"{}\">{}</text>",
+                x(i),
+                CHART_HEIGHT - 4.0,
+                anchor,
+                points[i].time.format("%H:%MZ")
+            )?;
+        }
+
+        // each series as a path, broken where it has no values
+        let stride = points.len().div_ceil(MAX_CHART_POINTS).max(1);
+        for (n, s) in series.iter().enumerate() {
+            let mut path = String::new();
+            let mut drawing = false;
+            for i in (0..points.len()).step_by(stride) {
+                match s.values[i] {
+                    Some(v) => {
+                        path.push_str(&format!("{}{:.1},{:.1} ", if drawing { "L" } else { "M" }, x(i), y(v)));
+                        drawing = true;
+                    }
+                    None => drawing = false,
+                }
+            }
+            writeln!(
+                writer,
+                "<path class=\"series\" stroke=\"{}\" d=\"{}\"><title>{}</title></path>",
+                SERIES_COLORS[n % SERIES_COLORS.len()],
+                path.trim_end(),
+                escape_xml(&s.name)
+            )?;
+        }
+        writeln!(writer, "</svg>")?;
+
+        if series.len() > 1 {
+            write!(writer, "<p class=\"legend\">")?;
+            for (n, s) in series.iter().enumerate() {
+                write!(
+                    writer,
+                    "<span style=\"background: {}\"></span>{}",
+                    SERIES_COLORS[n % SERIES_COLORS.len()],
+                    escape_xml(&s.name)
+                )?;
+            }
+            writeln!(writer, "</p>")?;
+        }
+        Ok(())
+    }
+
+    /// Write a table of the exceedances
+    fn write_exceedances<W: Write>(&self, exceedances: &[Exceedance], writer: &mut W) -> Result<(), FDRWriteError> {
+        writeln!(writer, "<h2>Exceedances</h2>")?;
+        if exceedances.is_empty() {
+            writeln!(writer, "<p>None</p>")?;
+            return Ok(());
+        }
+        writeln!(writer, "<table>")?;
+        writeln!(
+            writer,
+            "<tr><th>Column</th><th>From</th><th>To</th><th>Peak</th><th>Limit</th></tr>"
+        )?;
+        for e in exceedances {
+            writeln!(
+                writer,
+                "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+                escape_xml(&e.column),
+                time_of_day(&e.start),
+                time_of_day(&e.end),
+                escape_xml(&self.with_unit(&e.column, e.peak)),
+                escape_xml(&self.with_unit(&e.column, e.limit))
+            )?;
+        }
+        writeln!(writer, "</table>")?;
+        Ok(())
+    }
+
+    /// A value of a column with its unit
+    fn with_unit(&self, column: &str, value: f64) -> String {
+        match self.units.get(column).filter(|u| !u.is_empty()) {
+            Some(unit) => format!("{} {}", number(value), unit),
+            None => number(value),
+        }
+    }
+
+    /// A description of an exceedance, for the tooltips of its markers
+    fn describe(&self, exceedance: &Exceedance) -> String {
+        format!(
+            "{} {} beyond {} at {}",
+            exceedance.column,
+            self.with_unit(&exceedance.column, exceedance.peak),
+            number(exceedance.limit),
+            time_of_day(&exceedance.start)
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{detection::read_avionics_log, export, fdr::FDRConfigurationBuilder, AviationLogSourceOption};
+
+    #[test]
+    fn test_html_report() -> Result<(), Box<dyn std::error::Error>> {
+        let path = crate::resource_path("log_231104_084813_KPOU.csv");
+        let source = read_avionics_log(&AviationLogSourceOption::Garmin, &path)?;
+        let config = FDRConfigurationBuilder::default().build();
+        let units = export::converted_columns(source.as_ref(), &config.profile);
+        let converted: Vec<&str> = units.iter().map(|(name, _)| name.as_str()).collect();
+
+        let writer = HtmlReportWriter::default()
+            .name("N12345".to_string())
+            .units(units.clone());
+        let block = export::export_block(source.as_ref(), &config, &writer.columns(&converted)?)?;
+        let mut buffer = Vec::new();
+        writer.write(&block, &mut buffer)?;
+        let html = String::from_utf8(buffer)?;
+
+        // one file, with nothing fetched over the network
+        assert!(html.starts_with("<!DOCTYPE html>") && html.trim_end().ends_with("</html>"));
+        assert!(!html.contains("http") && !html.contains("<script"));
+        for heading in [
+            "Track",
+            "Phases",
+            "Altitude (ft Baro)",
+            "Speed (kt)",
+            "Cylinder head temperature (deg F)",
+        ] {
+            assert!(html.contains(heading), "{}", heading);
+        }
+        assert!(html.contains("<tr><th>Distance</th>"));
+        // the flight was flown within the bank and pitch of aerobatic flight
+        assert!(html.contains("<tr><th>Exceedances</th><td>0</td></tr>"));
+
+        // the limits of a profile mark exceedances, including every column matching an engine pattern
+        let profile: AircraftProfile =
+            serde_json::from_str(r#"{"limits": {"Roll": {"min": -20, "max": 20}, "E{n} CHT{m}": {"max": 370}}}"#)?;
+        let writer = HtmlReportWriter::default().limits(&profile).units(units.clone());
+        let block = export::export_block(source.as_ref(), &config, &writer.columns(&converted)?)?;
+        let exceedances = writer.exceedances(&block)?;
+        assert!(exceedances.iter().any(|e| e.column == "Roll"));
+        assert!(exceedances.iter().any(|e| e.column.starts_with("E1 CHT")));
+        assert!(exceedances
+            .iter()
+            .all(|e| e.peak.abs() > e.limit.abs() && e.start <= e.end));
+        let mut buffer = Vec::new();
+        writer.write(&block, &mut buffer)?;
+        let html = String::from_utf8(buffer)?;
+        assert_eq!(html.matches("<circle class=\"marker\"").count(), exceedances.len());
+        assert!(html.contains("<td>Roll</td>"));
+
+        // limits are checked on the converted data of the block, including the parameters derived by the profile
+        let profile: AircraftProfile =
+            serde_json::from_str(r#"{"derive": ["density_altitude"], "limits": {"DensAlt": {"max": 1000}}}"#)?;
+        let config = FDRConfigurationBuilder::default().profile(profile.clone()).build();
+        let units = export::converted_columns(source.as_ref(), &profile);
+        let derived: Vec<&str> = units.iter().map(|(name, _)| name.as_str()).collect();
+        let writer = HtmlReportWriter::default().limits(&profile);
+        let block = export::export_block(source.as_ref(), &config, &writer.columns(&derived)?)?;
+        assert!(writer.exceedances(&block)?.iter().any(|e| e.column == "DensAlt"));
+
+        // a limit on a column the log does not have is not silently skipped
+        let profile: AircraftProfile = serde_json::from_str(r#"{"limits": {"E{n} TIT": {"max": 1650}}}"#)?;
+        let writer = HtmlReportWriter::default().limits(&profile);
+        assert!(matches!(
+            writer.columns(&converted),
+            Err(FlightDataError::UnknownColumn(column)) if column == "E{n} TIT"
+        ));
+        Ok(())
+    }
+}
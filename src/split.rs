@@ -0,0 +1,87 @@
+//! Splitting a log that covers several flights, or has long breaks in recording, into one FDR file per segment.
+
+use crate::fdr::{FDRWriteError, FDRWriter, FlightDataBlock};
+use polars::prelude::*;
+use std::path::{Path, PathBuf};
+
+/// Find the segments of a data block separated by gaps in the timestamps longer than `max_gap_secs`
+///
+/// Returns the offset and length of each segment, in order.
+pub fn segments(block: &FlightDataBlock, max_gap_secs: f64) -> Vec<(usize, usize)> {
+    let Some(Ok(ts)) = block.data.select_at_idx(0).map(|c| c.datetime().cloned()) else {
+        return vec![(0, block.data.height())];
+    };
+    let scale = match ts.time_unit() {
+        TimeUnit::Nanoseconds => 1e9,
+        TimeUnit::Microseconds => 1e6,
+        TimeUnit::Milliseconds => 1e3,
+    };
+
+    let mut segments = Vec::new();
+    let mut start = 0;
+    let mut previous: Option<i64> = None;
+    for (idx, value) in ts.into_iter().enumerate() {
+        let Some(value) = value else { continue };
+        if let Some(previous) = previous {
+            if (value - previous) as f64 / scale > max_gap_secs {
+                segments.push((start, idx - start));
+                start = idx;
+            }
+        }
+        previous = Some(value);
+    }
+    if block.data.height() > start {
+        segments.push((start, block.data.height() - start));
+    }
+    segments
+}
+
+/// Write each segment of a data block to its own FDR file, named after `stem`, in `output_dir`
+///
+/// Returns the paths of the files written.
+pub fn write_segments(
+    writer: &FDRWriter,
+    tail_number: &str,
+    block: &FlightDataBlock,
+    max_gap_secs: f64,
+    output_dir: &Path,
+    stem: &str,
+) -> Result<Vec<PathBuf>, FDRWriteError> {
+    std::fs::create_dir_all(output_dir)?;
+    let mut paths = Vec::new();
+    for (n, (offset, length)) in segments(block, max_gap_secs).into_iter().enumerate() {
+        let segment = FlightDataBlock::new(block.drefs.clone(), block.data.slice(offset as i64, length))?;
+        let path = output_dir.join(format!("{}_{:02}.fdr", stem, n + 1));
+        let mut file = std::io::BufWriter::new(std::fs::File::create(&path)?);
+        writer.write_blocks(tail_number, std::iter::once(Ok(segment)), &mut file)?;
+        paths.push(path);
+    }
+    Ok(paths)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_segments_split_on_gaps() -> PolarsResult<()> {
+        let seconds = [0i64, 1, 2, 600, 601, 2000];
+        let ts = Series::new("timestamp".into(), seconds.map(|s| s * 1_000_000))
+            .cast(&DataType::Datetime(TimeUnit::Microseconds, None))?;
+        let zeros = |name: &str| Column::new(name.into(), vec![0.0; seconds.len()]);
+        let data = DataFrame::new(vec![
+            ts.into(),
+            zeros("lon"),
+            zeros("lat"),
+            zeros("alt"),
+            zeros("hdg"),
+            zeros("pitch"),
+            zeros("roll"),
+        ])?;
+        let block = FlightDataBlock::new(vec![], data).unwrap();
+
+        assert_eq!(segments(&block, 300.0), vec![(0, 3), (3, 2), (5, 1)]);
+        assert_eq!(segments(&block, 3600.0), vec![(0, 6)]);
+        Ok(())
+    }
+}
@@ -0,0 +1,480 @@
+//! Flight summaries for logbook entries: block and flight times, distance, altitude, fuel, landings and night time.
+//!
+//! Block out and in are the first and last records with an engine running, takeoffs and landings are where the
+//! aircraft leaves and returns to the ground (from `OnGrnd`, or the ground speed when it isn't logged) for at least a
+//! few seconds, so a bounce is not counted, and night is when the sun is below civil twilight at the aircraft. A
+//! landing is a full stop if the aircraft slows to a taxi before it next takes off. With an airport database, the
+//! departure and arrival airports and runways are identified from where the aircraft took off and landed.
+
+use crate::airports::{self, RunwayPosition};
+use crate::derive::{self, Derivation};
+use crate::fdr::{FDRConfiguration, FlightDataError, FlightDataSource};
+use crate::track;
+use chrono::{DateTime, NaiveDate, Utc};
+use polars::prelude::*;
+use serde::Serialize;
+use std::{fmt::Display, io::Write};
+
+/// An engine turning faster than this many RPM is running
+const RUNNING_RPM: f64 = 400.0;
+/// A turbine whose gas generator turns faster than this percentage is running
+const RUNNING_NG_PERCENT: f64 = 40.0;
+/// A landing is a full stop if the ground speed falls below this many knots before the next takeoff
+const FULL_STOP_GROUND_SPEED_KT: f64 = 25.0;
+/// The elevation of the sun in degrees at the end of evening and start of morning civil twilight, between which it
+/// is night
+const CIVIL_TWILIGHT_DEG: f64 = -6.0;
+/// The course at a takeoff or landing is measured from where the aircraft was at least this many seconds before
+const COURSE_SECS: i64 = 3;
+
+/// The logbook entry of a flight
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct FlightSummary {
+    pub tail_number: Option<String>,
+    pub aircraft_type: Option<String>,
+    /// The UTC date of block out
+    pub date: Option<NaiveDate>,
+    /// The airport identified from the takeoff position, or the one the source records
+    pub departure: Option<String>,
+    pub departure_runway: Option<String>,
+    /// The airport identified from the position of the last landing
+    pub arrival: Option<String>,
+    pub arrival_runway: Option<String>,
+    pub block_out: Option<DateTime<Utc>>,
+    pub takeoff: Option<DateTime<Utc>>,
+    /// The time of the last landing
+    pub landing: Option<DateTime<Utc>>,
+    pub block_in: Option<DateTime<Utc>>,
+    /// Hours from block out to block in
+    pub block_hours: f64,
+    /// Hours from the first takeoff to the last landing
+    pub flight_hours: f64,
+    /// Hours of block time at night
+    pub night_hours: f64,
+    /// Nautical miles flown between block out and in
+    pub distance_nm: f64,
+    /// The highest altitude, in feet MSL
+    pub max_altitude_ft: Option<f64>,
+    /// Fuel burned between block out and in, in the unit of `fuel_unit`
+    pub fuel_used: Option<f64>,
+    pub fuel_unit: String,
+    pub day_takeoffs: usize,
+    pub night_takeoffs: usize,
+    /// Landings by day and night, including touch and goes
+    pub day_landings: usize,
+    pub night_landings: usize,
+    /// Landings by day and night that came to a full stop, every landing if the ground speed isn't logged
+    pub day_full_stop_landings: usize,
+    pub night_full_stop_landings: usize,
+    /// Where the last landing touched down on the runway
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub touchdown: Option<RunwayPosition>,
+}
+
+/// The largest value of the engine fields of a record, such as the RPM of the fastest engine
+fn engine_max(df: &DataFrame, field: &str) -> PolarsResult<Option<Vec<Option<f64>>>> {
+    let mut max: Option<Vec<Option<f64>>> = None;
+    for name in df.get_column_names() {
+        if !derive::is_engine_field(name, field) {
+            continue;
+        }
+        let values = track::float_column(df, name)?.unwrap_or_default();
+        max = Some(match max {
+            None => values,
+            Some(max) => max
+                .iter()
+                .zip(values)
+                .map(|(a, b)| match (*a, b) {
+                    (Some(a), Some(b)) => Some(a.max(b)),
+                    (a, b) => a.or(b),
+                })
+                .collect(),
+        });
+    }
+    Ok(max)
+}
+
+/// The elevation of the sun above the horizon at a position and time, in degrees
+///
+/// Uses the low precision solar coordinates of the Astronomical Almanac, good to about a hundredth of a degree.
+pub fn sun_elevation(latitude: f64, longitude: f64, time: &DateTime<Utc>) -> f64 {
+    let days = time.timestamp_millis() as f64 / 86_400_000.0 + 2_440_587.5 - 2_451_545.0;
+    let mean_longitude = 280.460 + 0.9856474 * days;
+    let anomaly = (357.528 + 0.9856003 * days).to_radians();
+    let ecliptic_longitude = (mean_longitude + 1.915 * anomaly.sin() + 0.020 * (2.0 * anomaly).sin()).to_radians();
+    let obliquity = (23.439 - 0.0000004 * days).to_radians();
+
+    let right_ascension = (obliquity.cos() * ecliptic_longitude.sin()).atan2(ecliptic_longitude.cos());
+    let declination = (obliquity.sin() * ecliptic_longitude.sin()).asin();
+    let sidereal_hours = (18.697374558 + 24.06570982441908 * days).rem_euclid(24.0);
+    let hour_angle = (sidereal_hours * 15.0 + longitude).to_radians() - right_ascension;
+
+    let latitude = latitude.to_radians();
+    (latitude.sin() * declination.sin() + latitude.cos() * declination.cos() * hour_angle.cos())
+        .asin()
+        .to_degrees()
+}
+
+impl FlightSummary {
+    /// Summarize the flight logged by a source, with the engine type of the profile of a configuration deciding the
+    /// unit of fuel, and its airport database identifying the airports
+    pub fn from_source(source: &dyn FlightDataSource, config: &FDRConfiguration) -> Result<Self, FlightDataError> {
+        let data = source.data()?;
+        let read_error = |e: PolarsError| FlightDataError::ReadError(e.to_string());
+        let times: Vec<Option<DateTime<Utc>>> = data
+            .column("timestamp")
+            .and_then(|c| Ok(c.datetime()?.cast_time_unit(TimeUnit::Microseconds)))
+            .map_err(read_error)?
+            .into_iter()
+            .map(|t| t.and_then(DateTime::<Utc>::from_timestamp_micros))
+            .collect();
+        let column = |name: &str| track::float_column(&data, name).map_err(read_error);
+        let (latitude, longitude) = (column("Latitude")?, column("Longitude")?);
+        let altitude = match column("AltMSL")? {
+            Some(altitude) => Some(altitude),
+            None => column("AltB")?,
+        };
+
+        // the engines run from block out to block in
+        let rpm = engine_max(&data, "RPM").map_err(read_error)?;
+        let ng = engine_max(&data, "NG").map_err(read_error)?;
+        let running: Vec<bool> = (0..data.height())
+            .map(|i| {
+                let turning =
+                    |v: &Option<Vec<Option<f64>>>, min: f64| v.as_ref().and_then(|v| v[i]).is_some_and(|v| v > min);
+                match (&rpm, &ng) {
+                    (None, None) => true,
+                    _ => turning(&rpm, RUNNING_RPM) || turning(&ng, RUNNING_NG_PERCENT),
+                }
+            })
+            .collect();
+        let block: Vec<usize> = (0..data.height())
+            .filter(|i| running[*i] && times[*i].is_some())
+            .collect();
+        let (Some(&first), Some(&last)) = (block.first(), block.last()) else {
+            return Err(FlightDataError::InsufficientData);
+        };
+
+        let ground_speed = column("GndSpd")?;
+        let on_ground = track::on_ground(&data, &times).map_err(read_error)?;
+        // whether a landing slowed to a taxi before the next takeoff, or the end of the block
+        let full_stop = |landing: usize| {
+            let Some(speed) = &ground_speed else {
+                return true;
+            };
+            let until = (landing..=last).find(|j| on_ground[*j] == Some(false)).unwrap_or(last);
+            speed[landing..=until]
+                .iter()
+                .flatten()
+                .any(|v| *v < FULL_STOP_GROUND_SPEED_KT)
+        };
+        let position = |i: usize| {
+            latitude
+                .as_ref()
+                .zip(longitude.as_ref())
+                .and_then(|(lat, lon)| lat[i].zip(lon[i]))
+        };
+        let is_night = |i: usize, at: Option<(f64, f64)>| -> bool {
+            match (position(i).or(at), times[i]) {
+                (Some((lat, lon)), Some(time)) => sun_elevation(lat, lon, &time) < CIVIL_TWILIGHT_DEG,
+                _ => false,
+            }
+        };
+
+        let mut summary = FlightSummary {
+            tail_number: source.tail_number(),
+            aircraft_type: source.metadata().get("airframe_name").cloned(),
+            date: times[first].map(|t| t.date_naive()),
+            departure: source.departure_airport(),
+            block_out: times[first],
+            block_in: times[last],
+            fuel_unit: derive::fuel_unit(&source.columns(), &config.profile).to_string(),
+            ..Default::default()
+        };
+        let mut last_position: Option<(f64, f64)> = None;
+        let mut flying: Option<bool> = None;
+        let (mut takeoff, mut landing) = (None, None);
+        for i in first..=last {
+            let Some(time) = times[i] else {
+                continue;
+            };
+            if let (Some(here), Some(before)) = (position(i), last_position) {
+                summary.distance_nm += track::distance_nm(before.0, before.1, here.0, here.1);
+            }
+            let night = is_night(i, last_position);
+            last_position = position(i).or(last_position);
+
+            if i < last {
+                let next = times[i + 1..=last].iter().flatten().next();
+                let hours = next.map_or(0.0, |n| (*n - time).num_milliseconds() as f64 / 3_600_000.0);
+                if night && hours > 0.0 {
+                    summary.night_hours += hours;
+                }
+            }
+            if let Some(altitude) = altitude.as_ref().and_then(|a| a[i]) {
+                summary.max_altitude_ft = Some(summary.max_altitude_ft.map_or(altitude, |m| m.max(altitude)));
+            }
+
+            let Some(ground) = on_ground[i] else {
+                continue;
+            };
+            match (flying, ground) {
+                (Some(false), false) => {
+                    if summary.takeoff.is_none() {
+                        summary.takeoff = Some(time);
+                        takeoff = Some(i);
+                    }
+                    if night {
+                        summary.night_takeoffs += 1;
+                    } else {
+                        summary.day_takeoffs += 1;
+                    }
+                }
+                (Some(true), true) => {
+                    summary.landing = Some(time);
+                    landing = Some(i);
+                    let full_stop = full_stop(i);
+                    if night {
+                        summary.night_landings += 1;
+                        summary.night_full_stop_landings += usize::from(full_stop);
+                    } else {
+                        summary.day_landings += 1;
+                        summary.day_full_stop_landings += usize::from(full_stop);
+                    }
+                }
+                _ => {}
+            }
+            flying = Some(!ground);
+        }
+
+        let hours = |from: Option<DateTime<Utc>>, to: Option<DateTime<Utc>>| match (from, to) {
+            (Some(from), Some(to)) => (to - from).num_milliseconds() as f64 / 3_600_000.0,
+            _ => 0.0,
+        };
+        summary.block_hours = hours(summary.block_out, summary.block_in);
+        summary.flight_hours = hours(summary.takeoff, summary.landing);
+
+        if let Some(database) = &config.airports {
+            // the position and true course of the aircraft on the runway
+            let on_runway = |i: usize| {
+                let (latitude, longitude) = position(i)?;
+                let time = times[i]?;
+                let (lat, lon) = (0..i)
+                    .rev()
+                    .filter(|j| times[*j].is_some_and(|t| (time - t).num_seconds() >= COURSE_SECS))
+                    .find_map(position)?;
+                Some((latitude, longitude, airports::bearing(lat, lon, latitude, longitude)))
+            };
+            if let Some((lat, lon, course)) = takeoff.and_then(on_runway) {
+                if let Some((airport, runway)) = database.identify(lat, lon, course) {
+                    summary.departure = Some(airport.ident.clone());
+                    summary.departure_runway = runway.map(|r| r.runway);
+                }
+            }
+            if let Some((lat, lon, course)) = landing.and_then(on_runway) {
+                if let Some((airport, runway)) = database.identify(lat, lon, course) {
+                    summary.arrival = Some(airport.ident.clone());
+                    summary.arrival_runway = runway.as_ref().map(|r| r.runway.clone());
+                    summary.touchdown = runway;
+                }
+            }
+        }
+
+        let fuel = derive::derive_columns(data.clone(), &[Derivation::FuelBurned]).map_err(read_error)?;
+        if let Some(used) = track::float_column(&fuel, "FUsed").map_err(read_error)? {
+            let at_out = used[first..=last].iter().flatten().next();
+            let at_in = used[first..=last].iter().flatten().last();
+            summary.fuel_used = at_out.zip(at_in).map(|(out, at_in)| at_in - out);
+        }
+        Ok(summary)
+    }
+
+    /// The number of landings, by day and night
+    pub fn landings(&self) -> usize {
+        self.day_landings + self.night_landings
+    }
+}
+
+impl Display for FlightSummary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let time = |t: Option<DateTime<Utc>>| t.map_or("-".to_string(), |t| t.format("%H:%MZ").to_string());
+        let text = |t: &Option<String>| t.clone().unwrap_or_else(|| "-".to_string());
+        writeln!(
+            f,
+            "{} {} ({})",
+            self.date.map_or("-".to_string(), |d| d.to_string()),
+            text(&self.tail_number),
+            text(&self.aircraft_type)
+        )?;
+        let airport = |airport: &Option<String>, runway: &Option<String>| match runway {
+            Some(runway) => format!("{} runway {}", text(airport), runway),
+            None => text(airport),
+        };
+        writeln!(
+            f,
+            "  Route:    {} to {}",
+            airport(&self.departure, &self.departure_runway),
+            airport(&self.arrival, &self.arrival_runway)
+        )?;
+        writeln!(
+            f,
+            "  Times:    out {}, off {}, on {}, in {}",
+            time(self.block_out),
+            time(self.takeoff),
+            time(self.landing),
+            time(self.block_in)
+        )?;
+        writeln!(
+            f,
+            "  Hours:    {:.1} block, {:.1} flight, {:.1} night",
+            self.block_hours, self.flight_hours, self.night_hours
+        )?;
+        writeln!(f, "  Distance: {:.1} nm", self.distance_nm)?;
+        if let Some(altitude) = self.max_altitude_ft {
+            writeln!(f, "  Altitude: {:.0} ft max", altitude)?;
+        }
+        if let Some(fuel) = self.fuel_used {
+            writeln!(f, "  Fuel:     {:.1} {}", fuel, self.fuel_unit)?;
+        }
+        write!(
+            f,
+            "  Landings: {} ({} day, {} night, {} full stop)",
+            self.landings(),
+            self.day_landings,
+            self.night_landings,
+            self.day_full_stop_landings + self.night_full_stop_landings
+        )?;
+        if let Some(touchdown) = &self.touchdown {
+            write!(f, "\n  Touchdown: {}", touchdown)?;
+        }
+        Ok(())
+    }
+}
+
+/// The columns of logbook CSV rows. Landings are counted as full stops and as all landings, including touch and goes
+pub const LOGBOOK_COLUMNS: [&str; 19] = [
+    "Date",
+    "AircraftID",
+    "Airframe",
+    "From",
+    "To",
+    "TimeOut",
+    "TimeOff",
+    "TimeOn",
+    "TimeIn",
+    "TotalTime",
+    "FlightTime",
+    "Night",
+    "Distance",
+    "DayTakeoffs",
+    "DayLandingsFullStop",
+    "NightTakeoffs",
+    "NightLandingsFullStop",
+    "AllLandings",
+    "FuelUsed",
+];
+
+/// Write summaries as logbook CSV rows with a header, times as HH:MM UTC and durations in decimal hours
+pub fn write_logbook_csv<W: Write>(summaries: &[FlightSummary], writer: &mut W) -> std::io::Result<()> {
+    writeln!(writer, "{}", LOGBOOK_COLUMNS.join(","))?;
+    let text = |t: &Option<String>| match t {
+        Some(t) if t.contains([',', '"']) => format!("\"{}\"", t.replace('"', "\"\"")),
+        Some(t) => t.clone(),
+        None => String::new(),
+    };
+    let time = |t: Option<DateTime<Utc>>| t.map_or(String::new(), |t| t.format("%H:%M").to_string());
+    for s in summaries {
+        let row = [
+            s.date.map_or(String::new(), |d| d.to_string()),
+            text(&s.tail_number),
+            text(&s.aircraft_type),
+            text(&s.departure),
+            text(&s.arrival),
+            time(s.block_out),
+            time(s.takeoff),
+            time(s.landing),
+            time(s.block_in),
+            format!("{:.1}", s.block_hours),
+            format!("{:.1}", s.flight_hours),
+            format!("{:.1}", s.night_hours),
+            format!("{:.1}", s.distance_nm),
+            s.day_takeoffs.to_string(),
+            s.day_full_stop_landings.to_string(),
+            s.night_takeoffs.to_string(),
+            s.night_full_stop_landings.to_string(),
+            s.landings().to_string(),
+            s.fuel_used.map_or(String::new(), |f| format!("{:.1}", f)),
+        ];
+        writeln!(writer, "{}", row.join(","))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        airports::AirportDatabase, detection::read_avionics_log, fdr::FDRConfigurationBuilder, AviationLogSourceOption,
+    };
+    use std::sync::Arc;
+
+    #[test]
+    fn test_summary_of_sample_flight() -> Result<(), Box<dyn std::error::Error>> {
+        let path = crate::resource_path("log_231104_084813_KPOU.csv");
+        let source = read_avionics_log(&AviationLogSourceOption::Garmin, &path)?;
+        let summary = FlightSummary::from_source(source.as_ref(), &FDRConfigurationBuilder::default().build())?;
+
+        assert_eq!(summary.tail_number.as_deref(), Some("N12345"));
+        assert_eq!(summary.departure.as_deref(), Some("KPOU"));
+        let time = |t: Option<DateTime<Utc>>| t.map(|t| t.format("%H:%M:%S").to_string());
+        assert_eq!(time(summary.takeoff).as_deref(), Some("13:02:28"));
+        assert_eq!(time(summary.landing).as_deref(), Some("13:45:10"));
+        assert!(summary.block_out < summary.takeoff && summary.landing < summary.block_in);
+        assert!((summary.flight_hours - 0.71).abs() < 0.01);
+        assert_eq!(
+            (summary.day_takeoffs, summary.day_landings, summary.landings()),
+            (1, 1, 1)
+        );
+        assert_eq!(summary.day_full_stop_landings, 1);
+        // a morning flight
+        assert_eq!(summary.night_hours, 0.0);
+        assert!(summary.distance_nm > 50.0);
+        assert!(summary.max_altitude_ft.unwrap() > 4000.0);
+        assert!(summary.fuel_used.unwrap() > 1.0);
+        // in the unit of fuel of the fuel flow, or of the engines logged when the header doesn't give it
+        assert_eq!(summary.fuel_unit, "gals");
+        let turbine = [("E1 ITT", "deg C"), ("E1 FFlow", "")].map(|(n, u)| (n.to_string(), u.to_string()));
+        assert_eq!(derive::fuel_unit(&turbine, &Default::default()), "lbs");
+
+        let mut buffer = Vec::new();
+        write_logbook_csv(&[summary], &mut buffer)?;
+        let csv = String::from_utf8(buffer)?;
+        let row: Vec<&str> = csv.lines().nth(1).unwrap().split(',').collect();
+        assert_eq!(row.len(), LOGBOOK_COLUMNS.len());
+        assert_eq!(&row[..4], ["2023-11-04", "N12345", "Mooney M20J", "KPOU"]);
+        assert_eq!(row[6], "13:02");
+
+        // the airports and runways are identified from a database
+        let database = AirportDatabase::load(&crate::resource_path("ourairports"))?;
+        let config = FDRConfigurationBuilder::default()
+            .airports(Some(Arc::new(database)))
+            .build();
+        let summary = FlightSummary::from_source(source.as_ref(), &config)?;
+        assert_eq!(summary.departure.as_deref(), Some("KPOU"));
+        assert_eq!(summary.departure_runway.as_deref(), Some("24"));
+        assert_eq!(summary.arrival.as_deref(), Some("N40"));
+        assert_eq!(summary.arrival_runway.as_deref(), Some("25"));
+        let touchdown = summary.touchdown.unwrap();
+        assert!(touchdown.distance_from_threshold_ft > 0.0 && touchdown.centerline_offset_ft.abs() < 100.0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_sun_elevation() {
+        // noon and midnight at Greenwich near the equinox
+        let noon = DateTime::parse_from_rfc3339("2024-03-20T12:07:00Z").unwrap().to_utc();
+        assert!((sun_elevation(0.0, 0.0, &noon) - 90.0).abs() < 1.0);
+        let midnight = DateTime::parse_from_rfc3339("2024-03-20T00:07:00Z").unwrap().to_utc();
+        assert!(sun_elevation(51.5, 0.0, &midnight) < CIVIL_TWILIGHT_DEG);
+    }
+}
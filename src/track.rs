@@ -0,0 +1,320 @@
+//! The flight path of a data block: positioned, timed samples of the aircraft and the phase of flight at each.
+
+use crate::fdr::{FlightDataBlock, FlightDataError};
+use chrono::{DateTime, Utc};
+use polars::prelude::*;
+
+/// Mean radius of the earth in nautical miles
+const EARTH_RADIUS_NM: f64 = 3440.065;
+
+/// Feet in a meter
+pub const FEET_PER_METER: f64 = 3.28084;
+
+/// Without `OnGrnd`, the aircraft is flying above this ground speed in knots, and on the ground below it
+pub const FLYING_GROUND_SPEED_KT: f64 = 50.0;
+
+/// The aircraft must stay on the ground or in the air this many seconds for it to have landed or taken off
+const GROUND_DWELL_SECS: i64 = 3;
+
+/// One sample of the required fields of a data block
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TrackPoint {
+    pub time: DateTime<Utc>,
+    pub longitude: f64,
+    pub latitude: f64,
+    /// Altitude in feet
+    pub altitude: f64,
+    /// Heading in degrees
+    pub heading: f64,
+    /// Pitch in degrees, nose up positive
+    pub pitch: f64,
+    /// Roll in degrees, right wing down positive
+    pub roll: f64,
+}
+
+/// The values of a column as floats, or nulls if it cannot be cast
+pub(crate) fn float_values(df: &DataFrame, idx: usize) -> Result<Vec<Option<f64>>, FlightDataError> {
+    let column = df.select_at_idx(idx).ok_or(FlightDataError::InsufficientData)?;
+    let values = column
+        .cast(&DataType::Float64)
+        .map_err(|e| FlightDataError::ReadError(e.to_string()))?;
+    let values = values.f64().map_err(|e| FlightDataError::ReadError(e.to_string()))?;
+    Ok(values.into_iter().collect())
+}
+
+/// The values of a named column of a data block as floats, if it has the column
+pub fn column_values(block: &FlightDataBlock, name: &str) -> Option<Vec<Option<f64>>> {
+    let idx = block.data.get_column_index(name)?;
+    float_values(&block.data, idx).ok()
+}
+
+/// The values of a named column of a dataframe as floats, if it has the column
+pub fn float_column(df: &DataFrame, name: &str) -> PolarsResult<Option<Vec<Option<f64>>>> {
+    match df.column(name) {
+        Ok(c) => Ok(Some(c.cast(&DataType::Float64)?.f64()?.into_iter().collect())),
+        Err(_) => Ok(None),
+    }
+}
+
+/// Whether the aircraft was on the ground at each record of a log, from its `OnGrnd` field, or its ground speed when
+/// that isn't logged, and None where neither is. Changes that last less than [GROUND_DWELL_SECS] are ignored, so a
+/// bounce on landing is not a takeoff and a second landing.
+pub fn on_ground(df: &DataFrame, times: &[Option<DateTime<Utc>>]) -> PolarsResult<Vec<Option<bool>>> {
+    let on_ground: Vec<Option<bool>> = match float_column(df, "OnGrnd")? {
+        Some(on_ground) => on_ground.iter().map(|v| v.map(|v| v != 0.0)).collect(),
+        None => match float_column(df, "GndSpd")? {
+            Some(speed) => speed.iter().map(|v| v.map(|v| v < FLYING_GROUND_SPEED_KT)).collect(),
+            None => vec![None; df.height()],
+        },
+    };
+    Ok(debounce_ground(&on_ground, times))
+}
+
+/// Whether the aircraft was in the air at each record of a log, as [on_ground] finds it. A log without the fields to
+/// tell is taken to be in the air throughout, rather than on the ground, so that it is still analyzed.
+pub fn airborne(df: &DataFrame, times: &[Option<DateTime<Utc>>]) -> PolarsResult<Vec<bool>> {
+    let on_ground = on_ground(df, times)?;
+    if on_ground.iter().all(Option::is_none) {
+        return Ok(vec![true; df.height()]);
+    }
+    Ok(on_ground.iter().map(|g| *g == Some(false)).collect())
+}
+
+/// Ignore the changes between on the ground and in the air that last less than [GROUND_DWELL_SECS]
+fn debounce_ground(on_ground: &[Option<bool>], times: &[Option<DateTime<Utc>>]) -> Vec<Option<bool>> {
+    let mut debounced = on_ground.to_vec();
+    let mut state: Option<bool> = None;
+    let mut i = 0;
+    while i < on_ground.len() {
+        let Some(ground) = on_ground[i] else {
+            i += 1;
+            continue;
+        };
+        let Some(current) = state.filter(|s| *s != ground) else {
+            state = Some(ground);
+            i += 1;
+            continue;
+        };
+        // the records until the aircraft is back in its current state, if it ever is
+        let end = (i..on_ground.len())
+            .find(|j| on_ground[*j] == Some(current))
+            .unwrap_or(on_ground.len());
+        let dwell = times
+            .get(end)
+            .copied()
+            .flatten()
+            .zip(times[i])
+            .map(|(back, from)| back - from);
+        if dwell.is_some_and(|d| d.num_seconds() < GROUND_DWELL_SECS) {
+            for value in debounced[i..end].iter_mut().filter(|v| v.is_some()) {
+                *value = Some(current);
+            }
+        } else {
+            state = Some(ground);
+        }
+        i = end;
+    }
+    debounced
+}
+
+/// The timestamps of the first column of a dataframe
+fn time_values(df: &DataFrame) -> Result<Vec<Option<DateTime<Utc>>>, FlightDataError> {
+    let column = df.select_at_idx(0).ok_or(FlightDataError::InsufficientData)?;
+    let ts = column
+        .datetime()
+        .map_err(|e| FlightDataError::ReadError(e.to_string()))?;
+    let to_utc = |v: i64| match ts.time_unit() {
+        TimeUnit::Nanoseconds => Some(DateTime::<Utc>::from_timestamp_nanos(v)),
+        TimeUnit::Microseconds => DateTime::<Utc>::from_timestamp_micros(v),
+        TimeUnit::Milliseconds => DateTime::<Utc>::from_timestamp_millis(v),
+    };
+    Ok(ts.into_iter().map(|v| v.and_then(to_utc)).collect())
+}
+
+/// The indices of the records of a data block that have every required field, and those records as track points
+pub fn indexed_track_points(block: &FlightDataBlock) -> Result<Vec<(usize, TrackPoint)>, FlightDataError> {
+    indexed_frame_points(&block.data)
+}
+
+/// As [indexed_track_points], for any dataframe whose first 7 columns are the required fields of a data block
+pub fn indexed_frame_points(df: &DataFrame) -> Result<Vec<(usize, TrackPoint)>, FlightDataError> {
+    let times = time_values(df)?;
+    let fields = (1..7).map(|idx| float_values(df, idx)).collect::<Result<Vec<_>, _>>()?;
+
+    Ok((0..df.height())
+        .filter_map(|i| {
+            let v = |field: usize| fields[field][i];
+            Some((
+                i,
+                TrackPoint {
+                    time: times[i]?,
+                    longitude: v(0)?,
+                    latitude: v(1)?,
+                    altitude: v(2)?,
+                    heading: v(3)?,
+                    pitch: v(4)?,
+                    roll: v(5)?,
+                },
+            ))
+        })
+        .collect())
+}
+
+/// The records of a data block that have every required field, as track points
+pub fn track_points(block: &FlightDataBlock) -> Result<Vec<TrackPoint>, FlightDataError> {
+    Ok(indexed_track_points(block)?.into_iter().map(|(_, p)| p).collect())
+}
+
+/// Great circle distance between two positions, in nautical miles
+pub fn distance_nm(lat1: f64, lon1: f64, lat2: f64, lon2: f64) -> f64 {
+    let (lat1, lat2) = (lat1.to_radians(), lat2.to_radians());
+    let d_lat = lat2 - lat1;
+    let d_lon = (lon2 - lon1).to_radians();
+    let a = (d_lat / 2.0).sin().powi(2) + lat1.cos() * lat2.cos() * (d_lon / 2.0).sin().powi(2);
+    2.0 * EARTH_RADIUS_NM * a.sqrt().asin()
+}
+
+impl TrackPoint {
+    /// Distance to another point, in nautical miles
+    pub fn distance_to(&self, other: &TrackPoint) -> f64 {
+        distance_nm(self.latitude, self.longitude, other.latitude, other.longitude)
+    }
+
+    /// Seconds elapsed from an earlier point
+    pub fn seconds_since(&self, earlier: &TrackPoint) -> f64 {
+        (self.time - earlier.time).num_milliseconds() as f64 / 1000.0
+    }
+}
+
+/// The phase of flight of a sample
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FlightPhase {
+    Ground,
+    Climb,
+    Cruise,
+    Descent,
+}
+
+impl FlightPhase {
+    pub fn name(&self) -> &'static str {
+        match self {
+            FlightPhase::Ground => "Ground",
+            FlightPhase::Climb => "Climb",
+            FlightPhase::Cruise => "Cruise",
+            FlightPhase::Descent => "Descent",
+        }
+    }
+}
+
+/// Vertical speeds beyond this many feet per minute are taken to be climbing or descending
+const VERTICAL_SPEED_FPM: f64 = 300.0;
+/// Speeds are averaged over this many seconds either side of a sample, to smooth over noisy positions
+const PHASE_WINDOW_SECS: f64 = 5.0;
+
+/// Ground speed in knots and vertical speed in feet per minute at each point, averaged over a short window
+pub fn speeds(points: &[TrackPoint]) -> Vec<(f64, f64)> {
+    let mut speeds = Vec::with_capacity(points.len());
+    let (mut first, mut last) = (0, 0);
+    for (i, p) in points.iter().enumerate() {
+        while p.seconds_since(&points[first]) > PHASE_WINDOW_SECS {
+            first += 1;
+        }
+        while last + 1 < points.len() && points[last + 1].seconds_since(p) <= PHASE_WINDOW_SECS {
+            last += 1;
+        }
+        let (a, b) = (&points[first], &points[last.max(i)]);
+        let seconds = b.seconds_since(a);
+        if seconds > 0.0 {
+            speeds.push((
+                a.distance_to(b) * 3600.0 / seconds,
+                (b.altitude - a.altitude) * 60.0 / seconds,
+            ));
+        } else {
+            speeds.push((0.0, 0.0));
+        }
+    }
+    speeds
+}
+
+/// Classify the phase of flight at each point from its ground and vertical speed
+pub fn phases(points: &[TrackPoint]) -> Vec<FlightPhase> {
+    speeds(points)
+        .into_iter()
+        .map(|(ground_speed, vertical_speed)| {
+            if ground_speed < FLYING_GROUND_SPEED_KT {
+                FlightPhase::Ground
+            } else if vertical_speed > VERTICAL_SPEED_FPM {
+                FlightPhase::Climb
+            } else if vertical_speed < -VERTICAL_SPEED_FPM {
+                FlightPhase::Descent
+            } else {
+                FlightPhase::Cruise
+            }
+        })
+        .collect()
+}
+
+/// Split a sequence into runs of consecutive equal values, as (value, first index, length)
+pub fn runs<T: PartialEq + Copy>(values: &[T]) -> Vec<(T, usize, usize)> {
+    let mut runs: Vec<(T, usize, usize)> = Vec::new();
+    for (i, v) in values.iter().enumerate() {
+        match runs.last_mut() {
+            Some(run) if run.0 == *v => run.2 += 1,
+            _ => runs.push((*v, i, 1)),
+        }
+    }
+    runs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{detection::read_avionics_log, fdr::FDRConfigurationBuilder, AviationLogSourceOption};
+
+    #[test]
+    fn test_phases_of_sample_flight() -> Result<(), Box<dyn std::error::Error>> {
+        let path = crate::resource_path("log_231104_084813_KPOU.csv");
+        let source = read_avionics_log(&AviationLogSourceOption::Garmin, &path)?;
+        let block = source.data_block(&FDRConfigurationBuilder::default().build())?;
+        let points = track_points(&block)?;
+        assert_eq!(points.len(), 3676 - 149);
+
+        // the flight starts and ends on the ground, and climbs before it descends
+        let phases = phases(&points);
+        let runs = runs(&phases);
+        assert_eq!(runs.first().map(|r| r.0), Some(FlightPhase::Ground));
+        assert_eq!(runs.last().map(|r| r.0), Some(FlightPhase::Ground));
+        let climb = phases.iter().position(|p| *p == FlightPhase::Climb).unwrap();
+        let descent = phases.iter().rposition(|p| *p == FlightPhase::Descent).unwrap();
+        assert!(climb < descent);
+        Ok(())
+    }
+
+    #[test]
+    fn test_bounce_is_one_landing() {
+        let start = DateTime::parse_from_rfc3339("2023-11-04T13:45:00Z").unwrap().to_utc();
+        let times: Vec<Option<DateTime<Utc>>> = (0..12).map(|s| Some(start + chrono::Duration::seconds(s))).collect();
+        // a touchdown, a bounce into the air for a second, and a touch and go
+        let on_ground: Vec<Option<bool>> = [0, 0, 1, 0, 1, 1, 1, 1, 0, 0, 0, 0]
+            .iter()
+            .map(|v| Some(*v == 1))
+            .collect();
+        let debounced: Vec<bool> = debounce_ground(&on_ground, &times).into_iter().flatten().collect();
+        // the aircraft lands once, when it settles after the bounce, and takes off once
+        assert_eq!(
+            debounced,
+            [false, false, false, false, true, true, true, true, false, false, false, false]
+        );
+    }
+
+    #[test]
+    fn test_airborne_without_ground_fields() -> Result<(), Box<dyn std::error::Error>> {
+        let times = vec![None; 3];
+        let logged = DataFrame::new(vec![Column::new("OnGrnd".into(), [1.0, 0.0, 0.0])])?;
+        assert_eq!(airborne(&logged, &times)?, [false, true, true]);
+        // without OnGrnd or a ground speed, every record is analyzed as in the air
+        let unlogged = DataFrame::new(vec![Column::new("IAS".into(), [0.0, 90.0, 90.0])])?;
+        assert_eq!(airborne(&unlogged, &times)?, [true, true, true]);
+        Ok(())
+    }
+}
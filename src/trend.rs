@@ -0,0 +1,515 @@
+//! Engine trend monitoring: stabilized cruise snapshots of the engines, collected across flights into a dataset per
+//! aircraft, with flags where a cylinder or engine parameter drifts from its history.
+//!
+//! A snapshot is the mean of the air data and engine fields over at least a minute of airborne flight with steady
+//! RPM, manifold pressure and altitude. Temperatures are normalized to their rise above the outside air, and fuel flow
+//! to the flow at 100% power, so that snapshots flown on different days can be compared.
+
+use crate::fdr::{FDRWriteError, FlightDataError, FlightDataSource};
+use crate::garmin::match_engine_pattern;
+use crate::track;
+use chrono::{DateTime, SecondsFormat, Utc};
+use polars::prelude::*;
+use std::collections::{BTreeMap, BTreeSet};
+use std::{fmt::Display, fs::File, io::Write, path::Path};
+
+/// A cruise is stable for a snapshot after this many seconds
+const MIN_STABLE_SECS: f64 = 60.0;
+/// A long stable cruise is divided into snapshots of at most this many seconds
+const MAX_STABLE_SECS: f64 = 300.0;
+/// The widest range of the RPM of each engine during a stable cruise
+const RPM_BAND: f64 = 50.0;
+/// The widest range of the manifold pressure of each engine during a stable cruise, in inches of mercury
+const MAP_BAND_INHG: f64 = 0.5;
+/// The widest range of altitude during a stable cruise, in feet
+const ALTITUDE_BAND_FT: f64 = 150.0;
+/// A gap in recording longer than this many seconds ends a stable cruise
+const MAX_GAP_SECS: f64 = 5.0;
+/// The air data fields recorded in a snapshot
+const AIR_FIELDS: [&str; 5] = ["AltB", "AltPress", "OAT", "IAS", "TAS"];
+/// The engine fields recorded in a snapshot, as engine rule patterns
+const ENGINE_FIELDS: [&str; 8] = [
+    "E{n} RPM",
+    "E{n} MAP",
+    "E{n} %Pwr",
+    "E{n} FFlow",
+    "E{n} OilT",
+    "E{n} OilP",
+    "E{n} CHT{m}",
+    "E{n} EGT{m}",
+];
+/// Engine fields in deg F that are normalized to their rise above the outside air temperature
+const TEMPERATURE_FIELDS: [&str; 3] = ["E{n} OilT", "E{n} CHT{m}", "E{n} EGT{m}"];
+/// A parameter is only compared with its history once it has this many earlier snapshots
+const MIN_HISTORY: usize = 5;
+/// A parameter drifts when it is further than this many standard deviations from the mean of its history
+const DRIFT_SIGMAS: f64 = 3.0;
+/// The smallest drift flagged for each kind of parameter, so that a very steady history doesn't flag noise
+const DRIFT_FLOORS: [(&str, f64); 5] = [
+    ("CHT", 15.0),
+    ("EGT", 30.0),
+    ("OilT", 10.0),
+    ("OilP", 5.0),
+    ("FFlow", 0.5),
+];
+/// The suffix of the names of normalized columns
+const NORM_SUFFIX: &str = " norm";
+/// The columns of a trend dataset that are not fields of the log
+const TAIL_COLUMN: &str = "tail";
+const LOG_COLUMN: &str = "log";
+const START_COLUMN: &str = "start";
+const SECONDS_COLUMN: &str = "seconds";
+const ANOMALIES_COLUMN: &str = "anomalies";
+
+/// Formats that trend datasets can be written as
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum TrendFormat {
+    /// Parquet, one row per snapshot
+    #[default]
+    Parquet,
+    /// csv with a header row, one row per snapshot
+    Csv,
+}
+
+impl TrendFormat {
+    /// The file extension of the format
+    pub fn extension(&self) -> &'static str {
+        match self {
+            TrendFormat::Parquet => "parquet",
+            TrendFormat::Csv => "csv",
+        }
+    }
+}
+
+/// The mean engine and air data of a stabilized cruise
+#[derive(Debug, Clone, PartialEq)]
+pub struct CruiseSnapshot {
+    /// The file name of the log the snapshot was taken from
+    pub log: String,
+    pub start: DateTime<Utc>,
+    /// The length of the stable cruise
+    pub seconds: f64,
+    /// The mean of each air data and engine field that was logged
+    pub values: BTreeMap<String, f64>,
+}
+
+impl CruiseSnapshot {
+    /// The normalized values of the snapshot, named with a ` norm` suffix: oil, cylinder head and exhaust gas
+    /// temperatures as their rise above the outside air in deg F, and fuel flow as the flow at 100% power
+    pub fn normalized(&self) -> BTreeMap<String, f64> {
+        let oat_f = self.values.get("OAT").map(|c| c * 9.0 / 5.0 + 32.0);
+        let mut normalized = BTreeMap::new();
+        for (name, value) in &self.values {
+            let norm = if TEMPERATURE_FIELDS
+                .iter()
+                .any(|p| match_engine_pattern(p, name).is_some())
+            {
+                oat_f.map(|oat| value - oat)
+            } else if let Some((engine, _)) = match_engine_pattern("E{n} FFlow", name) {
+                self.values
+                    .get(&format!("E{} %Pwr", engine))
+                    .filter(|power| **power > 0.0)
+                    .map(|power| value * 100.0 / power)
+            } else {
+                None
+            };
+            if let Some(norm) = norm {
+                normalized.insert(format!("{}{}", name, NORM_SUFFIX), norm);
+            }
+        }
+        normalized
+    }
+
+    /// The parameters compared with their history: the difference of each cylinder head and exhaust gas temperature
+    /// from the mean of the cylinders of its engine, oil pressure, and the normalized oil temperature and fuel flow
+    fn metrics(&self) -> BTreeMap<String, f64> {
+        let mut metrics = BTreeMap::new();
+        for family in ["CHT", "EGT"] {
+            let pattern = format!("E{{n}} {}{{m}}", family);
+            let mut engines: BTreeMap<usize, Vec<(&String, f64)>> = BTreeMap::new();
+            for (name, value) in &self.values {
+                if let Some((engine, Some(_))) = match_engine_pattern(&pattern, name) {
+                    engines.entry(engine).or_default().push((name, *value));
+                }
+            }
+            for cylinders in engines.values() {
+                let mean = cylinders.iter().map(|(_, v)| v).sum::<f64>() / cylinders.len() as f64;
+                metrics.extend(cylinders.iter().map(|(name, value)| (name.to_string(), value - mean)));
+            }
+        }
+        for (name, value) in &self.values {
+            if match_engine_pattern("E{n} OilP", name).is_some() {
+                metrics.insert(name.clone(), *value);
+            }
+        }
+        for (name, value) in self.normalized() {
+            let field = name.trim_end_matches(NORM_SUFFIX);
+            if ["E{n} OilT", "E{n} FFlow"]
+                .iter()
+                .any(|p| match_engine_pattern(p, field).is_some())
+            {
+                metrics.insert(name, value);
+            }
+        }
+        metrics
+    }
+}
+
+/// A parameter of a snapshot that drifted from its history
+#[derive(Debug, Clone, PartialEq)]
+pub struct TrendAnomaly {
+    pub log: String,
+    pub start: DateTime<Utc>,
+    /// The drifting parameter, a cylinder such as `E1 CHT3`, or an engine parameter such as `E1 OilT norm`
+    pub parameter: String,
+    /// The difference of the parameter from the mean of its history
+    pub drift: f64,
+}
+
+impl Display for TrendAnomaly {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} {}: {} drifted {:+.1} from its history",
+            self.start.to_rfc3339_opts(SecondsFormat::Secs, true),
+            self.log,
+            self.parameter,
+            self.drift
+        )
+    }
+}
+
+/// Find the stabilized cruises of the flight logged by a source, and take a snapshot of each. Logs without an engine
+/// RPM have no snapshots.
+pub fn cruise_snapshots(source: &dyn FlightDataSource, log: &str) -> Result<Vec<CruiseSnapshot>, FlightDataError> {
+    let data = source.data()?;
+    let read_error = |e: PolarsError| FlightDataError::ReadError(e.to_string());
+    let times: Vec<Option<DateTime<Utc>>> = data
+        .column("timestamp")
+        .and_then(|c| Ok(c.datetime()?.cast_time_unit(TimeUnit::Microseconds)))
+        .map_err(read_error)?
+        .into_iter()
+        .map(|t| t.and_then(DateTime::<Utc>::from_timestamp_micros))
+        .collect();
+    let seconds = |i: usize| times[i].map(|t| t.timestamp_micros() as f64 / 1e6);
+    let column = |name: &str| track::float_column(&data, name).map_err(read_error);
+
+    let mut fields = Vec::new();
+    let mut steady = Vec::new();
+    for name in data.get_column_names() {
+        let is_field = AIR_FIELDS.contains(&name.as_str())
+            || ENGINE_FIELDS.iter().any(|p| match_engine_pattern(p, name).is_some());
+        if !is_field {
+            continue;
+        }
+        let values = column(name)?.unwrap_or_default();
+        if match_engine_pattern("E{n} RPM", name).is_some() {
+            steady.push((values.clone(), RPM_BAND));
+        } else if match_engine_pattern("E{n} MAP", name).is_some() {
+            steady.push((values.clone(), MAP_BAND_INHG));
+        }
+        fields.push((name.to_string(), values));
+    }
+    if !data
+        .get_column_names()
+        .iter()
+        .any(|n| match_engine_pattern("E{n} RPM", n).is_some())
+    {
+        return Ok(Vec::new());
+    }
+    if let Some(altitude) = match column("AltB")? {
+        Some(altitude) => Some(altitude),
+        None => column("AltMSL")?,
+    } {
+        steady.push((altitude, ALTITUDE_BAND_FT));
+    }
+    let airborne = track::airborne(&data, &times).map_err(read_error)?;
+
+    let mut snapshots = Vec::new();
+    let mut start = 0;
+    while start < data.height() {
+        // extend the cruise while every steady field stays within its band
+        let mut end = start;
+        let mut ranges = vec![(f64::INFINITY, f64::NEG_INFINITY); steady.len()];
+        while end < data.height() && airborne[end] {
+            let (Some(time), Some(first)) = (seconds(end), seconds(start)) else {
+                break;
+            };
+            if end > start
+                && (seconds(end - 1).is_none_or(|t| time - t > MAX_GAP_SECS) || time - first > MAX_STABLE_SECS)
+            {
+                break;
+            }
+            let extended: Option<Vec<(f64, f64)>> = steady
+                .iter()
+                .zip(&ranges)
+                .map(|((values, band), (low, high))| {
+                    let value = values[end]?;
+                    let (low, high) = (low.min(value), high.max(value));
+                    (high - low <= *band).then_some((low, high))
+                })
+                .collect();
+            let Some(extended) = extended else {
+                break;
+            };
+            ranges = extended;
+            end += 1;
+        }
+
+        let length = match (seconds(start), end.checked_sub(1).and_then(seconds)) {
+            (Some(first), Some(last)) if end > start => last - first,
+            _ => 0.0,
+        };
+        if length < MIN_STABLE_SECS {
+            start += 1;
+            continue;
+        }
+        let values = fields
+            .iter()
+            .filter_map(|(name, values)| {
+                let logged: Vec<f64> = values[start..end].iter().flatten().copied().collect();
+                (!logged.is_empty()).then(|| (name.clone(), logged.iter().sum::<f64>() / logged.len() as f64))
+            })
+            .collect();
+        snapshots.push(CruiseSnapshot {
+            log: log.to_string(),
+            start: times[start].ok_or(FlightDataError::InsufficientData)?,
+            seconds: length,
+            values,
+        });
+        start = end;
+    }
+    Ok(snapshots)
+}
+
+/// The cruise snapshots of an aircraft, across flights
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TrendDataset {
+    pub tail_number: String,
+    /// Snapshots in order of their start
+    pub snapshots: Vec<CruiseSnapshot>,
+}
+
+impl TrendDataset {
+    /// An empty dataset for an aircraft
+    pub fn new(tail_number: String) -> Self {
+        TrendDataset {
+            tail_number,
+            snapshots: Vec::new(),
+        }
+    }
+
+    /// Read a dataset written by [TrendDataset::write]. Normalized values and anomalies are not read, as they are
+    /// computed from the snapshots.
+    pub fn load(tail_number: String, path: &Path, format: TrendFormat) -> Result<Self, FDRWriteError> {
+        let df = match format {
+            TrendFormat::Parquet => ParquetReader::new(File::open(path)?).finish()?,
+            TrendFormat::Csv => CsvReadOptions::default()
+                .with_has_header(true)
+                .try_into_reader_with_file_path(Some(path.to_path_buf()))?
+                .finish()?,
+        };
+        let text = |name: &str| -> PolarsResult<Vec<Option<String>>> {
+            Ok(df
+                .column(name)?
+                .cast(&DataType::String)?
+                .str()?
+                .into_iter()
+                .map(|s| s.map(str::to_string))
+                .collect())
+        };
+        let (logs, starts) = (text(LOG_COLUMN)?, text(START_COLUMN)?);
+        let lengths = track::float_column(&df, SECONDS_COLUMN)?.unwrap_or_else(|| vec![None; df.height()]);
+        let mut fields = Vec::new();
+        for name in df.get_column_names() {
+            let fixed = [TAIL_COLUMN, LOG_COLUMN, START_COLUMN, SECONDS_COLUMN, ANOMALIES_COLUMN];
+            if !fixed.contains(&name.as_str()) && !name.ends_with(NORM_SUFFIX) {
+                fields.push((name.to_string(), track::float_column(&df, name)?.unwrap_or_default()));
+            }
+        }
+
+        let mut dataset = TrendDataset::new(tail_number);
+        for i in 0..df.height() {
+            let start = starts[i]
+                .as_deref()
+                .and_then(|s| DateTime::parse_from_rfc3339(s).ok())
+                .ok_or_else(|| FlightDataError::ReadError(format!("Snapshot {} of the trend has no start", i + 1)))?;
+            dataset.snapshots.push(CruiseSnapshot {
+                log: logs[i].clone().unwrap_or_default(),
+                start: start.with_timezone(&Utc),
+                seconds: lengths[i].unwrap_or_default(),
+                values: fields
+                    .iter()
+                    .filter_map(|(name, values)| values[i].map(|v| (name.clone(), v)))
+                    .collect(),
+            });
+        }
+        dataset.snapshots.sort_by_key(|s| s.start);
+        Ok(dataset)
+    }
+
+    /// Add snapshots to the dataset, replacing any taken from the same log at the same time
+    pub fn merge(&mut self, snapshots: Vec<CruiseSnapshot>) {
+        for snapshot in snapshots {
+            self.snapshots
+                .retain(|s| !(s.log == snapshot.log && s.start == snapshot.start));
+            self.snapshots.push(snapshot);
+        }
+        self.snapshots.sort_by_key(|s| s.start);
+    }
+
+    /// The parameters of each snapshot that drifted from the snapshots before it
+    pub fn anomalies(&self) -> Vec<Vec<TrendAnomaly>> {
+        let metrics: Vec<BTreeMap<String, f64>> = self.snapshots.iter().map(|s| s.metrics()).collect();
+        self.snapshots
+            .iter()
+            .enumerate()
+            .map(|(i, snapshot)| {
+                metrics[i]
+                    .iter()
+                    .filter_map(|(parameter, value)| {
+                        let history: Vec<f64> = metrics[..i].iter().filter_map(|m| m.get(parameter)).copied().collect();
+                        if history.len() < MIN_HISTORY {
+                            return None;
+                        }
+                        let n = history.len() as f64;
+                        let mean = history.iter().sum::<f64>() / n;
+                        let deviation = (history.iter().map(|h| (h - mean).powi(2)).sum::<f64>() / (n - 1.0)).sqrt();
+                        let floor = DRIFT_FLOORS
+                            .iter()
+                            .find(|(kind, _)| parameter.contains(kind))
+                            .map_or(0.0, |(_, floor)| *floor);
+                        let drift = value - mean;
+                        (drift.abs() > (DRIFT_SIGMAS * deviation).max(floor)).then(|| TrendAnomaly {
+                            log: snapshot.log.clone(),
+                            start: snapshot.start,
+                            parameter: parameter.clone(),
+                            drift,
+                        })
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    /// The dataset as a table with one row per snapshot: the aircraft, log, start and length of the snapshot, the
+    /// mean of each field, the normalized values, and the parameters that drifted
+    pub fn frame(&self) -> PolarsResult<DataFrame> {
+        let normalized: Vec<BTreeMap<String, f64>> = self.snapshots.iter().map(|s| s.normalized()).collect();
+        let fields: BTreeSet<&String> = self.snapshots.iter().flat_map(|s| s.values.keys()).collect();
+        let norms: BTreeSet<&String> = normalized.iter().flat_map(|n| n.keys()).collect();
+        let anomalies: Vec<String> = self
+            .anomalies()
+            .iter()
+            .map(|a| {
+                a.iter()
+                    .map(|a| format!("{} {:+.1}", a.parameter, a.drift))
+                    .collect::<Vec<_>>()
+                    .join("; ")
+            })
+            .collect();
+
+        let mut columns = vec![
+            Column::new(TAIL_COLUMN.into(), vec![self.tail_number.clone(); self.snapshots.len()]),
+            Column::new(
+                LOG_COLUMN.into(),
+                self.snapshots.iter().map(|s| s.log.clone()).collect::<Vec<_>>(),
+            ),
+            Column::new(
+                START_COLUMN.into(),
+                self.snapshots
+                    .iter()
+                    .map(|s| s.start.to_rfc3339_opts(SecondsFormat::Secs, true))
+                    .collect::<Vec<_>>(),
+            ),
+            Column::new(
+                SECONDS_COLUMN.into(),
+                self.snapshots.iter().map(|s| s.seconds).collect::<Vec<_>>(),
+            ),
+        ];
+        for name in fields {
+            let values: Vec<Option<f64>> = self.snapshots.iter().map(|s| s.values.get(name).copied()).collect();
+            columns.push(Column::new(name.into(), values));
+        }
+        for name in norms {
+            let values: Vec<Option<f64>> = normalized.iter().map(|n| n.get(name).copied()).collect();
+            columns.push(Column::new(name.into(), values));
+        }
+        columns.push(Column::new(ANOMALIES_COLUMN.into(), anomalies));
+        DataFrame::new(columns)
+    }
+
+    /// Write the table of the dataset
+    pub fn write<W: Write>(&self, format: TrendFormat, writer: &mut W) -> Result<(), FDRWriteError> {
+        let mut df = self.frame()?;
+        match format {
+            TrendFormat::Parquet => {
+                ParquetWriter::new(writer).finish(&mut df)?;
+            }
+            TrendFormat::Csv => CsvWriter::new(writer).include_header(true).finish(&mut df)?,
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{detection::read_avionics_log, AviationLogSourceOption};
+
+    #[test]
+    fn test_engine_trend() -> Result<(), Box<dyn std::error::Error>> {
+        let path = crate::resource_path("log_231104_084813_KPOU.csv");
+        let source = read_avionics_log(&AviationLogSourceOption::Garmin, &path)?;
+        let snapshots = cruise_snapshots(source.as_ref(), "log_231104_084813_KPOU.csv")?;
+        assert!(!snapshots.is_empty());
+
+        // snapshots are taken in level flight, above the pattern, with every cylinder
+        for snapshot in &snapshots {
+            assert!(snapshot.seconds >= MIN_STABLE_SECS && snapshot.seconds <= MAX_STABLE_SECS);
+            assert!(snapshot.values["AltB"] > 1500.0, "{:?}", snapshot);
+            for cylinder in 1..=4 {
+                assert!(snapshot.values.contains_key(&format!("E1 CHT{}", cylinder)));
+                assert!(snapshot.values.contains_key(&format!("E1 EGT{}", cylinder)));
+            }
+            let normalized = snapshot.normalized();
+            let rise = normalized["E1 CHT1 norm"];
+            assert!(rise > 250.0 && rise < 350.0, "{}", rise);
+            assert!(normalized["E1 FFlow norm"] > snapshot.values["E1 FFlow"]);
+        }
+
+        // a cylinder running hotter than its history is flagged, and only once there is enough history
+        let mut dataset = TrendDataset::new("N12345".to_string());
+        let base = snapshots[0].clone();
+        let history = (0..6).map(|i| {
+            let mut snapshot = base.clone();
+            snapshot.start += chrono::Duration::days(i);
+            *snapshot.values.get_mut("E1 CHT2").unwrap() += i as f64;
+            snapshot
+        });
+        dataset.merge(history.collect());
+        let mut hot = base.clone();
+        hot.start += chrono::Duration::days(10);
+        *hot.values.get_mut("E1 CHT3").unwrap() += 40.0;
+        dataset.merge(vec![hot]);
+        let anomalies = dataset.anomalies();
+        assert!(anomalies[..6].iter().all(|a| a.is_empty()), "{:?}", anomalies);
+        let flagged: Vec<&str> = anomalies[6].iter().map(|a| a.parameter.as_str()).collect();
+        assert!(flagged.contains(&"E1 CHT3"), "{:?}", flagged);
+        assert!(anomalies[6].iter().all(|a| a.parameter.contains("CHT")));
+
+        // the dataset is read back from the table it writes, and merging a snapshot again replaces it
+        for format in [TrendFormat::Parquet, TrendFormat::Csv] {
+            let dir = tempfile::tempdir()?;
+            let path = dir.path().join(format!("N12345.{}", format.extension()));
+            dataset.write(format, &mut File::create(&path)?)?;
+            let mut loaded = TrendDataset::load("N12345".to_string(), &path, format)?;
+            assert_eq!(loaded.snapshots.len(), dataset.snapshots.len());
+            assert_eq!(loaded.snapshots[6].values.len(), dataset.snapshots[6].values.len());
+            assert!((loaded.snapshots[6].values["E1 CHT3"] - dataset.snapshots[6].values["E1 CHT3"]).abs() < 1e-6);
+            loaded.merge(vec![dataset.snapshots[0].clone()]);
+            assert_eq!(loaded.snapshots.len(), dataset.snapshots.len());
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,322 @@
+//! Watch a directory, such as a shared folder that SD-card logs are synced to, and convert logs as they appear.
+//!
+//! Logs are converted once they have stopped changing for a settling period, so that files still being copied are not
+//! converted early. Every converted log is recorded in a state file, keyed on its canonical path, size and modification
+//! time, so a restarted watcher only converts logs that are new, have changed, or failed to convert. A log that has
+//! changed since it was converted, such as one that was partly synced, replaces its earlier FDR file and metadata.
+
+use crate::batch::{self, BatchConverter, BatchOutcome};
+use crate::detection::{detect_source, open_avionics_log};
+use crate::fdr::FDRConfiguration;
+use crate::garmin::LogCheckMode;
+use crate::AviationLogSourceOption;
+use notify::{RecursiveMode, Watcher};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashMap},
+    error::Error,
+    path::{Path, PathBuf},
+    sync::mpsc,
+    time::{Duration, Instant, SystemTime},
+};
+
+/// The name of the state file written to the output directory when no other path is given
+pub const DEFAULT_STATE_FILE: &str = ".xfdr-watch-state.json";
+
+/// A log that has been converted, and the version of it that was converted
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct ProcessedLog {
+    size: u64,
+    modified: u64,
+    output: PathBuf,
+}
+
+/// The logs that a watcher has converted, persisted between runs
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct WatchState {
+    processed: BTreeMap<PathBuf, ProcessedLog>,
+}
+
+/// Metadata about a converted log, written next to its FDR file
+#[derive(Debug, Serialize)]
+struct Sidecar {
+    input: PathBuf,
+    output: PathBuf,
+    source: String,
+    tail_number: String,
+    aircraft_model: String,
+    start_time: Option<String>,
+    departure_airport: Option<String>,
+    converted_at: String,
+}
+
+/// Size and modification time (seconds since the epoch) of a file, which identify the version of a log
+fn file_signature(path: &Path) -> std::io::Result<(u64, u64)> {
+    let metadata = std::fs::metadata(path)?;
+    let modified = metadata
+        .modified()?
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs());
+    Ok((metadata.len(), modified))
+}
+
+/// Converts the avionics logs in a directory as they finish being written
+pub struct WatchFolder {
+    dir: PathBuf,
+    config: FDRConfiguration,
+    source: Option<AviationLogSourceOption>,
+    logcheck: LogCheckMode,
+    converter: BatchConverter,
+    settle: Duration,
+    state_path: PathBuf,
+    state: WatchState,
+    // logs waiting to settle, with the size last seen and when it last changed
+    pending: HashMap<PathBuf, (u64, Instant)>,
+}
+
+impl WatchFolder {
+    /// Watch `dir`, converting logs into `output_dir` with the given configuration
+    pub fn new(dir: PathBuf, output_dir: PathBuf, config: FDRConfiguration) -> Result<Self, Box<dyn Error>> {
+        std::fs::create_dir_all(&output_dir)?;
+        let state_path = output_dir.join(DEFAULT_STATE_FILE);
+        Ok(Self {
+            dir,
+            converter: BatchConverter::new(config.clone(), output_dir),
+            config,
+            source: None,
+            logcheck: LogCheckMode::default(),
+            settle: Duration::from_secs(5),
+            state: Self::load_state(&state_path)?,
+            state_path,
+            pending: HashMap::new(),
+        })
+    }
+
+    /// How long a log must go unchanged before it is converted
+    pub fn settle(mut self, settle: Duration) -> Self {
+        self.settle = settle;
+        self
+    }
+
+    /// The source of the logs, otherwise the source of each log is detected
+    pub fn source(mut self, source: Option<AviationLogSourceOption>) -> Self {
+        self.source = source;
+        self.converter = self.converter.source(source);
+        self
+    }
+
+    /// The file that records converted logs
+    pub fn state_file(mut self, path: PathBuf) -> Result<Self, Box<dyn Error>> {
+        self.state = Self::load_state(&path)?;
+        self.state_path = path;
+        Ok(self)
+    }
+
+    /// The template used to name converted files, see [batch::output_file_name]
+    pub fn name_template(mut self, template: String) -> Self {
+        self.converter = self.converter.name_template(template);
+        self
+    }
+
+    /// If set, read each log in batches of this many records
+    pub fn batch_size(mut self, batch_size: Option<usize>) -> Self {
+        self.converter = self.converter.batch_size(batch_size);
+        self
+    }
+
+    /// What to do with records that fail their checksum
+    pub fn logcheck(mut self, logcheck: LogCheckMode) -> Self {
+        self.logcheck = logcheck;
+        self.converter = self.converter.logcheck(logcheck);
+        self
+    }
+
+    fn load_state(path: &Path) -> Result<WatchState, Box<dyn Error>> {
+        match std::fs::read_to_string(path) {
+            Ok(text) => Ok(serde_json::from_str(&text)?),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(WatchState::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn save_state(&self) -> Result<(), Box<dyn Error>> {
+        std::fs::write(&self.state_path, serde_json::to_string_pretty(&self.state)?)?;
+        Ok(())
+    }
+
+    /// Queue every log already in the directory that has not been processed
+    pub fn scan(&mut self) -> Result<(), Box<dyn Error>> {
+        for path in batch::collect_inputs(&self.dir)? {
+            self.notice(&path);
+        }
+        Ok(())
+    }
+
+    /// Queue a file that has been created or changed, unless this version of it was already converted
+    pub fn notice(&mut self, path: &Path) {
+        if !path.extension().is_some_and(|e| e.eq_ignore_ascii_case("csv")) {
+            return;
+        }
+        // key on the canonical path, as the scan and the events of the watcher may name a log differently
+        let Ok(path) = path.canonicalize() else {
+            return;
+        };
+        let Ok((size, modified)) = file_signature(&path) else {
+            return;
+        };
+        let processed = self
+            .state
+            .processed
+            .get(&path)
+            .is_some_and(|p| p.size == size && p.modified == modified);
+        if processed {
+            return;
+        }
+
+        let now = Instant::now();
+        let entry = self.pending.entry(path).or_insert((size, now));
+        if entry.0 != size {
+            *entry = (size, now);
+        }
+    }
+
+    /// Convert the queued logs that have stopped changing, returning their outcomes
+    pub fn process_ready(&mut self) -> Result<Vec<(PathBuf, BatchOutcome)>, Box<dyn Error>> {
+        let now = Instant::now();
+        let mut ready = Vec::new();
+        for (path, (size, changed)) in self.pending.iter_mut() {
+            match file_signature(path) {
+                Ok((current, _)) if current != *size => (*size, *changed) = (current, now),
+                Ok(_) if now.duration_since(*changed) >= self.settle => ready.push(path.clone()),
+                Ok(_) => {}
+                // the file was removed or renamed before it settled, drop it below
+                Err(_) => ready.push(path.clone()),
+            }
+        }
+
+        let mut outcomes = Vec::new();
+        for path in ready {
+            self.pending.remove(&path);
+            let Ok((size, modified)) = file_signature(&path) else {
+                continue;
+            };
+
+            // a log converted before has changed since, so its output is out of date
+            let outcome = if self.state.processed.contains_key(&path) {
+                self.converter.reconvert_file(&path)
+            } else {
+                self.converter.convert_file(&path)
+            };
+            match &outcome {
+                BatchOutcome::Converted(output) | BatchOutcome::Skipped(output) => {
+                    if matches!(outcome, BatchOutcome::Converted(_)) {
+                        self.write_sidecar(&path, output)?;
+                    }
+                    let output = output.clone();
+                    let log = ProcessedLog { size, modified, output };
+                    self.state.processed.insert(path.clone(), log);
+                }
+                // a log that failed is not recorded, so it is converted again once it changes or the watcher restarts
+                BatchOutcome::Failed(_) => {
+                    self.state.processed.remove(&path);
+                }
+            }
+            self.save_state()?;
+            outcomes.push((path, outcome));
+        }
+        Ok(outcomes)
+    }
+
+    fn write_sidecar(&self, input: &Path, output: &Path) -> Result<(), Box<dyn Error>> {
+        let source = self.source.map_or_else(|| detect_source(input), Ok)?;
+        let log = open_avionics_log(&source, input, Some(1), self.logcheck)?;
+        let sidecar = Sidecar {
+            input: input.to_path_buf(),
+            output: output.to_path_buf(),
+            source: format!("{:?}", source),
+            tail_number: self.config.tail_number(log.as_ref()),
+            aircraft_model: self.config.aircraft_model.clone(),
+            start_time: log.timestamp().map(|t| t.to_rfc3339()),
+            departure_airport: log.departure_airport(),
+            converted_at: chrono::Utc::now().to_rfc3339(),
+        };
+        std::fs::write(output.with_extension("json"), serde_json::to_string_pretty(&sidecar)?)?;
+        Ok(())
+    }
+
+    /// Watch the directory until an error occurs, calling `report` with the outcome of each conversion
+    pub fn run(&mut self, mut report: impl FnMut(&Path, &BatchOutcome)) -> Result<(), Box<dyn Error>> {
+        let (tx, rx) = mpsc::channel();
+        let mut watcher = notify::recommended_watcher(tx)?;
+        watcher.watch(&self.dir, RecursiveMode::Recursive)?;
+        self.scan()?;
+
+        loop {
+            match rx.recv_timeout(Duration::from_secs(1)) {
+                Ok(Ok(event)) => {
+                    for path in event.paths {
+                        self.notice(&path);
+                    }
+                }
+                Ok(Err(e)) => return Err(e.into()),
+                Err(mpsc::RecvTimeoutError::Timeout) => {}
+                Err(mpsc::RecvTimeoutError::Disconnected) => return Ok(()),
+            }
+
+            for (path, outcome) in self.process_ready()? {
+                report(&path, &outcome);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fdr::FDRConfigurationBuilder;
+
+    #[test]
+    fn test_watch_folder_records_processed_logs() -> Result<(), Box<dyn Error>> {
+        let input_dir = tempfile::tempdir()?;
+        let output_dir = tempfile::tempdir()?;
+        let log = input_dir.path().join("log_231104_084813_KPOU.csv");
+        std::fs::copy(crate::resource_path("log_231104_084813_KPOU.csv"), &log)?;
+        std::fs::write(input_dir.path().join("notes.csv"), "not,a,log\n")?;
+
+        let config = FDRConfigurationBuilder::default().build();
+        let mut watch =
+            WatchFolder::new(input_dir.path().into(), output_dir.path().into(), config.clone())?.settle(Duration::ZERO);
+        watch.scan()?;
+        let mut outcomes = watch.process_ready()?;
+        outcomes.sort_by(|a, b| a.0.cmp(&b.0));
+        assert_eq!(outcomes.len(), 2);
+        assert!(matches!(outcomes[0].1, BatchOutcome::Converted(_)));
+        assert!(matches!(outcomes[1].1, BatchOutcome::Failed(_)));
+        assert!(output_dir.path().join("2023-11-04_124813_N12345_KPOU.json").exists());
+
+        // a restarted watcher does not convert the log again, but retries the one that failed
+        let mut watch = WatchFolder::new(input_dir.path().into(), output_dir.path().into(), config)?
+            .settle(Duration::ZERO)
+            .source(Some(AviationLogSourceOption::Garmin));
+        watch.scan()?;
+        let outcomes = watch.process_ready()?;
+        assert_eq!(outcomes.len(), 1);
+        assert!(outcomes[0].0.ends_with("notes.csv"));
+
+        // a log that changed after it was converted is converted again, rather than skipped for its earlier output
+        let sidecar = output_dir.path().join("2023-11-04_124813_N12345_KPOU.json");
+        std::fs::remove_file(&sidecar)?;
+        let modified = std::fs::metadata(&log)?.modified()? + Duration::from_secs(60);
+        std::fs::File::options()
+            .write(true)
+            .open(&log)?
+            .set_modified(modified)?;
+        watch.notice(&log);
+        let outcomes = watch.process_ready()?;
+        assert_eq!(outcomes.len(), 1);
+        assert!(matches!(outcomes[0].1, BatchOutcome::Converted(_)));
+        assert!(sidecar.exists());
+        assert_eq!(watch.state.processed.len(), 1);
+        Ok(())
+    }
+}
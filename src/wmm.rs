@@ -0,0 +1,201 @@
+//! World Magnetic Model (WMM) calculation of magnetic declination.
+//!
+//! The model coefficients are the published NOAA/NGA coefficient (COF) files, embedded from the "resources" directory.
+//! A model is valid for the five years following its epoch; the newest model whose epoch is not after the requested
+//! date is used.
+
+use chrono::{DateTime, Datelike, Timelike, Utc};
+
+/// WMM reference radius in km
+const REFERENCE_RADIUS: f64 = 6371.2;
+/// WGS-84 semi-major axis in km
+const WGS84_A: f64 = 6378.137;
+/// WGS-84 flattening
+const WGS84_F: f64 = 1.0 / 298.257223563;
+
+const WMM_COEFFICIENTS: [&str; 2] = [
+    include_str!("../resources/WMM2020.COF"),
+    include_str!("../resources/WMM2025.COF"),
+];
+
+/// A set of spherical harmonic coefficients for a WMM epoch
+struct MagneticModel {
+    epoch: f64,
+    max_degree: usize,
+    // coefficients indexed by [n][m]
+    g: Vec<Vec<f64>>,
+    h: Vec<Vec<f64>>,
+    g_dot: Vec<Vec<f64>>,
+    h_dot: Vec<Vec<f64>>,
+}
+
+impl MagneticModel {
+    /// Parse a model from the text of a WMM COF file
+    fn from_cof(text: &str) -> Self {
+        let mut lines = text.lines();
+        let epoch = lines
+            .next()
+            .and_then(|l| l.split_whitespace().next())
+            .and_then(|e| e.parse::<f64>().ok())
+            .expect("WMM coefficient file has no epoch");
+
+        let rows: Vec<Vec<f64>> = lines
+            .take_while(|l| !l.starts_with("9999"))
+            .map(|l| l.split_whitespace().filter_map(|v| v.parse::<f64>().ok()).collect())
+            .filter(|r: &Vec<f64>| r.len() == 6)
+            .collect();
+
+        let max_degree = rows.iter().map(|r| r[0] as usize).max().unwrap_or(0);
+        let zeros = || vec![vec![0.0; max_degree + 1]; max_degree + 1];
+        let (mut g, mut h, mut g_dot, mut h_dot) = (zeros(), zeros(), zeros(), zeros());
+        for r in rows {
+            let (n, m) = (r[0] as usize, r[1] as usize);
+            g[n][m] = r[2];
+            h[n][m] = r[3];
+            g_dot[n][m] = r[4];
+            h_dot[n][m] = r[5];
+        }
+
+        Self {
+            epoch,
+            max_degree,
+            g,
+            h,
+            g_dot,
+            h_dot,
+        }
+    }
+
+    /// Magnetic declination in degrees (east positive) at a geodetic position and decimal year
+    fn declination(&self, latitude: f64, longitude: f64, altitude_km: f64, year: f64) -> f64 {
+        let dt = year - self.epoch;
+        let lat = latitude.to_radians();
+        let lon = longitude.to_radians();
+
+        // geodetic to geocentric spherical coordinates
+        let e2 = WGS84_F * (2.0 - WGS84_F);
+        let rc = WGS84_A / (1.0 - e2 * lat.sin().powi(2)).sqrt();
+        let p = (rc + altitude_km) * lat.cos();
+        let z = (rc * (1.0 - e2) + altitude_km) * lat.sin();
+        let r = (p * p + z * z).sqrt();
+        let lat_gc = (z / r).asin();
+
+        // cos and sin of the geocentric colatitude, nudged away from the poles to avoid a division by zero
+        let x = lat_gc.sin();
+        let s = lat_gc.cos().max(1e-10);
+
+        let size = self.max_degree + 1;
+        let mut p_nm = vec![vec![0.0; size]; size];
+        let mut dp_nm = vec![vec![0.0; size]; size];
+        p_nm[0][0] = 1.0;
+
+        let (mut b_r, mut b_theta, mut b_phi) = (0.0, 0.0, 0.0);
+        let mut schmidt = vec![vec![0.0; size]; size];
+        schmidt[0][0] = 1.0;
+
+        for n in 1..size {
+            schmidt[n][0] = schmidt[n - 1][0] * (2 * n - 1) as f64 / n as f64;
+            let ratio = (REFERENCE_RADIUS / r).powi(n as i32 + 2);
+
+            for m in 0..=n {
+                // Gauss-normalized associated Legendre functions and their derivatives w.r.t colatitude
+                if n == m {
+                    p_nm[n][m] = s * p_nm[n - 1][m - 1];
+                    dp_nm[n][m] = s * dp_nm[n - 1][m - 1] + x * p_nm[n - 1][m - 1];
+                } else if n == 1 {
+                    p_nm[n][m] = x * p_nm[n - 1][m];
+                    dp_nm[n][m] = x * dp_nm[n - 1][m] - s * p_nm[n - 1][m];
+                } else {
+                    let k = ((n - 1) * (n - 1) - m * m) as f64 / ((2 * n - 1) * (2 * n - 3)) as f64;
+                    let (p2, dp2) = if m <= n - 2 {
+                        (p_nm[n - 2][m], dp_nm[n - 2][m])
+                    } else {
+                        (0.0, 0.0)
+                    };
+                    p_nm[n][m] = x * p_nm[n - 1][m] - k * p2;
+                    dp_nm[n][m] = x * dp_nm[n - 1][m] - s * p_nm[n - 1][m] - k * dp2;
+                }
+
+                if m > 0 {
+                    let delta = if m == 1 { 2.0 } else { 1.0 };
+                    schmidt[n][m] = schmidt[n][m - 1] * ((n - m + 1) as f64 * delta / (n + m) as f64).sqrt();
+                }
+
+                let g = (self.g[n][m] + dt * self.g_dot[n][m]) * schmidt[n][m];
+                let h = (self.h[n][m] + dt * self.h_dot[n][m]) * schmidt[n][m];
+                let (sin_ml, cos_ml) = (m as f64 * lon).sin_cos();
+                let gh = g * cos_ml + h * sin_ml;
+
+                b_r += ratio * (n + 1) as f64 * gh * p_nm[n][m];
+                b_theta -= ratio * gh * dp_nm[n][m];
+                b_phi -= ratio * m as f64 * (-g * sin_ml + h * cos_ml) * p_nm[n][m] / s;
+            }
+        }
+
+        // rotate the geocentric field components into the geodetic frame
+        let (north_gc, east, down_gc) = (-b_theta, b_phi, -b_r);
+        let psi = lat_gc - lat;
+        let north = north_gc * psi.cos() - down_gc * psi.sin();
+
+        east.atan2(north).to_degrees()
+    }
+}
+
+/// Convert a timestamp to a decimal year, as used by the model
+fn decimal_year(timestamp: &DateTime<Utc>) -> f64 {
+    let days_in_year = if chrono::NaiveDate::from_ymd_opt(timestamp.year(), 2, 29).is_some() {
+        366.0
+    } else {
+        365.0
+    };
+    let day = timestamp.ordinal0() as f64 + timestamp.num_seconds_from_midnight() as f64 / 86400.0;
+    timestamp.year() as f64 + day / days_in_year
+}
+
+/// Calculate the magnetic declination (variation) in degrees, east positive, using the World Magnetic Model
+///
+/// Add the declination to a magnetic heading to obtain a true heading.
+pub fn declination(latitude: f64, longitude: f64, altitude_ft: f64, timestamp: &DateTime<Utc>) -> f64 {
+    Declination::default().at(latitude, longitude, altitude_ft, timestamp)
+}
+
+/// Calculator that parses the embedded model coefficients once and reuses them for many positions
+pub struct Declination {
+    models: Vec<MagneticModel>,
+}
+
+impl Default for Declination {
+    fn default() -> Self {
+        Self {
+            models: WMM_COEFFICIENTS.iter().map(|c| MagneticModel::from_cof(c)).collect(),
+        }
+    }
+}
+
+impl Declination {
+    /// Magnetic declination in degrees, east positive. See [declination]
+    pub fn at(&self, latitude: f64, longitude: f64, altitude_ft: f64, timestamp: &DateTime<Utc>) -> f64 {
+        let year = decimal_year(timestamp);
+        let model = self
+            .models
+            .iter()
+            .rev()
+            .find(|m| m.epoch <= year)
+            .unwrap_or(&self.models[0]);
+        model.declination(latitude, longitude, altitude_ft * 0.0003048, year)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn test_declination_matches_logged_magvar() {
+        // the sample log was recorded at KPOU, where the avionics reported a variation of -12.8 degrees
+        let ts = Utc.with_ymd_and_hms(2023, 11, 4, 12, 48, 13).unwrap();
+        let d = declination(41.6266, -73.8842, 165.0, &ts);
+        assert!((d - -12.8).abs() < 0.5, "declination was {}", d);
+    }
+}
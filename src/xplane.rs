@@ -0,0 +1,176 @@
+//! The X-Plane UDP API, used to drive a running simulator without loading a FDR file.
+//!
+//! X-Plane listens on port 49000 for packets that start with a 4 character command and a null byte. Only the packets
+//! used by xfdr are implemented: `VEHX` to position the aircraft, `DREF` to set a dataref and `RREF` to subscribe to
+//! the values of datarefs, which X-Plane sends back to the subscribing address.
+
+use crate::track::{TrackPoint, FEET_PER_METER};
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+
+/// The port X-Plane listens on for UDP commands
+pub const XPLANE_PORT: u16 = 49000;
+
+/// The length of the null padded dataref path of a DREF packet
+const DREF_PATH_LENGTH: usize = 500;
+
+/// A packet that positions an aircraft, disabling its flight model
+///
+/// Positions are WGS-84 latitude and longitude, elevation in meters above sea level, and true heading, pitch and roll
+/// in degrees.
+pub fn vehx_packet(aircraft: i32, point: &TrackPoint) -> Vec<u8> {
+    let mut packet = Vec::with_capacity(45);
+    packet.extend_from_slice(b"VEHX\0");
+    packet.extend_from_slice(&aircraft.to_le_bytes());
+    packet.extend_from_slice(&point.latitude.to_le_bytes());
+    packet.extend_from_slice(&point.longitude.to_le_bytes());
+    packet.extend_from_slice(&(point.altitude / FEET_PER_METER).to_le_bytes());
+    packet.extend_from_slice(&(point.heading as f32).to_le_bytes());
+    packet.extend_from_slice(&(point.pitch as f32).to_le_bytes());
+    packet.extend_from_slice(&(point.roll as f32).to_le_bytes());
+    packet
+}
+
+/// A packet that sets the value of a dataref. Paths longer than the packet allows are truncated
+pub fn dref_packet(path: &str, value: f32) -> Vec<u8> {
+    let mut packet = Vec::with_capacity(9 + DREF_PATH_LENGTH);
+    packet.extend_from_slice(b"DREF\0");
+    packet.extend_from_slice(&value.to_le_bytes());
+    let path = path.as_bytes();
+    let len = path.len().min(DREF_PATH_LENGTH - 1);
+    packet.extend_from_slice(&path[..len]);
+    packet.resize(9 + DREF_PATH_LENGTH, 0);
+    packet
+}
+
+/// The length of the null padded dataref path of a RREF request
+const RREF_PATH_LENGTH: usize = 400;
+
+/// A packet that asks X-Plane to send the value of a dataref `frequency` times a second, tagged with `index`. A
+/// frequency of 0 stops sending it.
+pub fn rref_request_packet(frequency: i32, index: i32, path: &str) -> Vec<u8> {
+    let mut packet = Vec::with_capacity(13 + RREF_PATH_LENGTH);
+    packet.extend_from_slice(b"RREF\0");
+    packet.extend_from_slice(&frequency.to_le_bytes());
+    packet.extend_from_slice(&index.to_le_bytes());
+    let path = path.as_bytes();
+    let len = path.len().min(RREF_PATH_LENGTH - 1);
+    packet.extend_from_slice(&path[..len]);
+    packet.resize(13 + RREF_PATH_LENGTH, 0);
+    packet
+}
+
+/// The index and value pairs of a RREF packet sent by X-Plane, or None if it is not a RREF packet
+pub fn parse_rref_packet(packet: &[u8]) -> Option<Vec<(i32, f32)>> {
+    if packet.len() < 5 || &packet[..4] != b"RREF" {
+        return None;
+    }
+    Some(
+        packet[5..]
+            .chunks_exact(8)
+            .map(|c| {
+                (
+                    i32::from_le_bytes([c[0], c[1], c[2], c[3]]),
+                    f32::from_le_bytes([c[4], c[5], c[6], c[7]]),
+                )
+            })
+            .collect(),
+    )
+}
+
+/// A connection to a running X-Plane
+pub struct XPlaneConnection {
+    socket: UdpSocket,
+    target: SocketAddr,
+}
+
+impl XPlaneConnection {
+    /// Connect to X-Plane at an address such as "127.0.0.1:49000"
+    pub fn new(target: impl ToSocketAddrs) -> std::io::Result<Self> {
+        let target = target
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::InvalidInput, "No address to send to"))?;
+        let bind: SocketAddr = if target.is_ipv4() {
+            ([0, 0, 0, 0], 0).into()
+        } else {
+            ([0u16; 8], 0).into()
+        };
+        Ok(Self {
+            socket: UdpSocket::bind(bind)?,
+            target,
+        })
+    }
+
+    /// Position the user's aircraft
+    pub fn send_position(&self, point: &TrackPoint) -> std::io::Result<()> {
+        self.socket.send_to(&vehx_packet(0, point), self.target)?;
+        Ok(())
+    }
+
+    /// Set the value of a dataref
+    pub fn send_dref(&self, path: &str, value: f32) -> std::io::Result<()> {
+        self.socket.send_to(&dref_packet(path, value), self.target)?;
+        Ok(())
+    }
+
+    /// Ask X-Plane to send the value of a dataref `frequency` times a second, or stop sending it when 0
+    pub fn subscribe(&self, frequency: i32, index: i32, path: &str) -> std::io::Result<()> {
+        self.socket
+            .send_to(&rref_request_packet(frequency, index, path), self.target)?;
+        Ok(())
+    }
+
+    /// Wait up to `timeout` for the dataref values that X-Plane sends, returning None if none arrived
+    pub fn receive_values(&self, timeout: std::time::Duration) -> std::io::Result<Option<Vec<(i32, f32)>>> {
+        self.socket
+            .set_read_timeout(Some(timeout.max(std::time::Duration::from_millis(1))))?;
+        let mut buffer = [0u8; 2048];
+        match self.socket.recv(&mut buffer) {
+            Ok(len) => Ok(parse_rref_packet(&buffer[..len])),
+            Err(e) if matches!(e.kind(), std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// The local address that X-Plane sends subscribed values to
+    pub fn local_addr(&self) -> std::io::Result<SocketAddr> {
+        self.socket.local_addr()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{TimeZone, Utc};
+
+    #[test]
+    fn test_packets_sent_to_listener() -> std::io::Result<()> {
+        let listener = UdpSocket::bind("127.0.0.1:0")?;
+        let connection = XPlaneConnection::new(listener.local_addr()?)?;
+        let point = TrackPoint {
+            time: Utc.with_ymd_and_hms(2023, 11, 4, 12, 48, 13).unwrap(),
+            longitude: -73.88,
+            latitude: 41.62,
+            altitude: 1000.0,
+            heading: 90.0,
+            pitch: 2.5,
+            roll: -10.0,
+        };
+        connection.send_position(&point)?;
+        connection.send_dref("sim/cockpit2/gauges/indicators/airspeed_kts_pilot", 120.5)?;
+
+        let mut buffer = [0u8; 1024];
+        let len = listener.recv(&mut buffer)?;
+        assert_eq!(len, 45);
+        assert_eq!(&buffer[..5], b"VEHX\0");
+        assert_eq!(f64::from_le_bytes(buffer[9..17].try_into().unwrap()), 41.62);
+        assert_eq!(f32::from_le_bytes(buffer[41..45].try_into().unwrap()), -10.0);
+
+        let len = listener.recv(&mut buffer)?;
+        assert_eq!(len, 509);
+        assert_eq!(&buffer[..5], b"DREF\0");
+        assert_eq!(f32::from_le_bytes(buffer[5..9].try_into().unwrap()), 120.5);
+        assert!(buffer[9..].starts_with(b"sim/cockpit2/gauges/indicators/airspeed_kts_pilot\0"));
+        Ok(())
+    }
+}